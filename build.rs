@@ -0,0 +1,56 @@
+use std::env;
+
+/// The features selecting an interrupt controller other than the NVIC
+const BACKENDS: &[&str] = &["riscv", "msp430", "avr", "xtensa", "gic", "std", "wasm"];
+
+/// The target architectures the features tied to one can be built for
+const ARCHES: &[(&str, &[&str])] = &[
+    ("riscv", &["riscv32", "riscv64"]),
+    ("msp430", &["msp430"]),
+    ("avr", &["avr"]),
+    ("xtensa", &["xtensa"]),
+    ("gic", &["arm", "aarch64"]),
+    ("qemu", &["arm"]),
+];
+
+fn main() {
+    let target = env::var("TARGET").unwrap();
+
+    println!("cargo:rustc-check-cfg=cfg(armv6m)");
+    println!("cargo:rustc-check-cfg=cfg(cortex_m_backend)");
+
+    // ARMv6-M lacks IABR, BASEPRI, and byte access to the priority registers
+    if target.starts_with("thumbv6m-") {
+        println!("cargo:rustc-cfg=armv6m");
+    }
+
+    let enabled: Vec<&str> = BACKENDS
+        .iter()
+        .copied()
+        .filter(|backend| is_enabled(backend))
+        .collect();
+
+    match enabled.len() {
+        0 => println!("cargo:rustc-cfg=cortex_m_backend"),
+        1 => {}
+        _ => panic!(
+            "only one of the {:?} features may be enabled, but {:?} are",
+            BACKENDS, enabled
+        ),
+    }
+
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    for (feature, arches) in ARCHES {
+        if is_enabled(feature) && !arches.contains(&arch.as_str()) {
+            panic!(
+                "the {} feature requires one of the {:?} target architectures, but the target is {}",
+                feature, arches, arch
+            );
+        }
+    }
+}
+
+/// Whether the feature is enabled
+fn is_enabled(feature: &str) -> bool {
+    env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_some()
+}
@@ -0,0 +1,161 @@
+//! The attribute form of the `shared!` macro of the `shared` crate.
+//!
+//! This is re-exported by `shared` as `shared::attr::shared` with the
+//! `macros` feature, and should be used from there.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Attribute, Expr, Ident, Token, Type, Visibility};
+
+/// Declare a `shared` item from a `static`, guarded by the given interrupt.
+///
+/// ```rust,ignore
+/// use shared::attr::shared;
+///
+/// #[shared(interrupt = Interrupt::RADIO)]
+/// pub static RADIO_PKTS: usize = 0;
+///
+/// #[shared(interrupt = [Interrupt::RADIO, Interrupt::TIMER0], priority = 2)]
+/// static TIMESTAMP: u32 = 0;
+///
+/// // Items filled in at runtime with `set_initial` have no initial value
+/// #[shared(interrupt = Interrupt::UARTE0_UARTE0)]
+/// static UART: shared::Peripheral<nrf52840_pac::UARTE0>;
+/// ```
+///
+/// This generates the same token type and accessors as the equivalent
+/// `shared!` item, with the visibility of the `static`. Its doc comments
+/// are applied to the token, and a `link_section` attribute to the static
+/// holding the data. The type and the
+/// initial value follow the same rules as in `shared!`, and the
+/// `priority`, `spinlock`, `controller` and `reader` options may be given
+/// after the interrupt.
+#[proc_macro_attribute]
+pub fn shared(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut options = Options::default();
+    let parser = syn::meta::parser(|meta| {
+        let slot = if meta.path.is_ident("interrupt") {
+            &mut options.interrupt
+        } else if meta.path.is_ident("priority") {
+            &mut options.priority
+        } else if meta.path.is_ident("spinlock") {
+            &mut options.spinlock
+        } else if meta.path.is_ident("controller") {
+            &mut options.controller
+        } else if meta.path.is_ident("reader") {
+            &mut options.reader
+        } else {
+            return Err(meta
+                .error("expected `interrupt`, `priority`, `spinlock`, `controller` or `reader`"));
+        };
+
+        if slot.is_some() {
+            return Err(meta.error("this option was already given"));
+        }
+        *slot = Some(meta.value()?.parse()?);
+        Ok(())
+    });
+    parse_macro_input!(args with parser);
+    let item = parse_macro_input!(item as SharedStatic);
+
+    match expand(options, item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// The arguments of the attribute
+#[derive(Default)]
+struct Options {
+    interrupt: Option<Expr>,
+    priority: Option<Expr>,
+    spinlock: Option<Expr>,
+    controller: Option<Expr>,
+    reader: Option<Expr>,
+}
+
+/// A `static` declaration, whose initial value may be left out
+struct SharedStatic {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    name: Ident,
+    ty: Type,
+    init: Option<Expr>,
+}
+
+impl Parse for SharedStatic {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        input.parse::<Token![static]>()?;
+        if let Some(mutability) = input.parse::<Option<Token![mut]>>()? {
+            return Err(syn::Error::new_spanned(
+                mutability,
+                "shared items can not be `static mut`, they are modified through their token",
+            ));
+        }
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        let init = if input.parse::<Option<Token![=]>>()?.is_some() {
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        input.parse::<Token![;]>()?;
+
+        Ok(SharedStatic {
+            attrs,
+            vis,
+            name,
+            ty,
+            init,
+        })
+    }
+}
+
+fn expand(options: Options, item: SharedStatic) -> syn::Result<TokenStream2> {
+    let SharedStatic {
+        attrs,
+        vis,
+        name,
+        ty,
+        init,
+    } = item;
+
+    let interrupt = options.interrupt.ok_or_else(|| {
+        syn::Error::new(
+            name.span(),
+            "missing the interrupt, as in `#[shared(interrupt = Interrupt::RADIO)]`",
+        )
+    })?;
+
+    // The options follow the interrupt in the order `shared!` expects
+    let init = init.map(|init| quote!(= #init));
+    let mut tail = TokenStream2::new();
+    if let Some(priority) = options.priority {
+        tail.extend(quote!(, priority = #priority));
+    }
+    if let Some(spinlock) = options.spinlock {
+        tail.extend(quote!(, spinlock = #spinlock));
+    }
+    if let Some(controller) = options.controller {
+        tail.extend(quote!(, controller = #controller));
+    }
+    if let Some(reader) = options.reader {
+        tail.extend(quote!(, reader = #reader));
+    }
+
+    // The visibility and the attributes of the `static`, such as doc
+    // comments and `link_section`, are handled by `shared!`
+    Ok(quote! {
+        ::shared::shared! {
+            #(#attrs)*
+            #vis #name: #ty #init => #interrupt #tail
+        }
+    })
+}
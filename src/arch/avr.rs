@@ -0,0 +1,62 @@
+//! The AVR implementation of the interrupt primitives used by
+//! `nvic::IrqSet`, based on the interrupt enable bits of each peripheral
+//!
+//! Like the MSP430, AVR has no central interrupt controller, so this uses
+//! the interrupt sources registered with `shared::avr::set_sources`.
+
+use crate::CriticalSection;
+
+#[path = "running.rs"]
+mod running;
+#[path = "sources.rs"]
+mod sources;
+
+pub use self::running::*;
+pub use self::sources::*;
+
+/// The global interrupt enable bit in SREG
+const I: u8 = 1 << 7;
+
+/// AVR interrupt priorities are fixed by their vector, so this has no
+/// effect
+pub unsafe fn set_priority(_irqn: i16, _prio: u8) {}
+
+/// Mask all interrupts with `cli`, returning whether they were enabled
+/// beforehand
+pub fn mask_all() -> bool {
+    let sreg: u8;
+    // NOTE(unsafe) clearing the I bit has no other side effects
+    unsafe { core::arch::asm!("in {0}, 0x3F", "cli", out(reg) sreg) };
+    sreg & I != 0
+}
+
+/// Unmask all interrupts with `sei`
+pub unsafe fn unmask_all() {
+    core::arch::asm!("sei");
+}
+
+/// Whether interrupts were enabled before `mask_all`
+pub type Restore = bool;
+
+/// Undo `mask_all`
+pub unsafe fn restore_all(enabled: Restore) {
+    if enabled {
+        unmask_all()
+    }
+}
+
+/// Execute the closure with all interrupts masked
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    let enabled = mask_all();
+
+    // NOTE(unsafe) interrupts are masked until the closure returns
+    let r = f(unsafe { &CriticalSection::new() });
+
+    if enabled {
+        unsafe { unmask_all() }
+    }
+    r
+}
@@ -0,0 +1,378 @@
+//! The Cortex-M implementation of the interrupt primitives used by
+//! `nvic::IrqSet`, based on the NVIC and SCB
+//!
+//! Interrupts are identified by their CMSIS IRQ number, so device specific
+//! interrupts are positive, and core exceptions are negative.
+//!
+//! With the `trustzone` feature, secure code accesses interrupts targeting
+//! the non-secure state, according to `NVIC_ITNS`, through the non-secure
+//! aliases of the NVIC registers, as the secure registers have no effect on
+//! them.
+
+use core::ptr;
+
+#[cfg(feature = "basepri")]
+use cortex_m::peripheral::scb::SystemHandler;
+use cortex_m::peripheral::scb::VectActive;
+use cortex_m::peripheral::{NVIC, SCB, SYST};
+
+pub use cortex_m::interrupt::free;
+
+use crate::nvic::Active;
+use crate::CoreException;
+
+/// The interrupt controller, which must be held to enable interrupts with
+/// `set_initial_nvic`
+pub type Controller = NVIC;
+
+/// The address of the first NVIC interrupt set-enable register
+const ISER: usize = 0xE000_E100;
+
+/// The address of the first NVIC interrupt clear-enable register
+const ICER: usize = 0xE000_E180;
+
+/// The address of the first NVIC interrupt set-pending register
+const ISPR: usize = 0xE000_E200;
+
+/// The address of the first NVIC interrupt clear-pending register
+const ICPR: usize = 0xE000_E280;
+
+/// The address of the first NVIC interrupt active bit register
+#[cfg(not(armv6m))]
+const IABR: usize = 0xE000_E300;
+
+/// The address of the first NVIC interrupt target non-secure register
+#[cfg(feature = "trustzone")]
+const ITNS: usize = 0xE000_E380;
+
+/// The offset from a register of the system control space to its
+/// non-secure alias
+#[cfg(feature = "trustzone")]
+const NS_ALIAS: usize = 0x2_0000;
+
+/// The address of the first NVIC priority register
+const IPR: usize = 0xE000_E400;
+
+/// The address of the priority register of exception 4, as if SHPR1
+/// existed on ARMv6-M
+const SHPR: usize = 0xE000_ED18;
+
+/// The offset from the NVIC registers to the registers used for the device
+/// specific interrupt: the non-secure aliases if it targets the non-secure
+/// state. `NVIC_ITNS` reads as zero from the non-secure state, which uses its
+/// own registers directly.
+#[cfg(feature = "trustzone")]
+fn bank(irqn: i16) -> usize {
+    let itns = (ITNS + 4 * (irqn as usize / 32)) as *const u32;
+
+    // NOTE(unsafe) atomic read with no side effects
+    if unsafe { ptr::read_volatile(itns) } & bit(irqn) != 0 {
+        NS_ALIAS
+    } else {
+        0
+    }
+}
+
+/// The offset from the NVIC registers to the registers used for the device
+/// specific interrupt
+#[cfg(not(feature = "trustzone"))]
+fn bank(_irqn: i16) -> usize {
+    0
+}
+
+/// The address of the NVIC register holding the bit for the device specific
+/// interrupt, in the bit array starting at `base`
+fn bit_register(base: usize, irqn: i16) -> *mut u32 {
+    (bank(irqn) + base + 4 * (irqn as usize / 32)) as *mut u32
+}
+
+/// The bit for the device specific interrupt in its register
+fn bit(irqn: i16) -> u32 {
+    1 << (irqn as usize % 32)
+}
+
+/// Is the bit for the device specific interrupt set, in the bit array
+/// starting at `base`?
+fn read_bit(base: usize, irqn: i16) -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    unsafe { ptr::read_volatile(bit_register(base, irqn)) & bit(irqn) != 0 }
+}
+
+/// Set the bit for the device specific interrupt in the write-one-to-set or
+/// write-one-to-clear bit array starting at `base`
+fn write_bit(base: usize, irqn: i16) {
+    // NOTE(unsafe) writes of zero bits have no effect, so this doesn't race
+    unsafe { ptr::write_volatile(bit_register(base, irqn), bit(irqn)) }
+}
+
+impl CoreException {
+    /// The CMSIS IRQ number of the exception
+    pub(crate) fn irqn(self) -> i16 {
+        match self {
+            CoreException::SVCall => -5,
+            CoreException::PendSV => -2,
+            CoreException::SysTick => -1,
+        }
+    }
+
+    /// The exception with this CMSIS IRQ number
+    fn from_irqn(irqn: i16) -> Self {
+        match irqn {
+            -5 => CoreException::SVCall,
+            -2 => CoreException::PendSV,
+            -1 => CoreException::SysTick,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Could the exception run? `SVCall` is only taken synchronously,
+    /// `PendSV` runs once pended, and `SysTick` runs if the SysTick
+    /// interrupt is enabled.
+    fn is_enabled(self) -> bool {
+        // NOTE(unsafe) atomic reads with no side effects
+        match self {
+            CoreException::SVCall => false,
+            CoreException::PendSV => unsafe { (*SCB::PTR).icsr.read() & (1 << 28) != 0 },
+            CoreException::SysTick => unsafe { (*SYST::PTR).csr.read() & (1 << 1) != 0 },
+        }
+    }
+
+    /// Is the exception running, or preempted? This reads SHCSR, which
+    /// is not available on ARMv6-M.
+    #[cfg(not(armv6m))]
+    fn is_active(self) -> bool {
+        let bit = match self {
+            CoreException::SVCall => 7,
+            CoreException::PendSV => 10,
+            CoreException::SysTick => 11,
+        };
+
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { (*SCB::PTR).shcsr.read() & (1 << bit) != 0 }
+    }
+
+    /// Set the priority of the exception, in SHPR2 or SHPR3
+    unsafe fn set_priority(self, prio: u8) {
+        let index = match self {
+            CoreException::SVCall => 11,
+            CoreException::PendSV => 14,
+            CoreException::SysTick => 15,
+        };
+        write_priority(SHPR + index - 4, prio);
+    }
+
+    /// Is the exception pending?
+    fn is_pending(self) -> bool {
+        // NOTE(unsafe) atomic reads with no side effects
+        match self {
+            CoreException::SVCall => unsafe { (*SCB::PTR).shcsr.read() & (1 << 15) != 0 },
+            CoreException::PendSV => unsafe { (*SCB::PTR).icsr.read() & (1 << 28) != 0 },
+            CoreException::SysTick => unsafe { (*SCB::PTR).icsr.read() & (1 << 26) != 0 },
+        }
+    }
+
+    /// Set, or clear, the pending state of the exception
+    fn set_pending(self, pending: bool) {
+        match self {
+            // SHCSR is not write-only, so this is a read-modify-write
+            CoreException::SVCall => unsafe {
+                (*SCB::PTR).shcsr.modify(|v| {
+                    if pending {
+                        v | (1 << 15)
+                    } else {
+                        v & !(1 << 15)
+                    }
+                })
+            },
+            // NOTE(unsafe) writes of zero bits to ICSR have no effect
+            CoreException::PendSV => unsafe {
+                (*SCB::PTR)
+                    .icsr
+                    .write(if pending { 1 << 28 } else { 1 << 27 })
+            },
+            CoreException::SysTick => unsafe {
+                (*SCB::PTR)
+                    .icsr
+                    .write(if pending { 1 << 26 } else { 1 << 25 })
+            },
+        }
+    }
+
+    /// The priority of the exception
+    #[cfg(feature = "basepri")]
+    fn priority(self) -> u8 {
+        SCB::get_priority(match self {
+            CoreException::SVCall => SystemHandler::SVCall,
+            CoreException::PendSV => SystemHandler::PendSV,
+            CoreException::SysTick => SystemHandler::SysTick,
+        })
+    }
+}
+
+/// Read the currently active vector from the SCB
+pub fn active() -> Active {
+    match SCB::vect_active() {
+        VectActive::ThreadMode => Active::Thread,
+        VectActive::Interrupt { irqn } => Active::Interrupt(i16::from(irqn)),
+        VectActive::Exception(ex) => Active::Interrupt(i16::from(ex.irqn())),
+    }
+}
+
+/// Is the interrupt enabled?
+pub fn is_enabled(irqn: i16) -> bool {
+    if irqn < 0 {
+        CoreException::from_irqn(irqn).is_enabled()
+    } else {
+        read_bit(ISER, irqn)
+    }
+}
+
+/// Is the interrupt active (running, or preempted)?
+#[cfg(not(armv6m))]
+pub fn is_active(irqn: i16) -> bool {
+    if irqn < 0 {
+        CoreException::from_irqn(irqn).is_active()
+    } else {
+        read_bit(IABR, irqn)
+    }
+}
+
+/// Is the interrupt running? ARMv6-M has neither IABR nor the active bits
+/// of SHCSR, so preempted interrupts are not detected.
+#[cfg(armv6m)]
+pub fn is_active(irqn: i16) -> bool {
+    active() == Active::Interrupt(irqn)
+}
+
+/// Is the interrupt pending?
+pub fn is_pending(irqn: i16) -> bool {
+    if irqn < 0 {
+        CoreException::from_irqn(irqn).is_pending()
+    } else {
+        read_bit(ISPR, irqn)
+    }
+}
+
+/// Set, or clear, the pending state of the interrupt
+pub fn set_pending(irqn: i16, pending: bool) {
+    match (irqn < 0, pending) {
+        (true, _) => CoreException::from_irqn(irqn).set_pending(pending),
+        (false, true) => write_bit(ISPR, irqn),
+        (false, false) => write_bit(ICPR, irqn),
+    }
+}
+
+/// Can the interrupt be disabled on its own? Core exceptions can not, so
+/// they are masked along with all other interrupts instead.
+pub fn can_disable(irqn: i16) -> bool {
+    irqn >= 0
+}
+
+/// Disable the interrupt, which must be one that `can_disable`
+pub fn disable(irqn: i16) {
+    write_bit(ICER, irqn)
+}
+
+/// Enable the interrupt, which must be one that `can_disable`
+pub fn enable(irqn: i16) {
+    // NOTE: only interrupts disabled by this crate, or which guard data
+    // that has just been initialized, are enabled
+    write_bit(ISER, irqn)
+}
+
+/// Set the priority of the interrupt. This is the raw value written to the
+/// priority register, as with `NVIC::set_priority`.
+pub unsafe fn set_priority(irqn: i16, prio: u8) {
+    if irqn < 0 {
+        CoreException::from_irqn(irqn).set_priority(prio);
+    } else {
+        write_priority(bank(irqn) + IPR + irqn as usize, prio);
+    }
+}
+
+/// Write the priority field at `address`
+#[cfg(not(armv6m))]
+unsafe fn write_priority(address: usize, prio: u8) {
+    ptr::write_volatile(address as *mut u8, prio);
+}
+
+/// Write the priority field at `address`. The priority registers are only
+/// word accessible on ARMv6-M, so this is a read-modify-write of the word
+/// containing the field, with all interrupts masked.
+#[cfg(armv6m)]
+unsafe fn write_priority(address: usize, prio: u8) {
+    let word = (address & !3) as *mut u32;
+    let shift = 8 * (address & 3);
+    free(|_| {
+        let value = ptr::read_volatile(word) & !(0xFF << shift);
+        ptr::write_volatile(word, value | (u32::from(prio) << shift));
+    });
+}
+
+/// The priority of the interrupt
+#[cfg(feature = "basepri")]
+pub fn priority(irqn: i16) -> u8 {
+    if irqn < 0 {
+        CoreException::from_irqn(irqn).priority()
+    } else {
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { ptr::read_volatile((bank(irqn) + IPR + irqn as usize) as *const u8) }
+    }
+}
+
+/// Raise BASEPRI to `ceiling`, returning its previous value
+#[cfg(feature = "basepri")]
+pub fn raise_priority(ceiling: u8) -> u8 {
+    let previous = cortex_m::register::basepri::read();
+    cortex_m::register::basepri_max::write(ceiling);
+    previous
+}
+
+/// Restore BASEPRI to a value returned by `raise_priority`
+#[cfg(feature = "basepri")]
+pub fn restore_priority(previous: u8) {
+    unsafe { cortex_m::register::basepri::write(previous) }
+}
+
+/// Mask all interrupts with PRIMASK, returning whether they were enabled
+/// beforehand
+pub fn mask_all() -> bool {
+    let enabled = cortex_m::register::primask::read().is_active();
+    cortex_m::interrupt::disable();
+    enabled
+}
+
+/// Unmask all interrupts with PRIMASK
+pub unsafe fn unmask_all() {
+    cortex_m::interrupt::enable()
+}
+
+/// Whether interrupts were enabled before `mask_all`
+pub type Restore = bool;
+
+/// Undo `mask_all`
+pub unsafe fn restore_all(enabled: Restore) {
+    if enabled {
+        unmask_all()
+    }
+}
+
+/// Checks whether the entry in the active vector table for the interrupt
+/// points at something other than the `DefaultHandler` provided by
+/// `cortex-m-rt`
+#[cfg(feature = "check-handlers")]
+#[inline]
+pub fn handler_installed(irqn: i16) -> bool {
+    extern "C" {
+        fn DefaultHandler();
+    }
+
+    // NOTE(unsafe) atomic reads with no side effects. VTOR always points
+    // at a valid vector table, which has an entry for every interrupt
+    let handler = unsafe {
+        let table = (*SCB::PTR).vtor.read() as *const usize;
+        table.offset(16 + isize::from(irqn)).read_volatile()
+    };
+
+    handler != DefaultHandler as *const () as usize
+}
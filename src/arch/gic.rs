@@ -0,0 +1,303 @@
+//! The Cortex-A implementation of the interrupt primitives used by
+//! `nvic::IrqSet`, based on the GICv2 distributor and CPU interface
+//!
+//! Interrupts are identified by their GIC interrupt ID. The GIC only
+//! reports which interrupt is running when it is acknowledged, so handlers
+//! must acknowledge their interrupt with `shared::gic::acknowledge` for
+//! interrupt context access to be granted.
+
+use core::ptr;
+use core::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+
+use crate::nvic::Active;
+use crate::CriticalSection;
+
+/// The offset of `GICD_ISENABLER0`
+const ISENABLER: usize = 0x100;
+
+/// The offset of `GICD_ICENABLER0`
+const ICENABLER: usize = 0x180;
+
+/// The offset of `GICD_ISPENDR0`
+const ISPENDR: usize = 0x200;
+
+/// The offset of `GICD_ICPENDR0`
+const ICPENDR: usize = 0x280;
+
+/// The offset of `GICD_ISACTIVER0`
+const ISACTIVER: usize = 0x300;
+
+/// The offset of `GICD_IPRIORITYR0`, which is byte accessible
+const IPRIORITYR: usize = 0x400;
+
+/// The offset of `GICD_SGIR`
+const SGIR: usize = 0xF00;
+
+/// The offset of `GICC_IAR`
+const IAR: usize = 0x0C;
+
+/// The offset of `GICC_EOIR`
+const EOIR: usize = 0x10;
+
+/// The interrupt ID read from `GICC_IAR` when no interrupt is pending
+const SPURIOUS: u16 = 1023;
+
+/// The number of software generated interrupts, which are pended through
+/// `GICD_SGIR` rather than `GICD_ISPENDR`
+const SGIS: i16 = 16;
+
+static DISTRIBUTOR: AtomicUsize = AtomicUsize::new(0);
+static CPU_INTERFACE: AtomicUsize = AtomicUsize::new(0);
+
+/// The ID of the most recently acknowledged interrupt, plus one, or 0 in
+/// application context
+static CURRENT: AtomicU16 = AtomicU16::new(0);
+
+/// A GIC interrupt ID, which may be used as the interrupt of a `shared`
+/// item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Id(pub u16);
+
+unsafe impl cortex_m::interrupt::InterruptNumber for Id {
+    fn number(self) -> u16 {
+        self.0
+    }
+}
+
+/// Exclusive access to the GIC
+pub struct Gic {
+    _private: (),
+}
+
+impl Gic {
+    /// Take the GIC
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may be enabling interrupts while the `Gic` is held.
+    pub unsafe fn steal() -> Self {
+        Gic { _private: () }
+    }
+}
+
+/// The interrupt controller, which must be held to enable interrupts with
+/// `set_initial_nvic`
+pub type Controller = Gic;
+
+/// Use the GIC with its distributor at `distributor`, and its CPU interface
+/// at `cpu_interface`. This must be called before any `shared` item is
+/// initialized.
+///
+/// # Safety
+///
+/// The addresses must be those of the GICv2 of the device.
+pub unsafe fn set_gic(distributor: usize, cpu_interface: usize) {
+    DISTRIBUTOR.store(distributor, Ordering::SeqCst);
+    CPU_INTERFACE.store(cpu_interface, Ordering::SeqCst);
+}
+
+/// The address of the register at `offset` from `base`
+///
+/// # Panics
+///
+/// Panics if `set_gic` was not called.
+fn register(base: &AtomicUsize, offset: usize) -> *mut u32 {
+    let base = base.load(Ordering::SeqCst);
+    assert!(base != 0, "`shared::gic::set_gic` must be called first");
+    (base + offset) as *mut u32
+}
+
+/// The address of the distributor register holding the bit for `irqn`, in
+/// the bit array starting at `offset`
+fn bit_register(offset: usize, irqn: i16) -> *mut u32 {
+    register(&DISTRIBUTOR, offset + 4 * (irqn as usize / 32))
+}
+
+/// The bit for `irqn` in its register
+fn bit(irqn: i16) -> u32 {
+    1 << (irqn as usize % 32)
+}
+
+/// Is the bit for `irqn` set, in the bit array starting at `offset`?
+fn read_bit(offset: usize, irqn: i16) -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    unsafe { ptr::read_volatile(bit_register(offset, irqn)) & bit(irqn) != 0 }
+}
+
+/// Set the bit for `irqn` in the write-one-to-set or write-one-to-clear
+/// bit array starting at `offset`
+fn write_bit(offset: usize, irqn: i16) {
+    // NOTE(unsafe) writes of zero bits have no effect, so this doesn't race
+    unsafe { ptr::write_volatile(bit_register(offset, irqn), bit(irqn)) }
+}
+
+/// An interrupt acknowledged by its handler. The end of the interrupt is
+/// signalled when this is dropped.
+///
+/// ```rust,ignore
+/// #[no_mangle]
+/// extern "C" fn irq_handler() {
+///     while let Some(irq) = shared::gic::acknowledge() {
+///         match irq.id() {
+///             UART0 => UART_RX::modify_int_context(|rx| rx.push(read_byte())).unwrap(),
+///             _ => {}
+///         }
+///     }
+/// }
+/// ```
+pub struct Acknowledged {
+    iar: u32,
+    preempted: u16,
+}
+
+impl Acknowledged {
+    /// The ID of the interrupt
+    pub fn id(&self) -> u16 {
+        (self.iar & 0x3FF) as u16
+    }
+}
+
+impl Drop for Acknowledged {
+    fn drop(&mut self) {
+        CURRENT.store(self.preempted, Ordering::SeqCst);
+
+        // NOTE(unsafe) writing the acknowledged value ends the interrupt
+        unsafe { ptr::write_volatile(register(&CPU_INTERFACE, EOIR), self.iar) }
+    }
+}
+
+/// Acknowledge the most urgent pending interrupt, if any. Until the returned
+/// `Acknowledged` is dropped, the interrupt is considered to be running, and
+/// may access the `shared` items it guards.
+pub fn acknowledge() -> Option<Acknowledged> {
+    // NOTE(unsafe) acknowledging is atomic in the GIC
+    let iar = unsafe { ptr::read_volatile(register(&CPU_INTERFACE, IAR)) };
+    let id = (iar & 0x3FF) as u16;
+    if id == SPURIOUS {
+        return None;
+    }
+
+    let preempted = free(|_| {
+        let preempted = CURRENT.load(Ordering::SeqCst);
+        CURRENT.store(id + 1, Ordering::SeqCst);
+        preempted
+    });
+
+    Some(Acknowledged { iar, preempted })
+}
+
+/// The most recently acknowledged interrupt which has not ended yet
+pub fn active() -> Active {
+    match CURRENT.load(Ordering::SeqCst) {
+        0 => Active::Thread,
+        current => Active::Interrupt(current as i16 - 1),
+    }
+}
+
+/// Is the interrupt enabled in the distributor?
+pub fn is_enabled(irqn: i16) -> bool {
+    read_bit(ISENABLER, irqn)
+}
+
+/// Is the interrupt active (running, or preempted)?
+pub fn is_active(irqn: i16) -> bool {
+    read_bit(ISACTIVER, irqn)
+}
+
+/// Is the interrupt pending?
+pub fn is_pending(irqn: i16) -> bool {
+    read_bit(ISPENDR, irqn)
+}
+
+/// Set, or clear, the pending state of the interrupt. Software generated
+/// interrupts are pended on the current CPU through `GICD_SGIR`, and can not
+/// be cleared.
+pub fn set_pending(irqn: i16, pending: bool) {
+    match (irqn < SGIS, pending) {
+        // NOTE(unsafe) a target list filter of 0b10 only targets this CPU
+        (true, true) => unsafe {
+            ptr::write_volatile(register(&DISTRIBUTOR, SGIR), (0b10 << 24) | irqn as u32)
+        },
+        (true, false) => {}
+        (false, true) => write_bit(ISPENDR, irqn),
+        (false, false) => write_bit(ICPENDR, irqn),
+    }
+}
+
+/// Every GIC interrupt can be disabled on its own
+pub fn can_disable(_irqn: i16) -> bool {
+    true
+}
+
+/// Disable the interrupt in the distributor
+pub fn disable(irqn: i16) {
+    write_bit(ICENABLER, irqn)
+}
+
+/// Enable the interrupt in the distributor
+pub fn enable(irqn: i16) {
+    write_bit(ISENABLER, irqn)
+}
+
+/// Set the priority of the interrupt. This is the raw value written to
+/// `GICD_IPRIORITYR`, where lower values are more urgent.
+pub unsafe fn set_priority(irqn: i16, prio: u8) {
+    let priority = register(&DISTRIBUTOR, IPRIORITYR + irqn as usize) as *mut u8;
+    ptr::write_volatile(priority, prio);
+}
+
+/// Whether IRQs were enabled before `mask_all`
+pub type Restore = bool;
+
+/// Mask IRQs with the I bit of CPSR, returning whether they were enabled
+/// beforehand
+#[cfg(target_arch = "arm")]
+pub fn mask_all() -> Restore {
+    let cpsr: u32;
+    // NOTE(unsafe) masking IRQs has no other side effects
+    unsafe { core::arch::asm!("mrs {0}, cpsr", "cpsid i", out(reg) cpsr) };
+    cpsr & (1 << 7) == 0
+}
+
+/// Unmask IRQs with the I bit of CPSR
+#[cfg(target_arch = "arm")]
+unsafe fn unmask_all() {
+    core::arch::asm!("cpsie i");
+}
+
+/// Mask IRQs with the I bit of DAIF, returning whether they were enabled
+/// beforehand
+#[cfg(target_arch = "aarch64")]
+pub fn mask_all() -> Restore {
+    let daif: u64;
+    // NOTE(unsafe) masking IRQs has no other side effects
+    unsafe { core::arch::asm!("mrs {0}, daif", "msr daifset, #2", out(reg) daif) };
+    daif & (1 << 7) == 0
+}
+
+/// Unmask IRQs with the I bit of DAIF
+#[cfg(target_arch = "aarch64")]
+unsafe fn unmask_all() {
+    core::arch::asm!("msr daifclr, #2");
+}
+
+/// Undo `mask_all`
+pub unsafe fn restore_all(enabled: Restore) {
+    if enabled {
+        unmask_all()
+    }
+}
+
+/// Execute the closure with all interrupts masked
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    let enabled = mask_all();
+
+    // NOTE(unsafe) interrupts are masked until the closure returns
+    let r = f(unsafe { &CriticalSection::new() });
+
+    unsafe { restore_all(enabled) };
+    r
+}
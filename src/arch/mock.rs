@@ -0,0 +1,368 @@
+//! A simulated interrupt controller for running code using `shared` items
+//! on the host, for example in unit tests
+//!
+//! Interrupts are identified by their number, as on Cortex-M. The enable,
+//! active and pending bits are kept in memory, and an interrupt handler is
+//! run with `shared::mock::fire`, typically from another thread than the
+//! application code:
+//!
+//! ```rust,ignore
+//! let mut nvic = unsafe { shared::mock::MockNvic::steal() };
+//! let mut token = COUNTER::set_initial_nvic(0, &mut nvic).unwrap();
+//!
+//! let isr = std::thread::spawn(|| {
+//!     shared::mock::fire(Irq(3), || {
+//!         COUNTER::modify_int_context(|c| *c += 1).unwrap();
+//!     })
+//! });
+//!
+//! token.modify_app_context(|c| *c += 1).unwrap();
+//! isr.join().unwrap();
+//! ```
+//!
+//! Like a real interrupt, a fired handler only runs once its interrupt is
+//! enabled and not masked, and masking an interrupt waits for a handler that
+//! is already running to return, as if it had preempted the application.
+//!
+//! Alternatively, `with_preemption` calls a hook at every point where the
+//! code running on the current thread touches the simulated controller, and
+//! the hook may run an interrupt handler right there with `preempt`. This
+//! walks through the interleavings one by one, on a single thread.
+
+use std::cell::RefCell;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+use std::vec::Vec;
+
+use crate::nvic::{Active, Interrupt};
+use crate::CriticalSection;
+
+/// The number of simulated interrupts
+const INTERRUPTS: usize = 496;
+
+/// The simulated state of every interrupt
+struct State {
+    enabled: [bool; INTERRUPTS],
+    active: [Option<ThreadId>; INTERRUPTS],
+    pending: [bool; INTERRUPTS],
+    priority: [u8; INTERRUPTS],
+    masked_by: Option<ThreadId>,
+}
+
+impl State {
+    const fn new() -> Self {
+        State {
+            enabled: [false; INTERRUPTS],
+            active: [None; INTERRUPTS],
+            pending: [false; INTERRUPTS],
+            priority: [0; INTERRUPTS],
+            masked_by: None,
+        }
+    }
+
+    /// Is any interrupt running on a thread other than the current one?
+    fn running_elsewhere(&self, me: ThreadId) -> bool {
+        self.active.iter().flatten().any(|&thread| thread != me)
+    }
+
+    /// Are all interrupts masked by a thread other than the current one?
+    fn masked_elsewhere(&self, me: ThreadId) -> bool {
+        matches!(self.masked_by, Some(thread) if thread != me)
+    }
+}
+
+static STATE: Mutex<State> = Mutex::new(State::new());
+
+/// Notified whenever the state changes, for threads waiting on it
+static CHANGED: Condvar = Condvar::new();
+
+std::thread_local! {
+    /// The interrupts running on this thread, innermost last
+    static RUNNING: RefCell<Vec<i16>> = const { RefCell::new(Vec::new()) };
+
+    /// Called at every preemption point of this thread, see `with_preemption`
+    static HOOK: RefCell<Option<Box<dyn FnMut()>>> = const { RefCell::new(None) };
+}
+
+/// Lock the simulated state. A panicking handler does not leave it
+/// inconsistent, so poisoning is ignored.
+///
+/// Every access is a preemption point.
+fn state() -> MutexGuard<'static, State> {
+    preemption_point();
+    STATE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Wait until `ready` returns `true` for the simulated state
+fn wait_until<F>(mut ready: F) -> MutexGuard<'static, State>
+where
+    F: FnMut(&State) -> bool,
+{
+    let mut state = state();
+    while !ready(&state) {
+        state = CHANGED
+            .wait(state)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+    state
+}
+
+/// A simulated interrupt number, for tests without the interrupt enum of a
+/// device crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Irq(pub u16);
+
+unsafe impl cortex_m::interrupt::InterruptNumber for Irq {
+    fn number(self) -> u16 {
+        self.0
+    }
+}
+
+/// The simulated interrupt controller
+pub struct MockNvic {
+    _private: (),
+}
+
+impl MockNvic {
+    /// Take the simulated interrupt controller
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may be enabling interrupts while the `MockNvic` is held.
+    pub unsafe fn steal() -> Self {
+        MockNvic { _private: () }
+    }
+
+    /// Enable the interrupt, as `NVIC::unmask` does
+    pub fn unmask<I: Interrupt>(&mut self, int: I) {
+        enable(int.number() as i16);
+    }
+}
+
+/// The interrupt controller, which must be held to enable interrupts with
+/// `set_initial_nvic`
+pub type Controller = MockNvic;
+
+/// Marks the interrupt as no longer running, even if the handler panics
+struct Exit {
+    irqn: i16,
+}
+
+impl Drop for Exit {
+    fn drop(&mut self) {
+        RUNNING.with(|running| running.borrow_mut().pop());
+        state().active[self.irqn as usize] = None;
+        CHANGED.notify_all();
+    }
+}
+
+/// Pend the interrupt, and run `handler` as its handler on the current
+/// thread once it is enabled and not masked. Returns what the handler
+/// returns.
+///
+/// This blocks until the interrupt may run, so the application code
+/// enabling it, or releasing the masking, must be running on another
+/// thread.
+pub fn fire<I, F, R>(int: I, handler: F) -> R
+where
+    I: Interrupt,
+    F: FnOnce() -> R,
+{
+    let irqn = int.number() as i16;
+    let index = irqn as usize;
+    let me = thread::current().id();
+
+    state().pending[index] = true;
+    CHANGED.notify_all();
+
+    {
+        let mut state = wait_until(|state| {
+            state.enabled[index] && state.active[index].is_none() && !state.masked_elsewhere(me)
+        });
+        state.pending[index] = false;
+        state.active[index] = Some(me);
+    }
+    CHANGED.notify_all();
+
+    RUNNING.with(|running| running.borrow_mut().push(irqn));
+    let _exit = Exit { irqn };
+    handler()
+}
+
+/// Run `handler` as the handler of the interrupt on the current thread, as
+/// if it had preempted the code running there. Returns what the handler
+/// returns.
+///
+/// Unlike `fire`, this does not wait: if the interrupt is disabled, masked
+/// or already running, the handler is not run, and `None` is returned.
+pub fn preempt<I, F, R>(int: I, handler: F) -> Option<R>
+where
+    I: Interrupt,
+    F: FnOnce() -> R,
+{
+    let irqn = int.number() as i16;
+    let index = irqn as usize;
+    let me = thread::current().id();
+
+    {
+        let mut state = state();
+        if !state.enabled[index] || state.active[index].is_some() || state.masked_by.is_some() {
+            return None;
+        }
+        state.pending[index] = false;
+        state.active[index] = Some(me);
+    }
+    CHANGED.notify_all();
+
+    RUNNING.with(|running| running.borrow_mut().push(irqn));
+    let _exit = Exit { irqn };
+    Some(handler())
+}
+
+/// Puts the hook replaced by `with_preemption` back, even if the code under
+/// test panics
+struct Unhook {
+    previous: Option<Box<dyn FnMut()>>,
+}
+
+impl Drop for Unhook {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        HOOK.with(|hook| *hook.borrow_mut() = previous);
+    }
+}
+
+/// Run `f`, calling `hook` at every preemption point it reaches on the
+/// current thread, which is every access to the simulated controller, and
+/// every call to `preemption_point`. Returns what `f` returns.
+///
+/// The hook may run interrupt handlers with `preempt`. While it runs, it is
+/// not called again, so the handlers it runs are not preempted themselves.
+pub fn with_preemption<H, F, R>(hook: H, f: F) -> R
+where
+    H: FnMut() + 'static,
+    F: FnOnce() -> R,
+{
+    let previous = HOOK.with(|slot| slot.borrow_mut().replace(Box::new(hook)));
+    let _unhook = Unhook { previous };
+    f()
+}
+
+/// Call the hook installed with `with_preemption`, if any. The simulated
+/// controller does so on every access, and code under test may do so
+/// wherever else it wants to be preempted, such as in the middle of an
+/// access to shared data.
+pub fn preemption_point() {
+    let taken = HOOK.with(|hook| hook.borrow_mut().take());
+    if let Some(mut hook) = taken {
+        hook();
+        HOOK.with(|slot| *slot.borrow_mut() = Some(hook));
+    }
+}
+
+/// Reset every interrupt to disabled, inactive and not pending, and
+/// release any masking. This is intended to be called between tests.
+pub fn reset() {
+    *state() = State::new();
+    CHANGED.notify_all();
+}
+
+/// The priority most recently set for the interrupt
+pub fn priority(irqn: u16) -> u8 {
+    state().priority[usize::from(irqn)]
+}
+
+/// The interrupt running on the current thread, if any. This is a
+/// preemption point, like every access to the simulated controller.
+pub fn active() -> Active {
+    preemption_point();
+    RUNNING.with(|running| match running.borrow().last() {
+        Some(&irqn) => Active::Interrupt(irqn),
+        None => Active::Thread,
+    })
+}
+
+/// Is the interrupt enabled?
+pub fn is_enabled(irqn: i16) -> bool {
+    state().enabled[irqn as usize]
+}
+
+/// Is the interrupt running, on any thread?
+pub fn is_active(irqn: i16) -> bool {
+    state().active[irqn as usize].is_some()
+}
+
+/// Is the interrupt pending?
+pub fn is_pending(irqn: i16) -> bool {
+    state().pending[irqn as usize]
+}
+
+/// Set, or clear, the pending bit of the interrupt. This does not run the
+/// interrupt, which is only done by `fire`.
+pub fn set_pending(irqn: i16, pending: bool) {
+    state().pending[irqn as usize] = pending;
+    CHANGED.notify_all();
+}
+
+/// Every simulated interrupt can be disabled on its own
+pub fn can_disable(_irqn: i16) -> bool {
+    true
+}
+
+/// Disable the interrupt. If it is running on another thread, this waits
+/// for the handler to return, as if it had preempted the current thread.
+pub fn disable(irqn: i16) {
+    let index = irqn as usize;
+    let me = thread::current().id();
+    let mut state =
+        wait_until(|state| !matches!(state.active[index], Some(thread) if thread != me));
+    state.enabled[index] = false;
+}
+
+/// Enable the interrupt
+pub fn enable(irqn: i16) {
+    state().enabled[irqn as usize] = true;
+    CHANGED.notify_all();
+}
+
+/// Record the priority of the interrupt, which is otherwise ignored
+pub unsafe fn set_priority(irqn: i16, prio: u8) {
+    state().priority[irqn as usize] = prio;
+}
+
+/// Whether all interrupts were unmasked before `mask_all`
+pub type Restore = bool;
+
+/// Mask all interrupts, waiting for handlers running on other threads to
+/// return first. Returns whether they were unmasked beforehand.
+pub fn mask_all() -> Restore {
+    let me = thread::current().id();
+    let mut state = wait_until(|state| !state.masked_elsewhere(me) && !state.running_elsewhere(me));
+    let unmasked = state.masked_by.is_none();
+    state.masked_by = Some(me);
+    unmasked
+}
+
+/// Undo `mask_all`
+pub unsafe fn restore_all(unmasked: Restore) {
+    if unmasked {
+        state().masked_by = None;
+        CHANGED.notify_all();
+    }
+}
+
+/// Execute the closure with all interrupts masked
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    let unmasked = mask_all();
+
+    // NOTE(unsafe) interrupts are masked until the closure returns
+    let r = f(unsafe { &CriticalSection::new() });
+
+    unsafe { restore_all(unmasked) };
+    r
+}
@@ -0,0 +1,63 @@
+//! The MSP430 implementation of the interrupt primitives used by
+//! `nvic::IrqSet`, based on the interrupt enable bits of each peripheral
+//!
+//! The MSP430 has no central interrupt controller, so this uses the
+//! interrupt sources registered with `shared::msp430::set_sources`.
+
+use crate::CriticalSection;
+
+#[path = "running.rs"]
+mod running;
+#[path = "sources.rs"]
+mod sources;
+
+pub use self::running::*;
+pub use self::sources::*;
+
+/// The interrupt enable bit in the status register
+const GIE: u16 = 1 << 3;
+
+/// MSP430 interrupt priorities are fixed by their vector, so this has no
+/// effect
+pub unsafe fn set_priority(_irqn: i16, _prio: u8) {}
+
+/// Mask all interrupts with the `GIE` bit, returning whether they were
+/// enabled beforehand
+pub fn mask_all() -> bool {
+    let sr: u16;
+    // NOTE(unsafe) clearing GIE has no other side effects. The `nop` is
+    // required for the `dint` to take effect before the next instruction
+    unsafe { core::arch::asm!("mov r2, {0}", "dint", "nop", out(reg) sr) };
+    sr & GIE != 0
+}
+
+/// Unmask all interrupts with the `GIE` bit
+pub unsafe fn unmask_all() {
+    core::arch::asm!("nop", "eint", "nop");
+}
+
+/// Whether interrupts were enabled before `mask_all`
+pub type Restore = bool;
+
+/// Undo `mask_all`
+pub unsafe fn restore_all(enabled: Restore) {
+    if enabled {
+        unmask_all()
+    }
+}
+
+/// Execute the closure with all interrupts masked
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    let enabled = mask_all();
+
+    // NOTE(unsafe) interrupts are masked until the closure returns
+    let r = f(unsafe { &CriticalSection::new() });
+
+    if enabled {
+        unsafe { unmask_all() }
+    }
+    r
+}
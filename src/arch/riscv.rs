@@ -0,0 +1,279 @@
+//! The RISC-V implementation of the interrupt primitives used by
+//! `nvic::IrqSet`, based on the platform level interrupt controller (PLIC)
+//!
+//! Interrupts are identified by their PLIC source ID. Unlike the NVIC, the
+//! PLIC does not know which interrupt is running, so handlers must claim
+//! their interrupt with `shared::riscv::claim` for interrupt context access
+//! to be granted.
+
+use core::ptr;
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicUsize, Ordering};
+
+use crate::nvic::Active;
+use crate::CriticalSection;
+
+/// The base address of the PLIC on the FE310, used unless `set_plic` is
+/// called
+const DEFAULT_BASE: usize = 0x0C00_0000;
+
+/// The offset of the pending bits
+const PENDING: usize = 0x1000;
+
+/// The offset of the enable bits of context 0. The other contexts follow
+/// at 0x80 byte intervals
+const ENABLE: usize = 0x2000;
+
+/// The offset of the priority threshold of context 0. The other contexts
+/// follow at 0x1000 byte intervals, with the claim/complete register
+/// directly after the threshold
+const THRESHOLD: usize = 0x20_0000;
+
+/// The number of interrupt sources supported by the PLIC, including the
+/// reserved source 0
+const SOURCES: usize = 1024;
+
+static BASE: AtomicUsize = AtomicUsize::new(DEFAULT_BASE);
+static CONTEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// The source ID of the most recently claimed interrupt, or 0 in
+/// application context
+static CURRENT: AtomicU16 = AtomicU16::new(0);
+
+/// A bit for every source ID that is claimed, and not yet completed
+#[allow(clippy::declare_interior_mutable_const)]
+const NONE_CLAIMED: AtomicU32 = AtomicU32::new(0);
+static CLAIMED: [AtomicU32; SOURCES / 32] = [NONE_CLAIMED; SOURCES / 32];
+
+/// The interrupt controller, which must be held to enable interrupts with
+/// `set_initial_nvic`
+pub type Controller = Plic;
+
+/// Exclusive access to the PLIC
+pub struct Plic {
+    _private: (),
+}
+
+impl Plic {
+    /// Take the PLIC
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may be enabling interrupts while the `Plic` is held.
+    pub unsafe fn steal() -> Self {
+        Plic { _private: () }
+    }
+}
+
+/// Use the PLIC at `base`, from the hart context `context`, rather than the
+/// FE310 defaults of `0x0C00_0000` and context 0. This must be called before
+/// any `shared` item is initialized.
+///
+/// # Safety
+///
+/// `base` must be the address of a PLIC, and `context` must be the context
+/// of the hart and privilege mode this code runs in.
+pub unsafe fn set_plic(base: usize, context: usize) {
+    BASE.store(base, Ordering::SeqCst);
+    CONTEXT.store(context, Ordering::SeqCst);
+}
+
+/// The address of the register at `offset` from the base of the PLIC
+fn register(offset: usize) -> *mut u32 {
+    (BASE.load(Ordering::SeqCst) + offset) as *mut u32
+}
+
+/// The address of the register holding the bit for `irqn`, in the bit array
+/// starting at `offset`
+fn bit_register(offset: usize, irqn: i16) -> *mut u32 {
+    register(offset + 4 * (irqn as usize / 32))
+}
+
+/// The bit for `irqn` in its register
+fn bit(irqn: i16) -> u32 {
+    1 << (irqn as usize % 32)
+}
+
+/// The address of the enable bits of the current context
+fn enable_offset() -> usize {
+    ENABLE + 0x80 * CONTEXT.load(Ordering::SeqCst)
+}
+
+/// The address of the claim/complete register of the current context
+fn claim_register() -> *mut u32 {
+    register(THRESHOLD + 0x1000 * CONTEXT.load(Ordering::SeqCst) + 4)
+}
+
+/// An interrupt claimed from the PLIC by its handler. The interrupt is
+/// completed when this is dropped.
+///
+/// ```rust,ignore
+/// #[no_mangle]
+/// fn MachineExternal() {
+///     while let Some(claim) = shared::riscv::claim() {
+///         match claim.id() {
+///             UART0 => UART_RX::modify_int_context(|rx| rx.push(read_byte())).unwrap(),
+///             _ => {}
+///         }
+///     }
+/// }
+/// ```
+pub struct Claim {
+    id: u16,
+    preempted: u16,
+}
+
+impl Claim {
+    /// The source ID of the interrupt
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let index = usize::from(self.id) / 32;
+        free(|_| {
+            let claimed = CLAIMED[index].load(Ordering::SeqCst);
+            CLAIMED[index].store(claimed & !bit(self.id as i16), Ordering::SeqCst);
+            CURRENT.store(self.preempted, Ordering::SeqCst);
+        });
+
+        // NOTE(unsafe) writing the claimed ID completes the interrupt
+        unsafe { ptr::write_volatile(claim_register(), u32::from(self.id)) }
+    }
+}
+
+/// Claim the most urgent pending interrupt, if any. Until the returned
+/// `Claim` is dropped, the interrupt is considered to be running, and may
+/// access the `shared` items it guards.
+pub fn claim() -> Option<Claim> {
+    // NOTE(unsafe) claiming is atomic in the PLIC
+    let id = unsafe { ptr::read_volatile(claim_register()) } as u16;
+    if id == 0 {
+        return None;
+    }
+
+    let index = usize::from(id) / 32;
+    let preempted = free(|_| {
+        let claimed = CLAIMED[index].load(Ordering::SeqCst);
+        CLAIMED[index].store(claimed | bit(id as i16), Ordering::SeqCst);
+        let preempted = CURRENT.load(Ordering::SeqCst);
+        CURRENT.store(id, Ordering::SeqCst);
+        preempted
+    });
+
+    Some(Claim { id, preempted })
+}
+
+/// The most recently claimed interrupt which has not been completed yet
+pub fn active() -> Active {
+    match CURRENT.load(Ordering::SeqCst) {
+        0 => Active::Thread,
+        id => Active::Interrupt(id as i16),
+    }
+}
+
+/// Is the interrupt enabled for the current context?
+pub fn is_enabled(irqn: i16) -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    unsafe { ptr::read_volatile(bit_register(enable_offset(), irqn)) & bit(irqn) != 0 }
+}
+
+/// Is the interrupt claimed, and not yet completed?
+pub fn is_active(irqn: i16) -> bool {
+    CLAIMED[irqn as usize / 32].load(Ordering::SeqCst) & bit(irqn) != 0
+}
+
+/// Is the interrupt pending?
+pub fn is_pending(irqn: i16) -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    unsafe { ptr::read_volatile(bit_register(PENDING, irqn)) & bit(irqn) != 0 }
+}
+
+/// The pending bits of the PLIC are read only, so interrupts can not be
+/// pended or unpended in software
+///
+/// # Panics
+///
+/// Always panics.
+pub fn set_pending(_irqn: i16, _pending: bool) {
+    panic!("the PLIC does not support pending interrupts in software");
+}
+
+/// Every PLIC source can be disabled on its own
+pub fn can_disable(_irqn: i16) -> bool {
+    true
+}
+
+/// Set or clear the enable bit of the interrupt. The enable registers are
+/// shared by 32 sources, so this is a read-modify-write with all
+/// interrupts masked.
+fn set_enabled(irqn: i16, enabled: bool) {
+    let enable = bit_register(enable_offset(), irqn);
+    free(|_| unsafe {
+        let bits = ptr::read_volatile(enable);
+        let bits = if enabled {
+            bits | bit(irqn)
+        } else {
+            bits & !bit(irqn)
+        };
+        ptr::write_volatile(enable, bits);
+    })
+}
+
+/// Disable the interrupt for the current context
+pub fn disable(irqn: i16) {
+    set_enabled(irqn, false)
+}
+
+/// Enable the interrupt for the current context
+pub fn enable(irqn: i16) {
+    set_enabled(irqn, true)
+}
+
+/// Set the priority of the interrupt. This is the raw value written to the
+/// priority register, where 0 never interrupts, and larger values are more
+/// urgent.
+pub unsafe fn set_priority(irqn: i16, prio: u8) {
+    ptr::write_volatile(register(4 * irqn as usize), u32::from(prio));
+}
+
+/// Mask all interrupts with `mstatus.MIE`, returning whether they were
+/// enabled beforehand
+pub fn mask_all() -> bool {
+    let mstatus: usize;
+    // NOTE(unsafe) clearing MIE has no other side effects
+    unsafe { core::arch::asm!("csrrci {0}, mstatus, 8", out(reg) mstatus) };
+    mstatus & 8 != 0
+}
+
+/// Unmask all interrupts with `mstatus.MIE`
+pub unsafe fn unmask_all() {
+    core::arch::asm!("csrsi mstatus, 8");
+}
+
+/// Whether interrupts were enabled before `mask_all`
+pub type Restore = bool;
+
+/// Undo `mask_all`
+pub unsafe fn restore_all(enabled: Restore) {
+    if enabled {
+        unmask_all()
+    }
+}
+
+/// Execute the closure with all interrupts masked
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    let enabled = mask_all();
+
+    // NOTE(unsafe) interrupts are masked until the closure returns
+    let r = f(unsafe { &CriticalSection::new() });
+
+    if enabled {
+        unsafe { unmask_all() }
+    }
+    r
+}
@@ -0,0 +1,96 @@
+//! Tracking which interrupt is running, for targets where the interrupt
+//! controller can not report it. Handlers `enter` their interrupt for
+//! interrupt context access to be granted.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use super::free;
+use crate::nvic::{Active, Interrupt};
+
+/// The number of interrupt vectors supported
+const VECTORS: usize = 64;
+
+/// The vector number of the most recently entered interrupt, plus one, or 0
+/// in application context
+static CURRENT: AtomicU16 = AtomicU16::new(0);
+
+/// A bit for every vector that has been entered, and not yet left
+#[allow(clippy::declare_interior_mutable_const)]
+const NONE_RUNNING: AtomicU16 = AtomicU16::new(0);
+static RUNNING: [AtomicU16; VECTORS / 16] = [NONE_RUNNING; VECTORS / 16];
+
+/// An interrupt vector, by number. This may be used as the interrupt of a
+/// `shared` item on targets whose device crates do not provide an
+/// interrupt enum with a number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vector(pub u8);
+
+unsafe impl cortex_m::interrupt::Nr for Vector {
+    fn nr(&self) -> u8 {
+        self.0
+    }
+}
+
+/// The bit for `irqn` in its word of `RUNNING`
+fn bit(irqn: i16) -> u16 {
+    1 << (irqn as usize % 16)
+}
+
+/// An interrupt that is running, made by `enter`. The interrupt is left when
+/// this is dropped.
+///
+/// ```rust,ignore
+/// #[interrupt]
+/// fn TIMER0_A0() {
+///     let _running = shared::msp430::enter(Interrupt::TIMER0_A0);
+///     TICKS::modify_int_context(|t| *t += 1).unwrap();
+/// }
+/// ```
+#[must_use = "the interrupt is left as soon as this is dropped"]
+pub struct Running {
+    irqn: i16,
+    preempted: u16,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let index = self.irqn as usize / 16;
+        free(|_| {
+            let running = RUNNING[index].load(Ordering::SeqCst);
+            RUNNING[index].store(running & !bit(self.irqn), Ordering::SeqCst);
+            CURRENT.store(self.preempted, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Mark the interrupt as running, until the returned `Running` is dropped.
+/// This must be called at the start of the handler of every interrupt that
+/// accesses `shared` items from interrupt context.
+pub fn enter<I: Interrupt>(int: I) -> Running {
+    let irqn = int.number() as i16;
+    assert!((irqn as usize) < VECTORS);
+
+    let index = irqn as usize / 16;
+    let preempted = free(|_| {
+        let running = RUNNING[index].load(Ordering::SeqCst);
+        RUNNING[index].store(running | bit(irqn), Ordering::SeqCst);
+        let preempted = CURRENT.load(Ordering::SeqCst);
+        CURRENT.store(irqn as u16 + 1, Ordering::SeqCst);
+        preempted
+    });
+
+    Running { irqn, preempted }
+}
+
+/// The most recently entered interrupt which has not been left yet
+pub fn active() -> Active {
+    match CURRENT.load(Ordering::SeqCst) {
+        0 => Active::Thread,
+        current => Active::Interrupt(current as i16 - 1),
+    }
+}
+
+/// Has the interrupt been entered, and not yet left?
+pub fn is_active(irqn: i16) -> bool {
+    RUNNING[irqn as usize / 16].load(Ordering::SeqCst) & bit(irqn) != 0
+}
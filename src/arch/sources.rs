@@ -0,0 +1,121 @@
+//! Interrupt primitives for targets without a central interrupt controller,
+//! such as the MSP430 and AVR, based on the interrupt enable bits of each
+//! peripheral
+//!
+//! The application registers an `InterruptSource` for every interrupt vector
+//! guarding a `shared` item with `set_sources`. Interrupts are identified by
+//! their vector number.
+
+/// The sources registered with `set_sources`, indexed by vector number
+static mut SOURCES: &[&dyn InterruptSource] = &[];
+
+/// The peripheral side of an interrupt vector, which can enable, disable,
+/// and report the pending state of the interrupt. This is usually a pair
+/// of bits in the control registers of a peripheral, such as `TACCTL0.CCIE`
+/// and `TACCTL0.CCIFG`.
+///
+/// ```rust,ignore
+/// struct TimerA0;
+///
+/// impl shared::msp430::InterruptSource for TimerA0 {
+///     fn enable(&self) {
+///         unsafe { (*TIMER0_A3::ptr()).taccctl0.modify(|_, w| w.ccie().set_bit()) }
+///     }
+///     // ...
+/// }
+/// ```
+pub trait InterruptSource: Sync {
+    /// Enable the interrupt in the peripheral
+    fn enable(&self);
+
+    /// Disable the interrupt in the peripheral
+    fn disable(&self);
+
+    /// Is the interrupt enabled in the peripheral?
+    fn is_enabled(&self) -> bool;
+
+    /// Is the interrupt flag of the peripheral set?
+    fn is_pending(&self) -> bool;
+
+    /// Set, or clear, the interrupt flag of the peripheral. Not every flag
+    /// may be written by software, so this panics by default.
+    fn set_pending(&self, _pending: bool) {
+        panic!("this interrupt source can not be pended in software");
+    }
+}
+
+/// Exclusive access to the registered interrupt sources
+pub struct Sources {
+    _private: (),
+}
+
+impl Sources {
+    /// Take the interrupt sources
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may be enabling interrupts while the `Sources` are held.
+    pub unsafe fn steal() -> Self {
+        Sources { _private: () }
+    }
+}
+
+/// The interrupt controller, which must be held to enable interrupts with
+/// `set_initial_nvic`
+pub type Controller = Sources;
+
+/// Register the interrupt source of every vector, indexed by vector number.
+/// Vectors without `shared` items may use any source, as they are never
+/// touched.
+///
+/// # Safety
+///
+/// This must be called before any `shared` item is initialized, and must not
+/// be called again afterwards.
+pub unsafe fn set_sources(sources: &'static [&'static dyn InterruptSource]) {
+    SOURCES = sources;
+}
+
+/// The source registered for the vector
+///
+/// # Panics
+///
+/// Panics if no source was registered for the vector.
+fn source(irqn: i16) -> &'static dyn InterruptSource {
+    // NOTE(unsafe) the sources are only written before any item exists
+    let sources = unsafe { SOURCES };
+    match sources.get(irqn as usize) {
+        Some(source) => *source,
+        None => panic!("no interrupt source registered for this vector"),
+    }
+}
+
+/// Is the interrupt enabled in its peripheral?
+pub fn is_enabled(irqn: i16) -> bool {
+    source(irqn).is_enabled()
+}
+
+/// Is the interrupt flag of the peripheral set?
+pub fn is_pending(irqn: i16) -> bool {
+    source(irqn).is_pending()
+}
+
+/// Set, or clear, the interrupt flag of the peripheral
+pub fn set_pending(irqn: i16, pending: bool) {
+    source(irqn).set_pending(pending)
+}
+
+/// Every registered source can be disabled on its own
+pub fn can_disable(_irqn: i16) -> bool {
+    true
+}
+
+/// Disable the interrupt in its peripheral
+pub fn disable(irqn: i16) {
+    source(irqn).disable()
+}
+
+/// Enable the interrupt in its peripheral
+pub fn enable(irqn: i16) {
+    source(irqn).enable()
+}
@@ -0,0 +1,291 @@
+//! A virtual interrupt controller for single threaded environments such as
+//! WebAssembly in the browser, for interactive documentation and demos
+//!
+//! Interrupts are identified by their number, as on Cortex-M, and their
+//! handlers are registered with `shared::wasm::set_handler`. Pending an
+//! interrupt, typically from a JavaScript callback, runs its handler straight
+//! away if it is enabled, unmasked, and more urgent than the code currently
+//! running, like the NVIC would. Otherwise it runs as soon as that changes,
+//! such as when the application releases the item it was accessing:
+//!
+//! ```rust,ignore
+//! fn timer() {
+//!     COUNTER::modify_int_context(|c| *c += 1).unwrap();
+//! }
+//!
+//! #[wasm_bindgen]
+//! pub fn start() {
+//!     shared::wasm::set_handler(Irq(3), timer);
+//!     let mut nvic = unsafe { shared::wasm::VirtualNvic::steal() };
+//!     COUNTER::set_initial_nvic(0, &mut nvic).unwrap();
+//! }
+//!
+//! #[wasm_bindgen]
+//! pub fn on_timer() {
+//!     shared::wasm::pend(Irq(3));
+//! }
+//! ```
+//!
+//! Everything happens synchronously on the calling thread, so a sequence of
+//! calls always produces the same interleaving. `set_trace` reports every
+//! handler entry and exit, to show that interleaving.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+
+use crate::nvic::{Active, Interrupt};
+use crate::CriticalSection;
+
+/// The number of virtual interrupts
+const INTERRUPTS: usize = 64;
+
+/// A bit for every interrupt
+struct Bits([AtomicU32; INTERRUPTS / 32]);
+
+impl Bits {
+    const fn new() -> Self {
+        Bits([AtomicU32::new(0), AtomicU32::new(0)])
+    }
+
+    fn get(&self, irqn: i16) -> bool {
+        self.0[index(irqn) / 32].load(Ordering::SeqCst) & bit(irqn) != 0
+    }
+
+    fn set(&self, irqn: i16, value: bool) {
+        let word = &self.0[index(irqn) / 32];
+        let bits = word.load(Ordering::SeqCst);
+        let bits = if value {
+            bits | bit(irqn)
+        } else {
+            bits & !bit(irqn)
+        };
+        word.store(bits, Ordering::SeqCst);
+    }
+}
+
+/// The index of `irqn` in the tables of interrupts
+fn index(irqn: i16) -> usize {
+    assert!((irqn as usize) < INTERRUPTS, "no such virtual interrupt");
+    irqn as usize
+}
+
+/// The bit for `irqn` in its word
+fn bit(irqn: i16) -> u32 {
+    1 << (index(irqn) % 32)
+}
+
+static ENABLED: Bits = Bits::new();
+static PENDING: Bits = Bits::new();
+static ACTIVE: Bits = Bits::new();
+
+#[allow(clippy::declare_interior_mutable_const)]
+const LOWEST: AtomicU8 = AtomicU8::new(0);
+static PRIORITIES: [AtomicU8; INTERRUPTS] = [LOWEST; INTERRUPTS];
+
+/// Whether all interrupts are masked
+static MASKED: AtomicBool = AtomicBool::new(false);
+
+/// The number of the interrupt currently running, plus one, or 0 in
+/// application context
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+/// The handler of every interrupt, as a `fn()`, or 0 if it has none
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static HANDLERS: [AtomicUsize; INTERRUPTS] = [NO_HANDLER; INTERRUPTS];
+
+/// The function given to `set_trace`, as a `fn(Event)`, or 0
+static TRACE: AtomicUsize = AtomicUsize::new(0);
+
+/// A virtual interrupt number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Irq(pub u16);
+
+unsafe impl cortex_m::interrupt::InterruptNumber for Irq {
+    fn number(self) -> u16 {
+        self.0
+    }
+}
+
+/// A change in the code that is running, reported to the `set_trace`
+/// function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The handler of the interrupt is about to run
+    Enter(u16),
+    /// The handler of the interrupt has returned
+    Exit(u16),
+}
+
+/// The virtual interrupt controller
+pub struct VirtualNvic {
+    _private: (),
+}
+
+impl VirtualNvic {
+    /// Take the virtual interrupt controller
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may be enabling interrupts while the `VirtualNvic` is
+    /// held.
+    pub unsafe fn steal() -> Self {
+        VirtualNvic { _private: () }
+    }
+}
+
+/// The interrupt controller, which must be held to enable interrupts with
+/// `set_initial_nvic`
+pub type Controller = VirtualNvic;
+
+/// Run `handler` whenever the interrupt is taken
+pub fn set_handler<I: Interrupt>(int: I, handler: fn()) {
+    HANDLERS[index(int.number() as i16)].store(handler as usize, Ordering::SeqCst);
+}
+
+/// Call `trace` whenever a handler is entered or exits
+pub fn set_trace(trace: fn(Event)) {
+    TRACE.store(trace as usize, Ordering::SeqCst);
+}
+
+/// Report an event to the `set_trace` function, if any
+fn trace(event: Event) {
+    let trace = TRACE.load(Ordering::SeqCst);
+    if trace != 0 {
+        // NOTE(unsafe) only ever set from a `fn(Event)`
+        let trace: fn(Event) = unsafe { core::mem::transmute(trace) };
+        trace(event);
+    }
+}
+
+/// Pend the interrupt. Its handler runs before this returns if it is
+/// enabled, unmasked, and more urgent than the code currently running.
+pub fn pend<I: Interrupt>(int: I) {
+    set_pending(int.number() as i16, true);
+}
+
+/// The priority of the code currently running, where 256 is the
+/// application, and lower values are more urgent
+fn running_priority() -> u16 {
+    match CURRENT.load(Ordering::SeqCst) {
+        0 => 256,
+        current => u16::from(PRIORITIES[usize::from(current - 1)].load(Ordering::SeqCst)),
+    }
+}
+
+/// The most urgent interrupt which should preempt the code currently
+/// running, if any
+fn next() -> Option<i16> {
+    if MASKED.load(Ordering::SeqCst) {
+        return None;
+    }
+
+    let running = running_priority();
+    (0..INTERRUPTS as i16)
+        .filter(|&irqn| PENDING.get(irqn) && ENABLED.get(irqn) && !ACTIVE.get(irqn))
+        .map(|irqn| (PRIORITIES[irqn as usize].load(Ordering::SeqCst), irqn))
+        .filter(|&(priority, _)| u16::from(priority) < running)
+        .min()
+        .map(|(_, irqn)| irqn)
+}
+
+/// Run the handlers of every interrupt that should preempt the code
+/// currently running, most urgent first
+fn dispatch() {
+    while let Some(irqn) = next() {
+        PENDING.set(irqn, false);
+        ACTIVE.set(irqn, true);
+        let preempted = CURRENT.swap(irqn as u8 + 1, Ordering::SeqCst);
+        trace(Event::Enter(irqn as u16));
+
+        let handler = HANDLERS[irqn as usize].load(Ordering::SeqCst);
+        if handler != 0 {
+            // NOTE(unsafe) only ever set from a `fn()`
+            let handler: fn() = unsafe { core::mem::transmute(handler) };
+            handler();
+        }
+
+        trace(Event::Exit(irqn as u16));
+        CURRENT.store(preempted, Ordering::SeqCst);
+        ACTIVE.set(irqn, false);
+    }
+}
+
+/// The interrupt currently running, if any
+pub fn active() -> Active {
+    match CURRENT.load(Ordering::SeqCst) {
+        0 => Active::Thread,
+        current => Active::Interrupt(i16::from(current) - 1),
+    }
+}
+
+/// Is the interrupt enabled?
+pub fn is_enabled(irqn: i16) -> bool {
+    ENABLED.get(irqn)
+}
+
+/// Is the interrupt active (running, or preempted)?
+pub fn is_active(irqn: i16) -> bool {
+    ACTIVE.get(irqn)
+}
+
+/// Is the interrupt pending?
+pub fn is_pending(irqn: i16) -> bool {
+    PENDING.get(irqn)
+}
+
+/// Set, or clear, the pending state of the interrupt
+pub fn set_pending(irqn: i16, pending: bool) {
+    PENDING.set(irqn, pending);
+    dispatch();
+}
+
+/// Every virtual interrupt can be disabled on its own
+pub fn can_disable(_irqn: i16) -> bool {
+    true
+}
+
+/// Disable the interrupt
+pub fn disable(irqn: i16) {
+    ENABLED.set(irqn, false);
+}
+
+/// Enable the interrupt, running its handler if it is pending
+pub fn enable(irqn: i16) {
+    ENABLED.set(irqn, true);
+    dispatch();
+}
+
+/// Set the priority of the interrupt, where lower values are more urgent
+pub unsafe fn set_priority(irqn: i16, prio: u8) {
+    PRIORITIES[index(irqn)].store(prio, Ordering::SeqCst);
+}
+
+/// Whether interrupts were unmasked before `mask_all`
+pub type Restore = bool;
+
+/// Mask all interrupts, returning whether they were unmasked beforehand
+pub fn mask_all() -> Restore {
+    !MASKED.swap(true, Ordering::SeqCst)
+}
+
+/// Undo `mask_all`, running the handlers of interrupts pended meanwhile
+pub unsafe fn restore_all(unmasked: Restore) {
+    if unmasked {
+        MASKED.store(false, Ordering::SeqCst);
+        dispatch();
+    }
+}
+
+/// Execute the closure with all interrupts masked
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    let unmasked = mask_all();
+
+    // NOTE(unsafe) interrupts are masked until the closure returns
+    let r = f(unsafe { &CriticalSection::new() });
+
+    unsafe { restore_all(unmasked) };
+    r
+}
@@ -0,0 +1,151 @@
+//! The Xtensa implementation of the interrupt primitives used by
+//! `nvic::IrqSet`, based on the `INTENABLE`, `INTERRUPT`, `INTSET` and
+//! `INTCLEAR` special registers, as used by the ESP32
+//!
+//! Interrupts are identified by their CPU interrupt number. Peripheral
+//! interrupts must be routed to a CPU interrupt through the interrupt matrix
+//! of the device, and handlers must `enter` their interrupt for interrupt
+//! context access to be granted, as the core does not report which
+//! interrupt is running.
+
+use crate::CriticalSection;
+
+#[path = "running.rs"]
+mod running;
+
+pub use self::running::*;
+
+/// The number of CPU interrupts
+const CPU_INTERRUPTS: i16 = 32;
+
+/// Exclusive access to the CPU interrupts
+pub struct CpuInterrupts {
+    _private: (),
+}
+
+impl CpuInterrupts {
+    /// Take the CPU interrupts
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may be enabling interrupts while the `CpuInterrupts` are
+    /// held.
+    pub unsafe fn steal() -> Self {
+        CpuInterrupts { _private: () }
+    }
+}
+
+/// The interrupt controller, which must be held to enable interrupts with
+/// `set_initial_nvic`
+pub type Controller = CpuInterrupts;
+
+/// The bit for the CPU interrupt in the interrupt special registers
+fn bit(irqn: i16) -> u32 {
+    assert!(irqn < CPU_INTERRUPTS);
+    1 << irqn
+}
+
+/// Read `INTENABLE`
+fn intenable() -> u32 {
+    let bits: u32;
+    // NOTE(unsafe) reading a special register has no side effects
+    unsafe { core::arch::asm!("rsr.intenable {0}", out(reg) bits) };
+    bits
+}
+
+/// Write `INTENABLE`
+unsafe fn set_intenable(bits: u32) {
+    core::arch::asm!("wsr.intenable {0}", "rsync", in(reg) bits);
+}
+
+/// Read `INTERRUPT`, which holds the pending bits
+fn interrupt() -> u32 {
+    let bits: u32;
+    // NOTE(unsafe) reading a special register has no side effects
+    unsafe { core::arch::asm!("rsr.interrupt {0}", out(reg) bits) };
+    bits
+}
+
+/// Set the pending bits of software and edge triggered interrupts
+unsafe fn intset(bits: u32) {
+    core::arch::asm!("wsr.intset {0}", "rsync", in(reg) bits);
+}
+
+/// Clear the pending bits of software and edge triggered interrupts
+unsafe fn intclear(bits: u32) {
+    core::arch::asm!("wsr.intclear {0}", "rsync", in(reg) bits);
+}
+
+/// Is the CPU interrupt enabled?
+pub fn is_enabled(irqn: i16) -> bool {
+    intenable() & bit(irqn) != 0
+}
+
+/// Is the CPU interrupt pending?
+pub fn is_pending(irqn: i16) -> bool {
+    interrupt() & bit(irqn) != 0
+}
+
+/// Set, or clear, the pending state of the CPU interrupt. This only has an
+/// effect on software and edge triggered interrupts.
+pub fn set_pending(irqn: i16, pending: bool) {
+    // NOTE(unsafe) INTSET and INTCLEAR only affect the written bits
+    unsafe {
+        if pending {
+            intset(bit(irqn));
+        } else {
+            intclear(bit(irqn));
+        }
+    }
+}
+
+/// Every CPU interrupt can be disabled on its own
+pub fn can_disable(_irqn: i16) -> bool {
+    true
+}
+
+/// Disable the CPU interrupt. `INTENABLE` is shared by all interrupts, so
+/// this is a read-modify-write with all interrupts masked.
+pub fn disable(irqn: i16) {
+    free(|_| unsafe { set_intenable(intenable() & !bit(irqn)) })
+}
+
+/// Enable the CPU interrupt
+pub fn enable(irqn: i16) {
+    free(|_| unsafe { set_intenable(intenable() | bit(irqn)) })
+}
+
+/// Xtensa interrupt levels are fixed for each CPU interrupt, so this has no
+/// effect
+pub unsafe fn set_priority(_irqn: i16, _prio: u8) {}
+
+/// The value of `PS` before `mask_all`
+pub type Restore = u32;
+
+/// Mask all interrupts by raising `PS.INTLEVEL` to 15, returning the
+/// previous value of `PS`
+pub fn mask_all() -> Restore {
+    let ps: u32;
+    // NOTE(unsafe) raising the interrupt level has no other side effects
+    unsafe { core::arch::asm!("rsil {0}, 15", out(reg) ps) };
+    ps
+}
+
+/// Restore `PS` to its value before `mask_all`
+pub unsafe fn restore_all(ps: Restore) {
+    core::arch::asm!("wsr.ps {0}", "rsync", in(reg) ps);
+}
+
+/// Execute the closure with all interrupts masked
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    let ps = mask_all();
+
+    // NOTE(unsafe) interrupts are masked until the closure returns
+    let r = f(unsafe { &CriticalSection::new() });
+
+    unsafe { restore_all(ps) };
+    r
+}
@@ -0,0 +1,155 @@
+//! A byte arena for variable length shared data
+
+use core::mem::{align_of, size_of};
+use core::slice;
+
+/// Types that can be viewed in place inside an `Arena`.
+///
+/// # Safety
+///
+/// Every bit pattern must be a valid value of the type, and the type must
+/// not contain any padding bytes.
+pub unsafe trait FromBytes: Sized {}
+
+unsafe impl FromBytes for u8 {}
+unsafe impl FromBytes for i8 {}
+unsafe impl FromBytes for u16 {}
+unsafe impl FromBytes for i16 {}
+unsafe impl FromBytes for u32 {}
+unsafe impl FromBytes for i32 {}
+unsafe impl FromBytes for u64 {}
+unsafe impl FromBytes for i64 {}
+unsafe impl FromBytes for usize {}
+unsafe impl FromBytes for isize {}
+unsafe impl<T: FromBytes, const M: usize> FromBytes for [T; M] {}
+
+/// A fixed capacity byte buffer, with a length chosen at runtime.
+///
+/// This can be used as the data type of a `shared` item when the layout
+/// of the data is not known until runtime, such as variable length
+/// protocol frames. Typed views into the buffer are checked for bounds and
+/// alignment when they are created:
+///
+/// ```rust,ignore
+/// shared!(
+///     (FRAME, shared::Arena<256>, Interrupt::UARTE0_UARTE0, shared::Arena::new()),
+/// );
+///
+/// token.modify_app_context(|frame| {
+///     frame.set_len(8);
+///     *frame.view_mut::<u32>(4).unwrap() = 0xC0FF_EE00;
+/// }).unwrap();
+/// ```
+#[repr(C, align(8))]
+pub struct Arena<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Arena<N> {
+    /// Create a new, empty, zeroed arena
+    pub const fn new() -> Self {
+        Arena {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    /// The maximum number of bytes the arena can hold
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// The number of bytes currently in use
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no bytes are currently in use
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Set the number of bytes currently in use
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is larger than the capacity of the arena.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= N);
+        self.len = len;
+    }
+
+    /// The bytes currently in use
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// The bytes currently in use, mutably
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[..self.len]
+    }
+
+    /// View the bytes at `offset` as a `T`. Returns `None` if the value
+    /// would extend past the bytes in use, or would not be aligned.
+    pub fn view<T: FromBytes>(&self, offset: usize) -> Option<&T> {
+        self.view_slice(offset, 1).map(|s| &s[0])
+    }
+
+    /// View the bytes at `offset` as a mutable `T`. Returns `None` if the
+    /// value would extend past the bytes in use, or would not be aligned.
+    pub fn view_mut<T: FromBytes>(&mut self, offset: usize) -> Option<&mut T> {
+        self.view_slice_mut(offset, 1).map(|s| &mut s[0])
+    }
+
+    /// View the bytes at `offset` as a slice of `count` values of `T`.
+    /// Returns `None` if the slice would extend past the bytes in use, or
+    /// would not be aligned.
+    pub fn view_slice<T: FromBytes>(&self, offset: usize, count: usize) -> Option<&[T]> {
+        if !self.fits::<T>(offset, count) {
+            return None;
+        }
+        let ptr = self.buf[offset..].as_ptr() as *const T;
+
+        // NOTE(unsafe) in bounds and aligned, and any bytes are a valid `T`
+        Some(unsafe { slice::from_raw_parts(ptr, count) })
+    }
+
+    /// View the bytes at `offset` as a mutable slice of `count` values of
+    /// `T`. Returns `None` if the slice would extend past the bytes in use,
+    /// or would not be aligned.
+    pub fn view_slice_mut<T: FromBytes>(
+        &mut self,
+        offset: usize,
+        count: usize,
+    ) -> Option<&mut [T]> {
+        if !self.fits::<T>(offset, count) {
+            return None;
+        }
+        let ptr = self.buf[offset..].as_mut_ptr() as *mut T;
+
+        // NOTE(unsafe) in bounds and aligned, and any bytes are a valid `T`.
+        // `T` has no padding, so writes can't de-initialize any bytes
+        Some(unsafe { slice::from_raw_parts_mut(ptr, count) })
+    }
+
+    /// Checks the bounds and alignment of a view
+    fn fits<T: FromBytes>(&self, offset: usize, count: usize) -> bool {
+        let end = size_of::<T>()
+            .checked_mul(count)
+            .and_then(|size| size.checked_add(offset));
+
+        match end {
+            Some(end) if end <= self.len => {
+                (self.buf.as_ptr() as usize + offset) & (align_of::<T>() - 1) == 0
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<const N: usize> Default for Arena<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,267 @@
+//! Buffers which let the application and an interrupt work on separate
+//! copies of the data at the same time
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::lend::LockFree;
+use crate::{Error, SharedItem};
+
+/// Two halves, one filled by the interrupt while the application processes
+/// the other, whose roles are swapped with `PingPong::flip`.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `PingPong` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     (SAMPLES, shared::DoubleBuffer<[u16; 64]>, Interrupt::SAADC,
+///         shared::DoubleBuffer::new([0; 64], [0; 64])),
+/// );
+///
+/// #[interrupt]
+/// fn SAADC() {
+///     SAMPLES::modify_int_half_int_context(|half| fill(half)).unwrap();
+/// }
+///
+/// token.flip().unwrap();
+/// token.modify_app_half(|half| process(half)).unwrap();
+/// ```
+///
+/// Neither side masks the interrupt to reach its own half, only `flip` does.
+pub struct DoubleBuffer<T> {
+    halves: [UnsafeCell<T>; 2],
+    int_half: AtomicUsize,
+}
+
+impl<T> DoubleBuffer<T> {
+    /// Create a new double buffer, where the interrupt starts out with
+    /// `first`, and the application with `second`
+    pub const fn new(first: T, second: T) -> Self {
+        DoubleBuffer {
+            halves: [UnsafeCell::new(first), UnsafeCell::new(second)],
+            int_half: AtomicUsize::new(0),
+        }
+    }
+
+    /// A pointer to the half currently owned by the interrupt, or by the
+    /// application
+    fn half(&self, int: bool) -> *mut T {
+        let int_half = self.int_half.load(Ordering::SeqCst);
+        let index = if int { int_half } else { int_half ^ 1 };
+        self.halves[index].get()
+    }
+}
+
+impl<T> LockFree for DoubleBuffer<T> {}
+
+/// Filling and processing the halves of a shared `DoubleBuffer`
+pub trait PingPong<T>: SharedItem<Data = DoubleBuffer<T>> {
+    /// Swap the halves of the application and the interrupt, with the
+    /// interrupt disabled, so that the application gets the half that was
+    /// just filled
+    fn flip(&mut self) -> Result<(), Error>;
+
+    /// Access the half owned by the application, without masking the
+    /// interrupt
+    fn modify_app_half<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R;
+
+    /// Access the half owned by the interrupt, from the corresponding
+    /// interrupt
+    fn modify_int_half_int_context<F, R>(f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R;
+}
+
+impl<S, T> PingPong<T> for S
+where
+    S: SharedItem<Data = DoubleBuffer<T>>,
+{
+    fn flip(&mut self) -> Result<(), Error> {
+        // The interrupt is masked while the halves change owner, so a load
+        // and a store suffice, which unlike `fetch_xor` are available on
+        // every target
+        self.inspect_app_context(|buffer| {
+            let int_half = buffer.int_half.load(Ordering::SeqCst);
+            buffer.int_half.store(int_half ^ 1, Ordering::SeqCst);
+        })
+    }
+
+    fn modify_app_half<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        if !S::is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+
+        // NOTE(unsafe) the halves only change owner in `flip`, which needs
+        // the token borrowed here, and the interrupt never touches the half
+        // of the application
+        let buffer = unsafe { &*S::storage() };
+        Ok(f(unsafe { &mut *buffer.half(false) }))
+    }
+
+    fn modify_int_half_int_context<F, R>(f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        // NOTE(unsafe) the application never touches the half of the
+        // interrupt
+        S::inspect_int_context(|buffer| f(unsafe { &mut *buffer.half(true) }))
+    }
+}
+
+/// Set in `TripleBuffer::middle` when the middle slot holds a value the
+/// application has not taken yet
+const FRESH: usize = 0b100;
+
+/// Three slots, so that the interrupt always has one to publish the next
+/// value into, while the application keeps reading the most recent one.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `Latest` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     (ATTITUDE, shared::TripleBuffer<Quaternion>, Interrupt::SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0,
+///         shared::TripleBuffer::new(Quaternion::IDENTITY)),
+/// );
+///
+/// #[interrupt]
+/// fn SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0() {
+///     ATTITUDE::publish_int_context(read_imu()).unwrap();
+/// }
+///
+/// loop {
+///     let attitude = token.latest().unwrap();
+///     update_motors(attitude);
+/// }
+/// ```
+///
+/// Values the application never got to read are overwritten. The interrupt
+/// is only disabled for the few instructions taking a newer value in
+/// `latest`, never while either side works on its slot.
+pub struct TripleBuffer<T> {
+    slots: [UnsafeCell<T>; 3],
+    /// The slot the interrupt publishes into, only used by the interrupt
+    write: AtomicUsize,
+    /// The slot passed between both sides, and the `FRESH` flag
+    middle: AtomicUsize,
+    /// The slot the application reads, only used by the application
+    read: AtomicUsize,
+}
+
+impl<T: Copy> TripleBuffer<T> {
+    /// Create a new triple buffer, where the application reads `initial`
+    /// until the interrupt publishes a value
+    pub const fn new(initial: T) -> Self {
+        TripleBuffer::from_slots(initial, initial, initial)
+    }
+}
+
+impl<T> TripleBuffer<T> {
+    /// Create a new triple buffer from three values, for types which are
+    /// not `Copy`. The application reads `read` until the interrupt
+    /// publishes a value.
+    pub const fn from_slots(write: T, middle: T, read: T) -> Self {
+        TripleBuffer {
+            slots: [
+                UnsafeCell::new(write),
+                UnsafeCell::new(middle),
+                UnsafeCell::new(read),
+            ],
+            write: AtomicUsize::new(0),
+            middle: AtomicUsize::new(1),
+            read: AtomicUsize::new(2),
+        }
+    }
+
+    /// Hand the slot the interrupt just wrote to over to the application
+    fn publish(&self) {
+        let write = self.write.load(Ordering::SeqCst);
+        let middle = self.middle.load(Ordering::SeqCst);
+        self.middle.store(write | FRESH, Ordering::SeqCst);
+        self.write.store(middle & !FRESH, Ordering::SeqCst);
+    }
+
+    /// Take the most recently published slot for the application, if it
+    /// has not already
+    fn take_fresh(&self) {
+        let middle = self.middle.load(Ordering::SeqCst);
+        if middle & FRESH != 0 {
+            let read = self.read.load(Ordering::SeqCst);
+            self.middle.store(read, Ordering::SeqCst);
+            self.read.store(middle & !FRESH, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T> LockFree for TripleBuffer<T> {}
+
+/// Publishing and reading the newest value of a shared `TripleBuffer`
+pub trait Latest<T>: SharedItem<Data = TripleBuffer<T>> {
+    /// The most recently published value, read from application context.
+    /// The interrupt is only disabled while a newer value is taken.
+    fn latest(&mut self) -> Result<&T, Error>;
+
+    /// Whether a value was published since the last call to `latest`,
+    /// checked from application context without masking the interrupt
+    fn has_new(&mut self) -> Result<bool, Error>;
+
+    /// Publish a value from the corresponding interrupt, replacing any
+    /// value the application has not read yet
+    fn publish_int_context(value: T) -> Result<(), Error>;
+
+    /// Fill in the free slot in place, then publish it, from the
+    /// corresponding interrupt. The slot holds a stale value beforehand.
+    fn publish_with_int_context<F, R>(f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R;
+}
+
+impl<S, T> Latest<T> for S
+where
+    S: SharedItem<Data = TripleBuffer<T>>,
+{
+    fn latest(&mut self) -> Result<&T, Error> {
+        self.inspect_app_context(|buffer| buffer.take_fresh())?;
+
+        // NOTE(unsafe) the slot of the application only changes in
+        // `take_fresh`, which needs the token borrowed here, and the
+        // interrupt never touches it
+        let buffer = unsafe { &*S::storage() };
+        Ok(unsafe { &*buffer.slots[buffer.read.load(Ordering::SeqCst)].get() })
+    }
+
+    fn has_new(&mut self) -> Result<bool, Error> {
+        if !S::is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+
+        // NOTE(unsafe) the flag is read with a single load
+        let buffer = unsafe { &*S::storage() };
+        Ok(buffer.middle.load(Ordering::SeqCst) & FRESH != 0)
+    }
+
+    fn publish_int_context(value: T) -> Result<(), Error> {
+        Self::publish_with_int_context(|slot| *slot = value)
+    }
+
+    fn publish_with_int_context<F, R>(f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        S::inspect_int_context(|buffer| {
+            let write = buffer.write.load(Ordering::SeqCst);
+
+            // NOTE(unsafe) the application never touches the slot of the
+            // interrupt
+            let r = f(unsafe { &mut *buffer.slots[write].get() });
+            buffer.publish();
+            r
+        })
+    }
+}
@@ -0,0 +1,166 @@
+//! Pushing to and popping from shared `heapless` collections, without a
+//! closure for every element
+
+use heapless::{Deque, String, Vec};
+
+use crate::{Error, Pop, SharedItem};
+
+/// Filling and emptying a shared `heapless::Vec` from either context.
+///
+/// This needs the `heapless` feature:
+///
+/// ```rust,ignore
+/// shared!(
+///     (SAMPLES, heapless::Vec<u16, 32>, Interrupt::SAADC, heapless::Vec::new()),
+/// );
+///
+/// #[interrupt]
+/// fn SAADC() {
+///     SAMPLES::push_int_context(read_sample()).unwrap();
+/// }
+///
+/// while let Some(sample) = token.pop().unwrap() {
+///     process(sample);
+/// }
+/// ```
+///
+/// Each call masks the interrupt as `modify_app_context` does. An element
+/// pushed to a full vector is dropped, and `Error::Full` is returned.
+pub trait SharedVec<T, const N: usize>: SharedItem<Data = Vec<T, N>> {
+    /// Append an element from application context
+    fn push(&mut self, elem: T) -> Result<(), Error>;
+
+    /// Remove the last element from application context, if there is one
+    fn pop(&mut self) -> Result<Option<T>, Error>;
+
+    /// Append an element from the corresponding interrupt
+    fn push_int_context(elem: T) -> Result<(), Error>;
+
+    /// Remove the last element from the corresponding interrupt, if there
+    /// is one
+    fn pop_int_context() -> Result<Option<T>, Error>;
+}
+
+impl<S, T, const N: usize> SharedVec<T, N> for S
+where
+    S: SharedItem<Data = Vec<T, N>>,
+{
+    fn push(&mut self, elem: T) -> Result<(), Error> {
+        self.access_app_context(|vec| vec.push(elem))?
+            .map_err(|_| Error::Full)
+    }
+
+    fn pop(&mut self) -> Result<Option<T>, Error> {
+        self.access_app_context(Vec::pop)
+    }
+
+    fn push_int_context(elem: T) -> Result<(), Error> {
+        S::access_int_context(|vec| vec.push(elem))?.map_err(|_| Error::Full)
+    }
+
+    fn pop_int_context() -> Result<Option<T>, Error> {
+        S::access_int_context(Vec::pop)
+    }
+}
+
+/// Queueing elements through a shared `heapless::Deque`, in either
+/// direction.
+///
+/// This needs the `heapless` feature. Elements are pushed to the back and
+/// popped from the front, so they come out in the order they went in. An
+/// element pushed to a full deque is dropped, and `Error::Full` is
+/// returned.
+pub trait SharedDeque<T, const N: usize>: SharedItem<Data = Deque<T, N>> {
+    /// Append an element to the back from application context
+    fn push_back(&mut self, elem: T) -> Result<(), Error>;
+
+    /// Remove the front element from application context, if there is one
+    fn pop_front(&mut self) -> Result<Option<T>, Error>;
+
+    /// Append an element to the back from the corresponding interrupt
+    fn push_back_int_context(elem: T) -> Result<(), Error>;
+
+    /// Remove the front element from the corresponding interrupt, if there
+    /// is one
+    fn pop_front_int_context() -> Result<Option<T>, Error>;
+}
+
+impl<S, T, const N: usize> SharedDeque<T, N> for S
+where
+    S: SharedItem<Data = Deque<T, N>>,
+{
+    fn push_back(&mut self, elem: T) -> Result<(), Error> {
+        self.access_app_context(|deque| deque.push_back(elem))?
+            .map_err(|_| Error::Full)
+    }
+
+    fn pop_front(&mut self) -> Result<Option<T>, Error> {
+        self.access_app_context(Deque::pop_front)
+    }
+
+    fn push_back_int_context(elem: T) -> Result<(), Error> {
+        S::access_int_context(|deque| deque.push_back(elem))?.map_err(|_| Error::Full)
+    }
+
+    fn pop_front_int_context() -> Result<Option<T>, Error> {
+        S::access_int_context(Deque::pop_front)
+    }
+}
+
+/// Appending text to a shared `heapless::String` from either context, such
+/// as a line being received.
+///
+/// This needs the `heapless` feature. Text which does not fit is not
+/// appended at all, and `Error::Full` is returned.
+pub trait SharedString<const N: usize>: SharedItem<Data = String<N>> {
+    /// Append `s` from application context
+    fn push_str(&mut self, s: &str) -> Result<(), Error>;
+
+    /// Remove the last character from application context, if there is one
+    fn pop_char(&mut self) -> Result<Option<char>, Error>;
+
+    /// Append `s` from the corresponding interrupt
+    fn push_str_int_context(s: &str) -> Result<(), Error>;
+
+    /// Remove the last character from the corresponding interrupt, if there
+    /// is one
+    fn pop_char_int_context() -> Result<Option<char>, Error>;
+}
+
+impl<S, const N: usize> SharedString<N> for S
+where
+    S: SharedItem<Data = String<N>>,
+{
+    fn push_str(&mut self, s: &str) -> Result<(), Error> {
+        self.access_app_context(|string| string.push_str(s))?
+            .map_err(|()| Error::Full)
+    }
+
+    fn pop_char(&mut self) -> Result<Option<char>, Error> {
+        self.access_app_context(String::pop)
+    }
+
+    fn push_str_int_context(s: &str) -> Result<(), Error> {
+        S::access_int_context(|string| string.push_str(s))?.map_err(|()| Error::Full)
+    }
+
+    fn pop_char_int_context() -> Result<Option<char>, Error> {
+        S::access_int_context(String::pop)
+    }
+}
+
+impl<T, const N: usize> Pop for Vec<T, N> {
+    type Item = T;
+
+    fn pop(&mut self) -> Option<T> {
+        Vec::pop(self)
+    }
+}
+
+impl<T, const N: usize> Pop for Deque<T, N> {
+    type Item = T;
+
+    fn pop(&mut self) -> Option<T> {
+        self.pop_front()
+    }
+}
@@ -0,0 +1,61 @@
+//! Interrupts routed through an interrupt controller other than the one of
+//! the core, such as a cascaded controller or a GPIO expander
+
+/// An interrupt controller outside of the core, whose interrupt lines may
+/// guard `shared` items given the `controller = <expr>` option.
+///
+/// The lines are usually serviced by a demultiplexing handler, which runs
+/// the handler of each pending line, and reports that line as active while
+/// doing so:
+///
+/// ```rust,ignore
+/// static EXPANDER: Expander = Expander::new();
+///
+/// shared!(
+///     (BUTTONS, u8, shared::Line(5), 0, controller = &EXPANDER),
+/// );
+///
+/// #[interrupt]
+/// fn GPIOTE() {
+///     EXPANDER.dispatch(|line| match line {
+///         5 => BUTTONS::modify_int_context(|b| *b += 1).unwrap(),
+///         _ => {}
+///     });
+/// }
+/// ```
+pub trait IntController: Sync {
+    /// Enable the line
+    fn enable(&self, line: u16);
+
+    /// Disable the line, so that its handler is not run until it is
+    /// enabled again
+    fn disable(&self, line: u16);
+
+    /// Is the line enabled?
+    fn is_enabled(&self, line: u16) -> bool;
+
+    /// Is the handler of the line running? This is used to grant interrupt
+    /// context access, so it must only be `true` while the handler runs,
+    /// and not while it is preempted by another interrupt.
+    fn is_active(&self, line: u16) -> bool;
+
+    /// Is the line pending? Not every controller reports this, so this is
+    /// `false` by default.
+    fn is_pending(&self, _line: u16) -> bool {
+        false
+    }
+
+    /// Set, or clear, the pending state of the line. Not every controller
+    /// supports this, so this panics by default.
+    fn set_pending(&self, _line: u16, _pending: bool) {
+        panic!("this interrupt controller can not pend lines in software");
+    }
+}
+
+/// An interrupt line of an `IntController`, by number, which may be used as
+/// the interrupt of a `shared` item given the `controller = <expr>` option.
+///
+/// Lines are not interrupts of the core, so they may only guard items given
+/// a controller, and items given a controller may only be guarded by lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Line(pub u16);
@@ -0,0 +1,398 @@
+//! Buffers handed back and forth between the application, a DMA engine,
+//! and the interrupt signalling the end of a transfer, singly or as a ring
+//! of descriptors
+
+use core::sync::atomic::{fence, AtomicU8, AtomicUsize, Ordering};
+
+use crate::lend::{Lend, LockFree};
+use crate::{Error, SharedItem};
+
+/// The buffer is owned by the application, and no transfer has been made
+const IDLE: u8 = 0;
+
+/// The buffer is owned by the DMA engine
+const IN_FLIGHT: u8 = 1;
+
+/// The transfer has completed, and the buffer is owned by the application
+/// again
+const COMPLETE: u8 = 2;
+
+/// A buffer used for DMA transfers, which tracks who currently owns it.
+///
+/// This is used as the data type of a `shared` item guarded by the
+/// interrupt signalling the end of a transfer, and is accessed through the
+/// `Dma` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     (RX_BUF, shared::DmaBuffer<[u8; 64]>, Interrupt::UARTE0_UARTE0, shared::DmaBuffer::new([0; 64])),
+/// );
+///
+/// let mut token = RX_BUF::take_token().unwrap();
+/// token.start_transfer(|buf| {
+///     uarte.rxd.ptr.write(|w| unsafe { w.ptr().bits(buf as u32) });
+///     uarte.tasks_startrx.write(|w| unsafe { w.bits(1) });
+/// }).unwrap();
+///
+/// #[interrupt]
+/// fn UARTE0_UARTE0() {
+///     RX_BUF::on_complete(|buf| process(buf)).unwrap();
+/// }
+/// ```
+///
+/// While a transfer is in flight, the buffer can not be accessed by the
+/// application or the interrupt. Accessing the shared item from
+/// application context, or with `modify_int_context`, returns `Error::Lent`,
+/// and tearing it down panics, as the DMA engine may still be writing to the
+/// buffer.
+pub struct DmaBuffer<B> {
+    buf: B,
+    state: AtomicU8,
+}
+
+impl<B> Lend for DmaBuffer<B> {
+    fn is_lent(&self) -> bool {
+        self.is_in_flight()
+    }
+}
+
+impl<B> DmaBuffer<B> {
+    /// Create a new buffer, owned by the application
+    pub const fn new(buf: B) -> Self {
+        DmaBuffer {
+            buf,
+            state: AtomicU8::new(IDLE),
+        }
+    }
+
+    /// Is a transfer currently in flight?
+    pub fn is_in_flight(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == IN_FLIGHT
+    }
+
+    /// The buffer, unless a transfer is in flight
+    pub fn buffer(&self) -> Option<&B> {
+        if self.is_in_flight() {
+            None
+        } else {
+            Some(&self.buf)
+        }
+    }
+
+    /// The buffer, mutably, unless a transfer is in flight
+    pub fn buffer_mut(&mut self) -> Option<&mut B> {
+        if self.is_in_flight() {
+            None
+        } else {
+            Some(&mut self.buf)
+        }
+    }
+}
+
+/// Handing the buffer of a shared `DmaBuffer` to and from a DMA engine
+pub trait Dma<B>: SharedItem<Data = DmaBuffer<B>> {
+    /// Hand the buffer to the DMA engine from application context. `start`
+    /// is called with a pointer to the buffer, and should start the
+    /// transfer.
+    ///
+    /// Returns `Error::WouldBlock` if a transfer is already in flight.
+    fn start_transfer<F>(&mut self, start: F) -> Result<(), Error>
+    where
+        F: FnOnce(*mut B);
+
+    /// Has the last transfer completed? This does not touch the NVIC, and
+    /// may be called from any context.
+    fn is_complete(&self) -> bool;
+
+    /// Mark the transfer as complete from within the corresponding
+    /// interrupt, and call `f` with the buffer.
+    ///
+    /// Returns `Ok(None)` if no transfer was in flight.
+    fn on_complete<F, R>(f: F) -> Result<Option<R>, Error>
+    where
+        F: FnOnce(&mut B) -> R;
+}
+
+impl<S, B> Dma<B> for S
+where
+    S: SharedItem<Data = DmaBuffer<B>>,
+{
+    fn start_transfer<F>(&mut self, start: F) -> Result<(), Error>
+    where
+        F: FnOnce(*mut B),
+    {
+        self.access_app_context(|dma| {
+            dma.state.store(IN_FLIGHT, Ordering::SeqCst);
+
+            // Make sure all writes to the buffer happen before the transfer
+            // is started
+            fence(Ordering::SeqCst);
+            start(&mut dma.buf);
+        })
+        .map_err(|err| match err {
+            Error::Lent => Error::WouldBlock,
+            err => err,
+        })
+    }
+
+    fn is_complete(&self) -> bool {
+        // NOTE(unsafe) only the atomic state is read, once the data is
+        // initialized
+        S::is_initialized() && unsafe { (*S::storage()).state.load(Ordering::SeqCst) == COMPLETE }
+    }
+
+    fn on_complete<F, R>(f: F) -> Result<Option<R>, Error>
+    where
+        F: FnOnce(&mut B) -> R,
+    {
+        // NOTE(unsafe) the buffer is only touched once the transfer is over
+        unsafe {
+            S::reclaim_int_context(|dma| {
+                if !dma.is_in_flight() {
+                    return None;
+                }
+                dma.state.store(COMPLETE, Ordering::SeqCst);
+
+                // Make sure no reads of the buffer happen before the transfer
+                // is complete
+                fence(Ordering::SeqCst);
+                Some(f(&mut dma.buf))
+            })
+        }
+    }
+}
+
+/// The descriptor holds no buffer, and is owned by the application
+const EMPTY: u8 = 0;
+
+/// The descriptor is owned by the DMA side, waiting for, or in, a transfer
+const OWNED_BY_DMA: u8 = 1;
+
+/// The transfer of the descriptor is complete, and it is owned by the
+/// application again
+const DONE: u8 = 2;
+
+/// An entry of a `DescriptorRing`: a buffer, and who owns it
+struct Descriptor {
+    addr: AtomicUsize,
+    len: AtomicUsize,
+    transferred: AtomicUsize,
+    owner: AtomicU8,
+}
+
+impl Descriptor {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Descriptor = Descriptor {
+        addr: AtomicUsize::new(0),
+        len: AtomicUsize::new(0),
+        transferred: AtomicUsize::new(0),
+        owner: AtomicU8::new(EMPTY),
+    };
+}
+
+/// A ring of `N` buffer descriptors, for scatter-gather transfers, walked
+/// by the interrupt driving the DMA engine while the application refills
+/// the completed ones.
+///
+/// This is used as the data type of a `shared` item guarded by that
+/// interrupt, and is accessed through the `Descriptors` trait. Every
+/// descriptor is owned by either side, and only its owner touches it:
+///
+/// ```rust,ignore
+/// shared!(
+///     (RX_RING, shared::DescriptorRing<4>, Interrupt::UARTE0_UARTE0, shared::DescriptorRing::new()),
+/// );
+///
+/// for buf in buffers {
+///     token.submit(buf).unwrap().unwrap();
+/// }
+///
+/// #[interrupt]
+/// fn UARTE0_UARTE0() {
+///     RX_RING::complete_int_context(received_len()).unwrap();
+///     if let Some((ptr, len)) = RX_RING::current_int_context().unwrap() {
+///         start_receive(ptr, len);
+///     }
+/// }
+///
+/// while let Some((buf, len)) = token.reclaim().unwrap() {
+///     process(&buf[..len]);
+///     token.submit(buf).unwrap().unwrap();
+/// }
+/// ```
+///
+/// The application hands descriptors over in order, and the interrupt
+/// completes them in the same order, so neither side masks the interrupt.
+/// Each change of owner is fenced, so that the buffer is written before
+/// the other side can see it.
+pub struct DescriptorRing<const N: usize> {
+    descriptors: [Descriptor; N],
+    /// The next descriptor to submit, only used by the application
+    submit: AtomicUsize,
+    /// The next descriptor to reclaim, only used by the application
+    reclaim: AtomicUsize,
+    /// The descriptor being transferred, only used by the interrupt
+    current: AtomicUsize,
+}
+
+impl<const N: usize> DescriptorRing<N> {
+    /// Rejects rings without any descriptor, whose indices could not be
+    /// reduced to one
+    const NOT_EMPTY: () = assert!(N > 0, "a descriptor ring must hold at least one descriptor");
+
+    /// Create a new ring, with every descriptor empty and owned by the
+    /// application
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::NOT_EMPTY;
+        DescriptorRing {
+            descriptors: [Descriptor::EMPTY; N],
+            submit: AtomicUsize::new(0),
+            reclaim: AtomicUsize::new(0),
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    /// The descriptor at the index stored in `index`
+    fn at(&self, index: &AtomicUsize) -> &Descriptor {
+        &self.descriptors[index.load(Ordering::SeqCst)]
+    }
+
+    /// Move the index stored in `index` to the next descriptor
+    fn advance(index: &AtomicUsize) {
+        index.store((index.load(Ordering::SeqCst) + 1) % N, Ordering::SeqCst);
+    }
+
+    /// Hand ownership of `descriptor` to the other side
+    fn hand_over(descriptor: &Descriptor, owner: u8) {
+        // The fields, and the buffer itself, must be written before the
+        // other side sees that it owns them
+        fence(Ordering::SeqCst);
+        descriptor.owner.store(owner, Ordering::SeqCst);
+    }
+
+    /// Whether the side which is `owner` owns `descriptor`
+    fn owns(descriptor: &Descriptor, owner: u8) -> bool {
+        let owns = descriptor.owner.load(Ordering::SeqCst) == owner;
+        // Nor may the fields be read before ownership is seen
+        fence(Ordering::SeqCst);
+        owns
+    }
+}
+
+impl<const N: usize> LockFree for DescriptorRing<N> {}
+
+impl<const N: usize> Default for DescriptorRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Submitting buffers to, and reclaiming them from, a shared
+/// `DescriptorRing`
+pub trait Descriptors<const N: usize>: SharedItem<Data = DescriptorRing<N>> {
+    /// Hand a buffer to the DMA side from application context, in the next
+    /// descriptor. The inner `Result` returns the buffer if that descriptor
+    /// has not been reclaimed yet.
+    fn submit(&mut self, buf: &'static mut [u8]) -> Result<Result<(), &'static mut [u8]>, Error>;
+
+    /// Take back the oldest buffer whose transfer is complete, from
+    /// application context, with the number of bytes transferred
+    fn reclaim(&mut self) -> Result<Option<(&'static mut [u8], usize)>, Error>;
+
+    /// The buffer of the descriptor to transfer next, from the
+    /// corresponding interrupt, as a pointer and a length to program the
+    /// DMA engine with. Returns `None` if the application has not submitted
+    /// one.
+    fn current_int_context() -> Result<Option<(*mut u8, usize)>, Error>;
+
+    /// Mark the transfer of the current descriptor as complete from the
+    /// corresponding interrupt, after `transferred` bytes, handing it back
+    /// to the application. Returns `false` if no descriptor was submitted.
+    fn complete_int_context(transferred: usize) -> Result<bool, Error>;
+}
+
+/// The ring shared with the interrupt, reached without masking it
+fn app_ring<S, const N: usize>(_token: &mut S) -> Result<&DescriptorRing<N>, Error>
+where
+    S: SharedItem<Data = DescriptorRing<N>>,
+{
+    if !S::is_initialized() {
+        return Err(Error::Uninitialized);
+    }
+
+    // NOTE(unsafe) the ring is initialized, and only shared references are
+    // made to it while it is used
+    Ok(unsafe { &*S::storage() })
+}
+
+impl<S, const N: usize> Descriptors<N> for S
+where
+    S: SharedItem<Data = DescriptorRing<N>>,
+{
+    fn submit(&mut self, buf: &'static mut [u8]) -> Result<Result<(), &'static mut [u8]>, Error> {
+        let ring = app_ring(self)?;
+        let descriptor = ring.at(&ring.submit);
+        if !DescriptorRing::<N>::owns(descriptor, EMPTY) {
+            return Ok(Err(buf));
+        }
+
+        descriptor
+            .addr
+            .store(buf.as_mut_ptr() as usize, Ordering::SeqCst);
+        descriptor.len.store(buf.len(), Ordering::SeqCst);
+        DescriptorRing::<N>::hand_over(descriptor, OWNED_BY_DMA);
+        DescriptorRing::<N>::advance(&ring.submit);
+        Ok(Ok(()))
+    }
+
+    fn reclaim(&mut self) -> Result<Option<(&'static mut [u8], usize)>, Error> {
+        let ring = app_ring(self)?;
+        let descriptor = ring.at(&ring.reclaim);
+        if !DescriptorRing::<N>::owns(descriptor, DONE) {
+            return Ok(None);
+        }
+
+        let addr = descriptor.addr.load(Ordering::SeqCst);
+        let len = descriptor.len.load(Ordering::SeqCst);
+        let transferred = descriptor.transferred.load(Ordering::SeqCst);
+        descriptor.owner.store(EMPTY, Ordering::SeqCst);
+        DescriptorRing::<N>::advance(&ring.reclaim);
+
+        // NOTE(unsafe) the buffer was handed over as a `&'static mut [u8]`
+        // in `submit`, and is owned by the application again
+        let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) };
+        Ok(Some((buf, transferred)))
+    }
+
+    fn current_int_context() -> Result<Option<(*mut u8, usize)>, Error> {
+        S::inspect_int_context(|ring| {
+            let descriptor = ring.at(&ring.current);
+            if !DescriptorRing::<N>::owns(descriptor, OWNED_BY_DMA) {
+                return None;
+            }
+
+            Some((
+                descriptor.addr.load(Ordering::SeqCst) as *mut u8,
+                descriptor.len.load(Ordering::SeqCst),
+            ))
+        })
+    }
+
+    fn complete_int_context(transferred: usize) -> Result<bool, Error> {
+        S::inspect_int_context(|ring| {
+            let descriptor = ring.at(&ring.current);
+            if !DescriptorRing::<N>::owns(descriptor, OWNED_BY_DMA) {
+                return false;
+            }
+
+            let len = descriptor.len.load(Ordering::SeqCst);
+            descriptor
+                .transferred
+                .store(transferred.min(len), Ordering::SeqCst);
+            DescriptorRing::<N>::hand_over(descriptor, DONE);
+            DescriptorRing::<N>::advance(&ring.current);
+            true
+        })
+    }
+}
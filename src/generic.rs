@@ -0,0 +1,1083 @@
+//! The shared item behind `shared!`, which may also be declared as a plain
+//! `static` without any macro
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::nvic::{self, Active, Controller, CoreLock, Interrupt, IrqSet, Masked};
+use crate::{CriticalSection, Error, Guard};
+
+/// The interrupts guarding a `Shared` item, and how they are configured.
+///
+/// This is implemented for every single interrupt, such as the variants of
+/// the interrupt enum of a PAC. `shared!` implements it for a type of its
+/// own for every item, following the options of the item, and it may be
+/// implemented by hand in the same way, such as to guard an item with
+/// several interrupts.
+///
+/// # Safety
+///
+/// Every method must always return the same value for the same item,
+/// `is_lent` must return `true` while part of the data is lent out, such
+/// as to a `WriteGrant`, and `is_lock_free` must return `true` for data
+/// which the application reads without masking the interrupts.
+pub unsafe trait Guards<T> {
+    /// The interrupts guarding the item
+    fn irqs(&self) -> IrqSet;
+
+    /// The priority set for the interrupts when the token is handed out,
+    /// as with the `priority = <n>` option of `shared!`
+    fn priority(&self) -> Option<u8> {
+        None
+    }
+
+    /// The hardware spinlock held during every access, as with the
+    /// `spinlock = <n>` option of `shared!`
+    fn spinlock(&self) -> Option<u8> {
+        None
+    }
+
+    /// Whether part of the data is currently lent out
+    fn is_lent(&self, _data: &T) -> bool {
+        false
+    }
+
+    /// Whether the application reaches the data without masking the
+    /// interrupts, as for a `Queue`, so that it may never be borrowed
+    /// mutably
+    fn is_lock_free(&self) -> bool {
+        false
+    }
+}
+
+unsafe impl<T, I: Interrupt> Guards<T> for I {
+    fn irqs(&self) -> IrqSet {
+        IrqSet::of(core::slice::from_ref(self))
+    }
+}
+
+/// Data shared between the application and the interrupts of `G`.
+///
+/// This is what `shared!` declares for each of its items, in a `static` of
+/// its own, next to the token type. Code bases which avoid macros may
+/// declare it themselves, in a plain `static`. Its token then borrows the
+/// `static`, rather than being a type of its own:
+///
+/// ```rust,ignore
+/// use shared::Shared;
+///
+/// static RADIO_PKTS: Shared<usize, Interrupt> = Shared::new(Interrupt::RADIO);
+/// static TICKS: Shared<u32, Interrupt> = Shared::with_initial(Interrupt::TIMER0, 0);
+///
+/// let mut pkts = RADIO_PKTS.set_initial(0).ok().unwrap();
+/// pkts.modify_app_context(|pkts| *pkts = 0).unwrap();
+///
+/// let ticks = TICKS.take_token().unwrap();
+///
+/// #[interrupt]
+/// fn RADIO() {
+///     RADIO_PKTS.modify_int_context(|pkts| *pkts += 1).unwrap();
+/// }
+/// ```
+///
+/// The options of `shared!` are given by implementing `Guards` for a type
+/// of your own. The extension traits are only implemented for the tokens
+/// of `shared!`.
+pub struct Shared<T, G> {
+    /// The shared data
+    data: UnsafeCell<MaybeUninit<T>>,
+
+    /// The interrupts guarding the data
+    guards: G,
+
+    /// Used to prevent re-entrant access to the data
+    flag: AtomicBool,
+
+    /// Marks whether the data has been initialized
+    init: AtomicBool,
+
+    /// Marks whether the token for the data has been handed out
+    taken: AtomicBool,
+
+    /// Marks whether the data has been modified from interrupt context
+    /// since the last `take_if_changed`
+    changed: AtomicBool,
+
+    /// Counts the read-only accesses currently in progress from within an
+    /// interrupt, which may be nested
+    readers: AtomicUsize,
+
+    /// Holds the masking left in place by `modify_app_context_and_hold`,
+    /// until `release` is called. This is only accessed with the token
+    held: UnsafeCell<Option<Masked>>,
+
+    /// Holds the function stored by `defer`, until the corresponding
+    /// interrupt runs it. This is protected in the same way as the data
+    deferred: UnsafeCell<Option<fn(&mut T)>>,
+}
+
+// NOTE(unsafe) the data is only reached through the interrupt discipline
+// below, which never hands out two mutable references to it at once
+unsafe impl<T: Send, G: Sync> Sync for Shared<T, G> {}
+
+impl<T, G> Shared<T, G> {
+    /// Create a new item guarded by `guards`, whose data is provided later
+    /// with `set_initial`
+    pub const fn new(guards: G) -> Self {
+        Self::with_data(guards, MaybeUninit::uninit(), false)
+    }
+
+    /// Create a new item guarded by `guards`, which already holds `data`.
+    /// Its token is taken with `take_token`.
+    pub const fn with_initial(guards: G, data: T) -> Self {
+        Self::with_data(guards, MaybeUninit::new(data), true)
+    }
+
+    const fn with_data(guards: G, data: MaybeUninit<T>, init: bool) -> Self {
+        Shared {
+            data: UnsafeCell::new(data),
+            guards,
+            flag: AtomicBool::new(false),
+            init: AtomicBool::new(init),
+            taken: AtomicBool::new(false),
+            changed: AtomicBool::new(false),
+            readers: AtomicUsize::new(0),
+            held: UnsafeCell::new(None),
+            deferred: UnsafeCell::new(None),
+        }
+    }
+
+    /// A pointer to the shared data. The data behind it is only valid once
+    /// `is_initialized` returns `true`.
+    #[doc(hidden)]
+    pub fn storage(&self) -> *mut T {
+        self.data.get() as *mut T
+    }
+
+    /// Whether the shared data has been initialized
+    pub fn is_initialized(&self) -> bool {
+        self.init.load(Ordering::SeqCst)
+    }
+
+    /// Get a raw pointer to the shared data, skipping every check made by
+    /// the other accessors.
+    ///
+    /// The NVIC is not read or written, and the re-entrancy flag is not
+    /// touched. This is intended for the hottest interrupt handlers, where
+    /// exclusive access can be proven by other means.
+    ///
+    /// # Safety
+    ///
+    /// `set_initial` must have been called, and the caller must guarantee
+    /// that no other access to the data (checked or unchecked) takes place
+    /// while the pointer is in use.
+    pub unsafe fn access_unchecked(&self) -> *mut T {
+        self.storage()
+    }
+}
+
+impl<T, G: Guards<T>> Shared<T, G> {
+    /// The interrupts guarding the data
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn irqs(&self) -> IrqSet {
+        self.guards.irqs()
+    }
+
+    /// Set the initial value of the shared data. This must be done from
+    /// application context, not interrupt context.
+    ///
+    /// This function must be called before the `modify_*` methods can be
+    /// used, otherwise they will return errors.
+    pub fn set_initial(&self, data: T) -> Result<Token<'_, T, G>, T> {
+        if !self.claim_uninit() {
+            return Err(data);
+        }
+
+        Ok(self.install(data))
+    }
+
+    /// Set the initial value of the shared data to the value returned by
+    /// the closure. This has the same requirements as `set_initial`.
+    ///
+    /// The closure is only called once it is known that initialization can
+    /// succeed, and its result is written straight into the static, which
+    /// avoids building large values on the stack first. If initialization
+    /// is not possible, the closure is handed back.
+    pub fn set_initial_with<F>(&self, f: F) -> Result<Token<'_, T, G>, F>
+    where
+        F: FnOnce() -> T,
+    {
+        if !self.claim_uninit() {
+            return Err(f);
+        }
+
+        Ok(self.install_with(f))
+    }
+
+    /// Set the initial value of the shared data from within a critical
+    /// section, such as `cortex_m::interrupt::free`.
+    ///
+    /// Unlike `set_initial`, this may be called while the corresponding
+    /// interrupt is enabled, as no interrupt can run until the critical
+    /// section ends.
+    pub fn set_initial_cs(&self, data: T, _cs: &CriticalSection) -> Result<Token<'_, T, G>, T> {
+        if !self.claim_uninit_masked() {
+            return Err(data);
+        }
+
+        Ok(self.install(data))
+    }
+
+    /// Set the initial value of the shared data, and then enable the
+    /// corresponding interrupts.
+    ///
+    /// Like `set_initial`, this fails if any of the interrupts are already
+    /// enabled. Core exceptions are not enabled. Taking the `NVIC` makes sure
+    /// that nothing else is configuring interrupts at the same time.
+    pub fn set_initial_nvic(&self, data: T, nvic: &mut Controller) -> Result<Token<'_, T, G>, T> {
+        let token = self.set_initial(data)?;
+        self.irqs().enable_with(nvic);
+        Ok(token)
+    }
+
+    /// Set the initial value of the shared data, even if the corresponding
+    /// interrupt is already enabled (for example by a bootloader).
+    ///
+    /// The interrupt is masked while the data is installed, in the same way
+    /// as for `modify_app_context`, and is then unmasked.
+    pub fn set_initial_masking(&self, data: T) -> Result<Token<'_, T, G>, T> {
+        let masked = self.irqs().mask();
+
+        let ret = if self.claim_uninit_masked() {
+            Ok(self.install(data))
+        } else {
+            Err(data)
+        };
+
+        masked.unmask();
+        ret
+    }
+
+    /// Checks whether `set_initial_cs` would succeed. This is used by
+    /// `init_all!`, and must be called from within a critical section.
+    #[doc(hidden)]
+    pub fn __can_init_masked(&self) -> bool {
+        !self.irqs().any_active()
+            && self.irqs().handlers_installed()
+            && !self.flag.load(Ordering::SeqCst)
+            && !self.init.load(Ordering::SeqCst)
+            && !self.taken.load(Ordering::SeqCst)
+    }
+
+    /// Checks that the data may be initialized, and if so marks the token
+    /// as handed out
+    fn claim_uninit(&self) -> bool {
+        !self.irqs().any_enabled() && self.claim_uninit_masked()
+    }
+
+    /// Like `claim_uninit`, but for when the interrupt can not fire, even
+    /// if it is enabled
+    fn claim_uninit_masked(&self) -> bool {
+        if self.irqs().any_active() || self.flag.load(Ordering::SeqCst) {
+            return false;
+        }
+        if !self.irqs().handlers_installed() {
+            return false;
+        }
+
+        if self.init.load(Ordering::SeqCst) || nvic::swap(&self.taken, true) {
+            return false;
+        }
+
+        self.configure();
+        true
+    }
+
+    /// Writes the data of a claimed item
+    fn install(&self, data: T) -> Token<'_, T, G> {
+        self.install_with(|| data)
+    }
+
+    /// Writes the data returned by `f` straight into a claimed item
+    fn install_with<F>(&self, f: F) -> Token<'_, T, G>
+    where
+        F: FnOnce() -> T,
+    {
+        // NOTE(unsafe) the token was claimed, and the data is not marked as
+        // initialized yet, so the interrupt can not access it
+        unsafe {
+            (*self.data.get()).write(f());
+        }
+        self.init.store(true, Ordering::SeqCst);
+        Token { shared: self }
+    }
+
+    /// Check that a handler is installed for every corresponding interrupt,
+    /// rather than `DefaultHandler`.
+    ///
+    /// The check is only made with the `check-handlers` feature, in which
+    /// case `set_initial` and the other initializers also fail if this does.
+    pub fn check_handler(&self) -> Result<(), Error> {
+        if self.irqs().handlers_installed() {
+            Ok(())
+        } else {
+            Err(Error::NoHandlerInstalled)
+        }
+    }
+
+    /// Applies the interrupt configuration of the guards
+    fn configure(&self) {
+        if let Some(priority) = self.guards.priority() {
+            unsafe { self.irqs().set_priority(priority) }
+        }
+    }
+
+    /// Reserve the token for the shared data without initializing it. The
+    /// data is provided later with `fill`.
+    ///
+    /// This allows the token to be handed to a driver before the data itself
+    /// exists. Until then, all accesses return `Error::NotFilled`. Returns
+    /// `None` if the token has already been handed out, or the data is
+    /// already initialized.
+    pub fn reserve(&self) -> Option<Token<'_, T, G>> {
+        if self.init.load(Ordering::SeqCst) || nvic::swap(&self.taken, true) {
+            return None;
+        }
+        self.configure();
+        Some(Token { shared: self })
+    }
+
+    /// Set the initial value of the shared data from within the
+    /// corresponding interrupt, for data that originates there.
+    ///
+    /// No token is returned, as the interrupt has no use for it. Instead,
+    /// application code may claim it afterwards with `take_token`. If this
+    /// is not called from the corresponding interrupt, or the data has
+    /// already been initialized, the data is handed back.
+    pub fn set_initial_int_context(&self, data: T) -> Result<(), T> {
+        match nvic::active_irq() {
+            active if self.irqs().is_running(active) => {}
+            _ => return Err(data),
+        }
+        if self.init.load(Ordering::SeqCst) || self.taken.load(Ordering::SeqCst) {
+            return Err(data);
+        }
+
+        // NOTE(unsafe) the corresponding interrupt is running, and no token
+        // exists, so nothing else can be accessing the data
+        unsafe {
+            (*self.data.get()).write(data);
+        }
+        self.init.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Initialize the shared data with `data` before RAM has been
+    /// initialized. See the `pre_init` function generated by `shared!`.
+    ///
+    /// # Safety
+    ///
+    /// This must only be called before `.data` and `.bss` have been
+    /// initialized, with the initial value of the `static`.
+    pub unsafe fn pre_init(&self, data: T) {
+        core::ptr::write_volatile(self.storage(), data);
+        self.flag.store(false, Ordering::SeqCst);
+        self.readers.store(0, Ordering::SeqCst);
+        self.taken.store(false, Ordering::SeqCst);
+        self.init.store(true, Ordering::SeqCst);
+        self.configure();
+    }
+
+    /// Take the token for an item that was created with `with_initial`, or
+    /// given its data with `set_initial_int_context`.
+    ///
+    /// Like `set_initial`, this only succeeds once, and only if the data has
+    /// been initialized.
+    pub fn take_token(&self) -> Option<Token<'_, T, G>> {
+        if self.init.load(Ordering::SeqCst) && !nvic::swap(&self.taken, true) {
+            self.configure();
+            Some(Token { shared: self })
+        } else {
+            None
+        }
+    }
+
+    /// Checks whether `take_token` would succeed. This is used by the
+    /// resources struct declared with `shared!`.
+    #[doc(hidden)]
+    pub fn __can_take(&self) -> bool {
+        self.init.load(Ordering::SeqCst) && !self.taken.load(Ordering::SeqCst)
+    }
+
+    /// Access the shared data from whichever context is currently running.
+    ///
+    /// The active vector is read from the SCB. In thread mode, this behaves
+    /// like `modify_app_context`. Inside the corresponding interrupt, this
+    /// behaves like `modify_int_context`. From any other interrupt or
+    /// exception, `Error::WrongContext` is returned.
+    ///
+    /// This is useful for helper functions that are called from both
+    /// contexts. Because no token is required, nested accesses from
+    /// application context are rejected with `Error::Reentrant`.
+    pub fn modify<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        match nvic::active_irq() {
+            Active::Thread => self.app_context(f),
+            active if self.irqs().is_running(active) => self.modify_int_context(f),
+            _ => Err(Error::WrongContext),
+        }
+    }
+
+    /// Inspect the shared data from whichever context is currently running,
+    /// as with `modify`. From any interrupt other than the corresponding
+    /// ones, `Error::WrongContext` is returned.
+    pub fn read<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        match nvic::active_irq() {
+            Active::Thread => self.inspect_app_context(f),
+            active if self.irqs().is_running(active) => self.read_int_context(f),
+            _ => Err(Error::WrongContext),
+        }
+    }
+
+    /// Access the shared data from an interrupt other than the corresponding
+    /// one.
+    ///
+    /// The corresponding interrupt is disabled for the duration of the closure,
+    /// just like in `modify_app_context`. If the current interrupt has preempted
+    /// the corresponding interrupt, it may be part way through an access, so
+    /// `Error::InterruptActive` is returned. Calling this from application
+    /// context or from the corresponding interrupt returns
+    /// `Error::WrongContext`.
+    ///
+    /// On ARMv6-M, where preempted interrupts can not be detected, this always
+    /// returns `Error::InterruptActive`.
+    pub fn modify_from_other_int<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        match nvic::active_irq() {
+            Active::Thread => Err(Error::WrongContext),
+            active if self.irqs().is_running(active) => Err(Error::WrongContext),
+            _ if !nvic::detects_preempted() => Err(Error::InterruptActive),
+            _ => self.app_context(f),
+        }
+    }
+
+    /// Access the shared data from the interrupt context. This function will
+    /// only work if the corresponding interrupt is currently active. This
+    /// function is not re-entrant - you cannot grab the shared data more than
+    /// once. The value returned by the closure is passed back to the caller.
+    ///
+    /// The data is marked as changed, for `take_if_changed`. While part of
+    /// the data is lent out, such as to a `WriteGrant`, `Error::Lent` is
+    /// returned. Data types which the application reads without masking the
+    /// interrupt, such as a `Queue`, can not be borrowed mutably, and return
+    /// `Error::LockFree`.
+    pub fn modify_int_context<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.int_context(|data| {
+            // NOTE(unsafe) the data is initialized
+            if self.guards.is_lent(unsafe { &*data }) {
+                return Err(Error::Lent);
+            }
+
+            // NOTE(unsafe) nothing is lent out
+            unsafe { self.modify_int_data(data, f) }
+        })?
+    }
+
+    /// Access the shared data from the interrupt context like
+    /// `modify_int_context`, even while part of it is lent out. This is used
+    /// by the interrupt ending the loan, such as at the end of a DMA
+    /// transfer.
+    ///
+    /// # Safety
+    ///
+    /// The closure must not touch the part of the data which is lent out
+    /// until the loan has ended.
+    #[doc(hidden)]
+    pub unsafe fn reclaim_int_context<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.int_context(|data| self.modify_int_data(data, f))?
+    }
+
+    /// Hands the data to the closure mutably, unless it is lock free, and
+    /// marks it as changed
+    ///
+    /// # Safety
+    ///
+    /// The re-entrancy flag must be set from within the interrupt, and the
+    /// closure must respect any loan of the data.
+    unsafe fn modify_int_data<F, R>(&self, data: *mut T, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        if self.guards.is_lock_free() {
+            return Err(Error::LockFree);
+        }
+
+        // The data is not lock free, so the application only reaches it with
+        // the interrupt masked
+        let ret = f(&mut *data);
+        self.changed.store(true, Ordering::SeqCst);
+        Ok(ret)
+    }
+
+    /// Inspect the shared data from the interrupt context, holding it as
+    /// exclusively as `modify_int_context` does, so that this can not be
+    /// nested within either of them.
+    ///
+    /// This is how the data types which update themselves through a shared
+    /// reference, such as a `Queue`, are reached from the interrupt, as two
+    /// updates must never overlap.
+    pub fn inspect_int_context<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        // NOTE(unsafe) only shared references are made to the data
+        self.int_context(|data| f(unsafe { &*data }))
+    }
+
+    /// Checks that the interrupt is running, and sets the re-entrancy flag
+    /// for the duration of the closure, which is handed the data
+    fn int_context<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(*mut T) -> R,
+    {
+        if !self.irqs().any_active() {
+            return Err(Error::WrongContext);
+        }
+        if !self.init.load(Ordering::SeqCst) {
+            return Err(self.uninit_error());
+        }
+        let _core = CoreLock::take(self.guards.spinlock())?;
+        if self.readers.load(Ordering::SeqCst) != 0 {
+            return Err(Error::Reentrant);
+        }
+        if nvic::swap(&self.flag, true) {
+            return Err(Error::Reentrant);
+        }
+
+        // The interrupt is running, so the application is not accessing the
+        // data, and the flag rules out a nested access
+        let ret = f(self.storage());
+
+        assert!(nvic::swap(&self.flag, false));
+        Ok(ret)
+    }
+
+    /// Inspect the shared data from the interrupt context. This function will
+    /// only work if the corresponding interrupt is currently active.
+    ///
+    /// Unlike `modify_int_context`, this may be nested: any number of
+    /// read-only accesses may be active at once, for example from a helper
+    /// function called inside another `read_int_context` closure. Calling
+    /// `modify_int_context` while a read is in progress (or vice versa)
+    /// returns `Error::Reentrant`.
+    pub fn read_int_context<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        if !self.irqs().any_active() {
+            return Err(Error::WrongContext);
+        }
+        if !self.init.load(Ordering::SeqCst) {
+            return Err(self.uninit_error());
+        }
+        let _core = CoreLock::take_nested(self.guards.spinlock())?;
+        if self.flag.load(Ordering::SeqCst) {
+            return Err(Error::Reentrant);
+        }
+
+        // Only the corresponding interrupt touches the reader count, and it
+        // cannot preempt itself, so a plain load and store is sufficient
+        let readers = self.readers.load(Ordering::SeqCst);
+        self.readers.store(readers + 1, Ordering::SeqCst);
+
+        // NOTE(unsafe) the interrupt is running, and the flag is not set, so
+        // only shared references to the data exist
+        let ret = f(unsafe { (*self.data.get()).assume_init_ref() });
+
+        self.readers.store(readers, Ordering::SeqCst);
+        Ok(ret)
+    }
+
+    /// Run the function stored with `defer`, if any, from within the
+    /// corresponding interrupt. Returns whether a function was run.
+    ///
+    /// This is intended to be called at the start or end of the interrupt
+    /// handler.
+    pub fn run_deferred(&self) -> Result<bool, Error> {
+        // NOTE(unsafe) the deferred function is protected by the flag, like
+        // the data
+        self.modify_int_context(|data| match unsafe { (*self.deferred.get()).take() } {
+            Some(f) => {
+                f(data);
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Disables the interrupt (if necessary) and grants access to the data
+    /// from application context. This is used by the tokens.
+    #[doc(hidden)]
+    pub fn app_context<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut guard = self.lock()?;
+        Ok(f(&mut guard))
+    }
+
+    /// Disables the interrupt (if necessary) and grants shared access to the
+    /// data from application context. Unlike `app_context`, this is also
+    /// possible for data which is reached without masking, such as a `Queue`.
+    /// This is used by the tokens.
+    #[doc(hidden)]
+    pub fn inspect_app_context<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let (masked, core) = self.enter()?;
+
+        // NOTE(unsafe) the data is initialized, and the flag is set
+        let ret = f(unsafe { (*self.data.get()).assume_init_ref() });
+
+        self.leave(masked, core);
+        Ok(ret)
+    }
+
+    /// Disables the interrupt (if necessary), checks the re-entrancy flag,
+    /// and returns a guard granting access to the data from application
+    /// context. This is used by the tokens.
+    ///
+    /// Data types which the application reads without masking the
+    /// interrupt, such as a `Queue`, return `Error::LockFree`.
+    #[doc(hidden)]
+    pub fn lock(&self) -> Result<Guard<'_, T>, Error> {
+        let (masked, core) = self.enter()?;
+        if self.guards.is_lock_free() {
+            self.leave(masked, core);
+            return Err(Error::LockFree);
+        }
+
+        // NOTE(unsafe) the interrupt is masked and the flag is set, so
+        // nothing else can access the data until the guard is dropped
+        unsafe {
+            Ok(Guard::new(
+                (*self.data.get()).assume_init_mut(),
+                &self.flag,
+                masked,
+                core,
+            ))
+        }
+    }
+
+    /// Disables the interrupt (if necessary), makes the checks shared by
+    /// every access from application context, and sets the re-entrancy
+    /// flag. These are undone with `leave`.
+    fn enter(&self) -> Result<(Masked, CoreLock), Error> {
+        let masked = self.irqs().mask();
+        let fail = |err| {
+            masked.unmask();
+            Err(err)
+        };
+
+        if self.irqs().any_active() {
+            return fail(Error::InterruptActive);
+        }
+        if !self.init.load(Ordering::SeqCst) {
+            return fail(self.uninit_error());
+        }
+        let mut core = match CoreLock::take(self.guards.spinlock()) {
+            Ok(core) => core,
+            Err(err) => return fail(err),
+        };
+        if nvic::swap(&self.flag, true) {
+            core.release();
+            return fail(Error::Reentrant);
+        }
+        // NOTE(unsafe) the data is initialized, and the flag is set
+        if self
+            .guards
+            .is_lent(unsafe { (*self.data.get()).assume_init_ref() })
+        {
+            self.leave(masked, core);
+            return Err(Error::Lent);
+        }
+
+        Ok((masked, core))
+    }
+
+    /// Clears the re-entrancy flag set by `enter`, releases the spinlock, and
+    /// restores the interrupt
+    fn leave(&self, masked: Masked, mut core: CoreLock) {
+        self.flag.store(false, Ordering::SeqCst);
+        core.release();
+        masked.unmask();
+    }
+
+    /// Disables the interrupt, and moves the data out, marking it as
+    /// uninitialized and the token as available again
+    fn teardown(&self) -> Option<T> {
+        if self.init.load(Ordering::SeqCst) {
+            // NOTE(unsafe) the data is initialized
+            assert!(
+                !self
+                    .guards
+                    .is_lent(unsafe { (*self.data.get()).assume_init_ref() }),
+                "shared data torn down while part of it is lent out"
+            );
+        }
+        // NOTE(unsafe) the held masking and the deferred function are only
+        // touched with the token, which is being given up
+        if let Some(masked) = unsafe { (*self.held.get()).take() } {
+            masked.unmask();
+        }
+        self.irqs().disable();
+        unsafe {
+            *self.deferred.get() = None;
+        }
+        self.changed.store(false, Ordering::SeqCst);
+        assert!(!self.irqs().any_active());
+        assert!(!self.flag.load(Ordering::SeqCst));
+
+        let data = if nvic::swap(&self.init, false) {
+            // NOTE(unsafe) the data was initialized, and is now marked as not
+            // being so
+            Some(unsafe { (*self.data.get()).assume_init_read() })
+        } else {
+            None
+        };
+        self.taken.store(false, Ordering::SeqCst);
+        data
+    }
+
+    /// The error to report when accessing data that is not initialized
+    fn uninit_error(&self) -> Error {
+        if self.taken.load(Ordering::SeqCst) {
+            Error::NotFilled
+        } else {
+            Error::Uninitialized
+        }
+    }
+}
+
+impl<T, G> Drop for Shared<T, G> {
+    fn drop(&mut self) {
+        if *self.init.get_mut() {
+            // NOTE(unsafe) the data is initialized, and no token borrows it
+            unsafe { self.data.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+/// The token of a `Shared` item, which grants access to its data from
+/// application context.
+///
+/// The tokens declared with `shared!` are made from this. Dropping it
+/// disables the interrupt and drops the data, so that `set_initial` may be
+/// called again.
+pub struct Token<'a, T, G: Guards<T>> {
+    shared: &'a Shared<T, G>,
+}
+
+impl<'a, T, G: Guards<T>> Token<'a, T, G> {
+    /// Recreate the token of `shared`. This is used by the code generated
+    /// by `shared!`, whose tokens stand for the token of their static.
+    ///
+    /// # Safety
+    ///
+    /// The token of `shared` must have been handed out, and must not be
+    /// used while this one exists, other than to drop it.
+    #[doc(hidden)]
+    pub unsafe fn new(shared: &'a Shared<T, G>) -> Self {
+        Token { shared }
+    }
+
+    /// Provide the data for a token obtained with `reserve`. If the data
+    /// has already been filled, it is handed back.
+    ///
+    /// The corresponding interrupt does not need to be disabled: it can not
+    /// access the data until it has been completely written.
+    pub fn fill(&mut self, data: T) -> Result<(), T> {
+        let shared = self.shared;
+        if shared.init.load(Ordering::SeqCst) {
+            return Err(data);
+        }
+
+        // NOTE(unsafe) the data is not initialized, so nothing else accesses
+        // it, and only the token may initialize it
+        unsafe {
+            (*shared.data.get()).write(data);
+        }
+        shared.init.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Access the shared data from the application (non-interrupt) context.
+    /// The interrupt must not be active when calling this function.
+    ///
+    /// During the scope of the closure, the corresponding interrupt will be
+    /// disabled to prevent concurrent access. The value returned by the
+    /// closure is passed back to the caller.
+    pub fn modify_app_context<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.shared.app_context(f)
+    }
+
+    /// Access the shared data from the application (non-interrupt) context,
+    /// like `modify_app_context`, but leave the corresponding interrupt
+    /// masked afterwards, until `release` is called.
+    ///
+    /// This may be called several times in a row, for example around
+    /// writes to peripheral registers that the interrupt must not observe
+    /// half way through. A single `release` unmasks the interrupt.
+    pub fn modify_app_context_and_hold<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut guard = self.shared.lock()?;
+        let ret = f(&mut guard);
+        let masked = guard.hold();
+
+        // If the interrupt is already held, the masking from the first call
+        // is kept
+        //
+        // NOTE(unsafe) the held masking is only touched with the token
+        unsafe {
+            let held = &mut *self.shared.held.get();
+            match held {
+                Some(_) => masked.unmask(),
+                None => *held = Some(masked),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Unmask the corresponding interrupt after `modify_app_context_and_hold`.
+    /// This does nothing if the interrupt is not held.
+    pub fn release(&mut self) {
+        // NOTE(unsafe) the held masking is only touched with the token
+        if let Some(masked) = unsafe { (*self.shared.held.get()).take() } {
+            masked.unmask();
+        }
+    }
+
+    /// Replace the shared data from the application (non-interrupt) context,
+    /// returning the previous value.
+    ///
+    /// The corresponding interrupt will be disabled while the data is swapped.
+    pub fn replace(&mut self, data: T) -> Result<T, Error> {
+        self.modify_app_context(move |x| core::mem::replace(x, data))
+    }
+
+    /// Update the shared data by value from the application (non-interrupt)
+    /// context.
+    ///
+    /// The current value is moved into the closure, and the value it returns
+    /// is stored in its place. This is convenient for enum based state
+    /// machines, where each transition consumes the previous state. The
+    /// corresponding interrupt is disabled for the duration of the closure.
+    pub fn update<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(T) -> T,
+    {
+        self.modify_app_context(|x| crate::update_in_place(x, f))
+    }
+
+    /// Overwrite the shared data from the application (non-interrupt) context.
+    ///
+    /// The corresponding interrupt will be disabled while the data is written.
+    /// The previous value is dropped after the interrupt has been restored.
+    pub fn set(&mut self, data: T) -> Result<(), Error> {
+        self.replace(data).map(drop)
+    }
+
+    /// Recover the shared data from the application (non-interrupt) context,
+    /// consuming the token.
+    ///
+    /// The corresponding interrupt is disabled, and is NOT re-enabled
+    /// afterwards. This is intended for shutdown paths, where the interrupt
+    /// is no longer needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the corresponding interrupt is active, which can only
+    /// happen if this is called from a higher priority interrupt, if
+    /// the token was obtained with `reserve` and never filled, or if
+    /// part of the data is lent out, such as to a `WriteGrant`.
+    pub fn into_inner(self) -> T {
+        let shared = self.shared;
+        core::mem::forget(self);
+        let data = shared.teardown();
+        data.expect("shared data was never filled")
+    }
+
+    /// Tear down the shared data, consuming the token, so that `set_initial`
+    /// may be called again.
+    ///
+    /// Like `into_inner`, this leaves the corresponding interrupt disabled,
+    /// as required by `set_initial`. The old data is dropped. This is the
+    /// same as dropping the token.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the corresponding interrupt is active, which can only
+    /// happen if this is called from a higher priority interrupt, or if
+    /// part of the data is lent out, such as to a `WriteGrant`.
+    pub fn reset(self) {
+        drop(self);
+    }
+
+    /// Lock the shared data for access from the application (non-interrupt)
+    /// context. The interrupt must not be active when calling this function.
+    ///
+    /// The corresponding interrupt will be disabled until the returned guard
+    /// is dropped, at which point it will be re-enabled if necessary.
+    pub fn lock_app_context(&mut self) -> Result<Guard<'_, T>, Error> {
+        self.shared.lock()
+    }
+
+    /// Access the shared data from the application (non-interrupt) context,
+    /// retrying while the interrupt is active until `deadline` has passed.
+    ///
+    /// `now` is called to read the current time from a monotonic time source,
+    /// such as a `fugit` instant from a timer. If the interrupt is still
+    /// active once `now() >= deadline`, `Error::Timeout` is returned.
+    pub fn modify_app_context_until<N, I, F, R>(
+        &mut self,
+        mut now: N,
+        deadline: I,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        N: FnMut() -> I,
+        I: PartialOrd,
+        F: FnOnce(&mut T) -> R,
+    {
+        loop {
+            match self.shared.lock() {
+                Ok(mut guard) => return Ok(f(&mut guard)),
+                Err(Error::InterruptActive) => {
+                    if now() >= deadline {
+                        return Err(Error::Timeout);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Attempt to access the shared data from the application (non-interrupt)
+    /// context, without treating contention as a failure.
+    ///
+    /// If the corresponding interrupt is currently active, the NVIC is left
+    /// untouched and `Error::WouldBlock` is returned, so the caller may skip
+    /// the access and retry later.
+    pub fn try_modify_app_context<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        if self.shared.irqs().any_active() {
+            return Err(Error::WouldBlock);
+        }
+
+        self.shared.app_context(f).map_err(|e| match e {
+            Error::InterruptActive => Error::WouldBlock,
+            e => e,
+        })
+    }
+
+    /// Inspect the shared data from the application (non-interrupt) context.
+    /// The interrupt must not be active when calling this function.
+    ///
+    /// This behaves like `modify_app_context`, but only hands out a shared
+    /// reference to the data.
+    pub fn read_app_context<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.shared.inspect_app_context(f)
+    }
+
+    /// Access the shared data from the application (non-interrupt) context,
+    /// like `modify_app_context`, but only if it has been modified from
+    /// interrupt context since the last call. Returns `Ok(None)` otherwise.
+    ///
+    /// Checking whether the data has changed does not touch the NVIC, so
+    /// this is cheap to poll.
+    pub fn take_if_changed<F, R>(&mut self, f: F) -> Result<Option<R>, Error>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let shared = self.shared;
+        if !shared.changed.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        let mut guard = shared.lock()?;
+        shared.changed.store(false, Ordering::SeqCst);
+        Ok(Some(f(&mut guard)))
+    }
+
+    /// Store a function to be run on the shared data from interrupt context,
+    /// and pend the corresponding interrupt. The function is run when the
+    /// interrupt calls `run_deferred`.
+    ///
+    /// Only one call may be waiting at a time. If one already is,
+    /// `Error::WouldBlock` is returned.
+    pub fn defer(&mut self, f: fn(&mut T)) -> Result<(), Error> {
+        let shared = self.shared;
+        let _guard = shared.lock()?;
+
+        // NOTE(unsafe) the deferred function is protected by the flag, like
+        // the data
+        unsafe {
+            let deferred = &mut *shared.deferred.get();
+            if deferred.is_some() {
+                return Err(Error::WouldBlock);
+            }
+            *deferred = Some(f);
+        }
+        shared.irqs().set_pending(true);
+        Ok(())
+    }
+
+    /// Pend the corresponding interrupt, so that it runs as soon as it is
+    /// enabled and not masked. This may be used to notify the interrupt of
+    /// new data.
+    pub fn pend(&self) {
+        self.shared.irqs().set_pending(true);
+    }
+
+    /// Clear the pending state of the corresponding interrupt
+    pub fn unpend(&self) {
+        self.shared.irqs().set_pending(false);
+    }
+
+    /// Is the corresponding interrupt pending?
+    pub fn is_pending(&self) -> bool {
+        self.shared.irqs().any_pending()
+    }
+}
+
+impl<'a, T, G: Guards<T>> Drop for Token<'a, T, G> {
+    fn drop(&mut self) {
+        drop(self.shared.teardown());
+    }
+}
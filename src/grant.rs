@@ -0,0 +1,309 @@
+//! A byte buffer handing out contiguous regions in place, so that frames
+//! are neither copied in nor out
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::lend::{Lend, LockFree};
+use crate::{Error, SharedItem};
+
+/// A fixed capacity byte buffer, filled by the interrupt and drained by the
+/// application through grants of contiguous regions.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `Grants` trait. The interrupt reserves a region with
+/// `grant_int_context`, which may be handed to a DMA transfer and committed
+/// in a later run of the handler, and the application reads whole committed
+/// regions in place:
+///
+/// ```rust,ignore
+/// shared!(
+///     (FRAMES, shared::GrantBuffer<1024>, Interrupt::UARTE0_UARTE0, shared::GrantBuffer::new()),
+/// );
+///
+/// static mut PENDING: Option<shared::WriteGrant<1024>> = None;
+///
+/// #[interrupt]
+/// fn UARTE0_UARTE0() {
+///     if let Some(grant) = unsafe { PENDING.take() } {
+///         grant.commit(received_len());
+///     }
+///     if let Some(mut grant) = FRAMES::grant_int_context(64).unwrap() {
+///         start_receive(&mut grant);
+///         unsafe { PENDING = Some(grant) };
+///     }
+/// }
+///
+/// if let Some(frame) = token.read_grant().unwrap() {
+///     let len = process(&frame);
+///     frame.release(len);
+/// }
+/// ```
+///
+/// Like a `Queue`, each side only moves its own indices, so the application
+/// never masks the interrupt. A region never wraps around the end of the
+/// buffer, so a grant which does not fit at the end starts over at the
+/// beginning, leaving the end unused until it is read past.
+pub struct GrantBuffer<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    /// Where the next region is written, only moved by the interrupt
+    write: AtomicUsize,
+    /// Where the next region is read, only moved by the application
+    read: AtomicUsize,
+    /// The end of the valid data when the writer has wrapped around
+    last: AtomicUsize,
+    /// Whether a `WriteGrant` is outstanding
+    granted: AtomicBool,
+    /// Whether a `ReadGrant` is outstanding
+    reading: AtomicBool,
+}
+
+impl<const N: usize> GrantBuffer<N> {
+    /// Create a new, empty buffer
+    pub const fn new() -> Self {
+        GrantBuffer {
+            buf: UnsafeCell::new([0; N]),
+            write: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            last: AtomicUsize::new(N),
+            granted: AtomicBool::new(false),
+            reading: AtomicBool::new(false),
+        }
+    }
+
+    /// The maximum number of bytes the buffer can hold
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// A pointer to the byte at `index`
+    fn at(&self, index: usize) -> *mut u8 {
+        // NOTE(unsafe) the index is never past the end of the buffer
+        unsafe { (self.buf.get() as *mut u8).add(index) }
+    }
+
+    /// Reserve `len` contiguous bytes for writing, returning where they
+    /// start, if there is room
+    ///
+    /// # Safety
+    ///
+    /// Only the interrupt may call this.
+    unsafe fn reserve(&self, len: usize) -> Option<usize> {
+        if self.granted.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let write = self.write.load(Ordering::SeqCst);
+        let read = self.read.load(Ordering::SeqCst);
+
+        // The write index may only reach the read index when the buffer
+        // is empty, so the regions stop one byte short of it
+        let start = if write < read {
+            if write + len < read {
+                write
+            } else {
+                return None;
+            }
+        } else if write + len <= N {
+            write
+        } else if len < read {
+            0
+        } else {
+            return None;
+        };
+
+        self.granted.store(true, Ordering::SeqCst);
+        Some(start)
+    }
+
+    /// Make the first `used` bytes of the region reserved at `start`
+    /// available to the application
+    ///
+    /// # Safety
+    ///
+    /// Only the holder of the `WriteGrant` for the region may call this.
+    unsafe fn commit(&self, start: usize, used: usize) {
+        let write = self.write.load(Ordering::SeqCst);
+        let end = start + used;
+
+        if start < write {
+            // The region wrapped around, so the data before it ends at the
+            // old write index
+            self.last.store(write, Ordering::SeqCst);
+        } else if end > self.last.load(Ordering::SeqCst) {
+            self.last.store(N, Ordering::SeqCst);
+        }
+
+        self.write.store(end, Ordering::SeqCst);
+        self.granted.store(false, Ordering::SeqCst);
+    }
+
+    /// The committed bytes which can be read contiguously, as the index of
+    /// the first one and their number
+    ///
+    /// # Safety
+    ///
+    /// Only the application may call this.
+    unsafe fn readable(&self) -> (usize, usize) {
+        let write = self.write.load(Ordering::SeqCst);
+        let last = self.last.load(Ordering::SeqCst);
+        let mut read = self.read.load(Ordering::SeqCst);
+
+        // Everything up to the end of the data before the wrap was read
+        if read == last && write < read {
+            read = 0;
+            self.read.store(0, Ordering::SeqCst);
+        }
+
+        let end = if write < read { last } else { write };
+        (read, end - read)
+    }
+}
+
+impl<const N: usize> Lend for GrantBuffer<N> {
+    fn is_lent(&self) -> bool {
+        self.granted.load(Ordering::SeqCst) || self.reading.load(Ordering::SeqCst)
+    }
+}
+
+impl<const N: usize> LockFree for GrantBuffer<N> {}
+
+impl<const N: usize> Default for GrantBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A region of a `GrantBuffer` reserved by the interrupt, which the
+/// application can not see until it is committed.
+///
+/// Dropping the grant commits none of it. While it is outstanding, the
+/// buffer may not be inspected from application context, nor modified by
+/// the interrupt, which return `Error::Lent`, and tearing the shared item
+/// down panics, as the grant still points into it.
+pub struct WriteGrant<const N: usize> {
+    buffer: &'static GrantBuffer<N>,
+    start: usize,
+    len: usize,
+}
+
+// NOTE(unsafe) the grant is the only access to its region, and the indices
+// it moves are atomics
+unsafe impl<const N: usize> Send for WriteGrant<N> {}
+
+impl<const N: usize> WriteGrant<N> {
+    /// Make the first `used` bytes of the region available to the
+    /// application, and end the grant
+    pub fn commit(self, used: usize) {
+        let used = used.min(self.len);
+        // NOTE(unsafe) the grant is unique, and is consumed here
+        unsafe { self.buffer.commit(self.start, used) };
+        core::mem::forget(self);
+    }
+}
+
+impl<const N: usize> Deref for WriteGrant<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // NOTE(unsafe) the region is reserved for this grant
+        unsafe { slice::from_raw_parts(self.buffer.at(self.start), self.len) }
+    }
+}
+
+impl<const N: usize> DerefMut for WriteGrant<N> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // NOTE(unsafe) the region is reserved for this grant
+        unsafe { slice::from_raw_parts_mut(self.buffer.at(self.start), self.len) }
+    }
+}
+
+impl<const N: usize> Drop for WriteGrant<N> {
+    fn drop(&mut self) {
+        // NOTE(unsafe) the grant is unique, and is dropped here
+        unsafe { self.buffer.commit(self.start, 0) };
+    }
+}
+
+/// A region of committed bytes of a `GrantBuffer`, read in place by the
+/// application.
+///
+/// Dropping the grant releases none of it, so the same bytes are granted
+/// again by the next `read_grant`. While it is outstanding, the buffer may
+/// not be modified by the interrupt, which returns `Error::Lent`.
+pub struct ReadGrant<'a, const N: usize> {
+    buffer: &'a GrantBuffer<N>,
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> ReadGrant<'_, N> {
+    /// Hand the first `used` bytes of the region back to the interrupt,
+    /// and end the grant
+    pub fn release(self, used: usize) {
+        let used = used.min(self.len);
+        self.buffer.read.store(self.start + used, Ordering::SeqCst);
+    }
+}
+
+impl<const N: usize> Drop for ReadGrant<'_, N> {
+    fn drop(&mut self) {
+        self.buffer.reading.store(false, Ordering::SeqCst);
+    }
+}
+
+impl<const N: usize> Deref for ReadGrant<'_, N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // NOTE(unsafe) the region is committed, and the interrupt does not
+        // write to it until it is released
+        unsafe { slice::from_raw_parts(self.buffer.at(self.start), self.len) }
+    }
+}
+
+/// Writing to and reading from a shared `GrantBuffer` in place
+pub trait Grants<const N: usize>: SharedItem<Data = GrantBuffer<N>> {
+    /// Take the committed bytes which can be read contiguously, from
+    /// application context, if there are any
+    fn read_grant(&mut self) -> Result<Option<ReadGrant<'_, N>>, Error>;
+
+    /// Reserve `len` contiguous bytes from the corresponding interrupt.
+    /// Returns `None` if there is no room, or if the previous `WriteGrant`
+    /// has not been committed or dropped yet.
+    fn grant_int_context(len: usize) -> Result<Option<WriteGrant<N>>, Error>;
+}
+
+impl<S, const N: usize> Grants<N> for S
+where
+    S: SharedItem<Data = GrantBuffer<N>>,
+{
+    fn read_grant(&mut self) -> Result<Option<ReadGrant<'_, N>>, Error> {
+        if !S::is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+
+        // NOTE(unsafe) the buffer is initialized, and the token borrowed
+        // here is the only reader
+        let buffer = unsafe { &*S::storage() };
+        let (start, len) = unsafe { buffer.readable() };
+        Ok(if len == 0 {
+            None
+        } else {
+            buffer.reading.store(true, Ordering::SeqCst);
+            Some(ReadGrant { buffer, start, len })
+        })
+    }
+
+    fn grant_int_context(len: usize) -> Result<Option<WriteGrant<N>>, Error> {
+        S::inspect_int_context(|_| ())?;
+
+        // NOTE(unsafe) this is the corresponding interrupt, and the storage
+        // of an initialized item is a static which is never moved or
+        // dropped
+        let buffer: &'static GrantBuffer<N> = unsafe { &*S::storage() };
+        Ok(unsafe { buffer.reserve(len) }.map(|start| WriteGrant { buffer, start, len }))
+    }
+}
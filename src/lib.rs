@@ -5,16 +5,60 @@
 //!
 //! ## Example
 //!
-//! ```rust
+//! ```rust,ignore
 //! use nrf52832_pac::Interrupt;
-//! use bare_metal;
 //! use cortex_m;
 //!
 //! // Tuples are of the format:
 //! //  (VARIABLE_NAME, VARIABLE_TYPE, CORRESPONDING_INTERRUPT),
+//! //
+//! // The interrupt may be any expression, such as a fully
+//! // qualified `nrf52832_pac::Interrupt::RADIO`. It is resolved
+//! // in the scope the macro is used in.
+//! //
+//! // Any `cortex_m::interrupt::InterruptNumber` may be used. PACs
+//! // implementing the older `bare_metal::Nr` are supported with
+//! // the `nr` feature.
+//! //
+//! // Data shared with several interrupts may list all of them,
+//! // as in `[Interrupt::RADIO, Interrupt::TIMER0]`. Application
+//! // access masks every listed interrupt, and each of them may
+//! // use `modify_int_context`.
+//! //
+//! // Core exceptions may be used as well, such as
+//! // `shared::CoreException::SysTick`.
+//! //
+//! // RISC-V targets with a PLIC are supported with the `riscv`
+//! // feature, using PLIC source IDs as interrupts. Handlers must
+//! // claim their interrupt with `shared::riscv::claim()`.
+//! //
+//! // Optionally, a constant initial value may be given as a fourth
+//! // element. These items are initialized before `main` runs.
+//! //
+//! // Finally, `priority = <value>` may be given to set the priority
+//! // of the interrupt when the item is initialized. This is the raw
+//! // value written to the NVIC, as with `NVIC::set_priority`.
+//! //
+//! // On the RP2040, `spinlock = <n>` may follow, to also hold SIO
+//! // spinlock `n` during every access, with the `rp2040` feature. On
+//! // dual-core STM32H7 parts, this holds HSEM semaphore `n` instead, with
+//! // the `stm32h7` feature.
+//! //
+//! // Last, `controller = <expr>` guards the item with lines of an
+//! // external `shared::IntController`, such as a GPIO expander, rather
+//! // than with interrupts of the core.
+//! //
+//! // `reader = <Name>` may also be given, to declare a read-only
+//! // handle `Name` to the data, which may be copied freely. The
+//! // token hands these out with `reader()`.
+//! //
+//! // The macro may be used any number of times in a module, for
+//! // example once in each driver for the items it needs.
 //! shared!(
 //!     (RADIO_PKTS, usize, Interrupt::RADIO),
 //!     (WALL_CLOCK, usize, Interrupt::RTC0),
+//!     (TICKS, u32, Interrupt::TIMER0, 0),
+//!     (EVENTS, u32, Interrupt::GPIOTE, 0, priority = 3 << 5),
 //! );
 //!
 //! #[entry]
@@ -27,10 +71,24 @@
 //!     // You access the data from within a closure. The interrupt
 //!     // this data is shared with is disabled for the duration of
 //!     // the closure. Other interrupts may still occur.
-//!     token.modify_app_context(|y| {
+//!     //
+//!     // Whatever the closure returns is handed back to the caller.
+//!     let remaining = token.modify_app_context(|y| {
 //!         *y -= 1;
-//!         y
+//!         *y
 //!     }).unwrap();
+//!     assert_eq!(remaining, 26);
+//!
+//!     // Items with an initial value don't need `set_initial`,
+//!     // the token can be taken directly, once.
+//!     let ticks = TICKS::take_token().unwrap();
+//! }
+//!
+//! // Items with an initial value may also be initialized before
+//! // RAM is, in case interrupts are already enabled at reset.
+//! #[pre_init]
+//! unsafe fn before_main() {
+//!     TICKS::pre_init();
 //! }
 //!
 //! #[interrupt]
@@ -42,7 +100,6 @@
 //!     // Access from within an interrupt doesn't require a token.
 //!     RADIO_PKTS::modify_int_context(|x| {
 //!         *x += 1;
-//!         x
 //!     }).unwrap();
 //! }
 //!
@@ -53,190 +110,2799 @@
 //!     // runtime!
 //!     BAZ::modify_int_context(|x| {
 //!         *x += 1;
-//!         x
 //!     }).unwrap();
 //! }
 //! ```
 
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(
+    any(target_arch = "msp430", target_arch = "avr", target_arch = "xtensa"),
+    feature(asm_experimental_arch)
+)]
 
-#[macro_export]
-macro_rules! shared {
-    (
-        $(($NAME:ident, $dat_ty:ty, $int:expr),)+
-    ) => {
-        /// Re-export all the structures at the top level, making them
-        /// visible at the scope the macro was used (not necessarily global!)
-        pub use shared_internals::structs::*;
-
-        /// This module is basically just here to hide all of the stuff
-        /// from being public
-        #[doc(hidden)]
-        pub mod shared_internals {
-
-            /// These are the actual data structures that back the
-            /// shared data
-            mod singletons {
-                $(
-                    pub static mut $NAME: Option<$dat_ty> = None;
-                )+
-            }
-
-            /// These flags are used to prevent re-entrant calls from within
-            /// an interrupt
-            mod flags {
-                use ::core::sync::atomic::AtomicBool;
-                $(
-                    pub static $NAME: AtomicBool = AtomicBool::new(false);
-                )+
-            }
-
-            /// This is the primary interface to the shared data. The struct itself
-            /// is actually an opaque zero sized type, with methods that grab data
-            /// from the `flags` and `singletons` modules
-            pub mod structs {
-                use ::core::sync::atomic::Ordering;
-                use ::cortex_m::peripheral::NVIC;
-                use ::bare_metal::Nr;
-
-                // This is bad. I don't know how else to generically get
-                // the interrupt enum provided by the -PAC though.
-                // PRs welcome :)
-                use super::super::Interrupt;
-
-                $(
-                    pub struct $NAME {
-                        _private: ()
-                    }
-
-                    impl $NAME {
-                        /// Set the initial value of the shared data. This must be done
-                        /// from application context, not interrupt context.
-                        ///
-                        /// This function must be called before the `modify_*` methods
-                        /// can be used, otherwise they will return errors.
-                        pub fn set_initial(data: $dat_ty) -> Result<$NAME, $dat_ty> {
-                            if int_is_enabled($int) || super::flags::$NAME.load(Ordering::SeqCst) {
-                                return Err(data);
-                            }
+use core::sync::atomic::{AtomicBool, Ordering};
 
-                            if unsafe { super::singletons::$NAME.is_none() } {
-                                unsafe {
-                                    super::singletons::$NAME = Some(data);
-                                }
-                                Ok($NAME { _private: () })
-                            } else {
-                                Err(data)
-                            }
-                        }
+pub use cortex_m::interrupt::CriticalSection;
 
-                        /// Access the shared data from the application (non-interrupt) context.
-                        /// The interrupt must not be active when calling this function.
-                        ///
-                        /// During the scope of the closure, the corresponding interrupt will be
-                        /// disabled to prevent concurrent access.
-                        pub fn modify_app_context<F>(&mut self, f: F) -> Result<(), ()>
-                        where
-                            for<'w> F: FnOnce(&'w mut $dat_ty) -> &'w mut $dat_ty,
-                        {
-                            // theoretical race condition: if an interrupt enables this interrupt between
-                            // the next line and the line after
-                            let enabled = int_is_enabled($int);
-                            if enabled {
-                                disable_int($int);
-                            }
-                            if int_is_active($int) || unsafe { super::singletons::$NAME.is_none() } {
-                                if enabled {
-                                    enable_int($int);
-                                }
-                                return Err(());
-                            }
+#[cfg(not(feature = "critical-section"))]
+#[doc(hidden)]
+pub use arch::free as __interrupt_free;
 
-                            unsafe {
-                                f(super::singletons::$NAME.as_mut().unwrap());
-                            }
+/// Execute the closure in a critical section of the `critical-section`
+/// crate
+#[cfg(feature = "critical-section")]
+#[doc(hidden)]
+pub fn __interrupt_free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    // NOTE(unsafe) the critical section lasts until the closure returns
+    critical_section::with(|_| f(unsafe { &CriticalSection::new() }))
+}
 
-                            if enabled {
-                                enable_int($int);
-                            }
+#[cfg_attr(cortex_m_backend, path = "arch/cortex_m.rs")]
+#[cfg_attr(feature = "riscv", path = "arch/riscv.rs")]
+#[cfg_attr(feature = "msp430", path = "arch/msp430.rs")]
+#[cfg_attr(feature = "avr", path = "arch/avr.rs")]
+#[cfg_attr(feature = "xtensa", path = "arch/xtensa.rs")]
+#[cfg_attr(feature = "gic", path = "arch/gic.rs")]
+#[cfg_attr(feature = "std", path = "arch/mock.rs")]
+#[cfg_attr(feature = "wasm", path = "arch/wasm.rs")]
+// The global masking of the backend is replaced with the `critical-section`
+// feature
+#[cfg_attr(feature = "critical-section", allow(dead_code, unused_imports))]
+mod arch;
+mod arena;
+mod buffer;
+#[cfg(feature = "heapless")]
+mod collections;
+mod controller;
+mod dma;
+mod generic;
+mod grant;
+mod limit;
+mod log;
+#[cfg(feature = "nrf52")]
+pub mod nrf52;
+mod peripheral;
+mod pool;
+#[cfg(feature = "qemu")]
+pub mod qemu;
+mod queue;
+#[cfg(feature = "rp2040")]
+pub mod rp2040;
+mod seqlock;
+mod signal;
+mod state;
+mod stats;
+#[cfg(feature = "stm32h7")]
+pub mod stm32h7;
+mod timer;
 
-                            Ok(())
-                        }
+#[cfg(feature = "rp2040")]
+use rp2040 as spinlocks;
+#[cfg(feature = "stm32h7")]
+use stm32h7 as spinlocks;
 
-                        /// Access the shared data from the interrupt context. This function will
-                        /// only work if the corresponding interrupt is currently active. This
-                        /// function is not re-entrant - you cannot grab the shared data more than
-                        /// once.
-                        pub fn modify_int_context<F>(f: F) -> Result<(), ()>
-                        where
-                            for<'w> F: FnOnce(&'w mut $dat_ty) -> &'w mut $dat_ty,
-                        {
-                            if !int_is_active($int) || unsafe { super::singletons::$NAME.is_none() } || super::flags::$NAME.swap(true, Ordering::SeqCst) {
-                                return Err(());
-                            }
+/// Claiming interrupts from, and configuring, the PLIC of RISC-V targets
+#[cfg(feature = "riscv")]
+pub mod riscv {
+    pub use crate::arch::{claim, set_plic, Claim, Plic};
+}
 
-                            unsafe {
-                                f(super::singletons::$NAME.as_mut().unwrap());
-                            }
+/// Registering the interrupt sources of MSP430 targets, and marking their
+/// handlers as running
+#[cfg(feature = "msp430")]
+pub mod msp430 {
+    pub use crate::arch::{enter, set_sources, InterruptSource, Running, Sources, Vector};
+}
 
-                            assert!(super::flags::$NAME.swap(false, Ordering::SeqCst));
-                            Ok(())
+/// Marking the handlers of CPU interrupts on Xtensa targets, such as the
+/// ESP32, as running
+#[cfg(feature = "xtensa")]
+pub mod xtensa {
+    pub use crate::arch::{enter, CpuInterrupts, Running, Vector};
+}
 
-                        }
-                    }
-                )+
-
-                /////////////////////////////////////////////////////////
-                // This section comes from the cortex-m crate.
-                //
-                // Ideally, we wouldn't need to copy/paste code, but
-                // I don't think it's possible to use these functions without
-                // having a mutable reference to the NVIC, which would require
-                // something taking ownership of it.
-                //
-                // PRs welcome if this could be done better!
-                /////////////////////////////////////////////////////////
-
-                /// This method comes from `cortex-m::NVIC`
-                fn int_is_enabled<I>(interrupt: I) -> bool
-                    where I: Nr,
-                {
-                    let nr = interrupt.nr();
-                    let mask = 1 << (nr % 32);
-
-                    // NOTE(unsafe) atomic read with no side effects
-                    unsafe { ((*NVIC::ptr()).iser[usize::from(nr / 32)].read() & mask) == mask }
+/// Configuring the GICv2 of Cortex-A targets, and acknowledging its
+/// interrupts
+#[cfg(feature = "gic")]
+pub mod gic {
+    pub use crate::arch::{acknowledge, set_gic, Acknowledged, Gic, Id};
+}
+
+/// Registering the interrupt sources of AVR targets, and marking their
+/// handlers as running
+#[cfg(feature = "avr")]
+pub mod avr {
+    pub use crate::arch::{enter, set_sources, InterruptSource, Running, Sources, Vector};
+}
+
+/// The attribute form of `shared!`, applied to `static` declarations
+#[cfg(feature = "macros")]
+pub mod attr {
+    pub use shared_macros::shared;
+}
+
+/// Simulating interrupts on the host, for testing code using `shared` items
+/// without hardware
+#[cfg(feature = "std")]
+pub mod mock {
+    pub use crate::arch::{
+        fire, preempt, preemption_point, priority, reset, with_preemption, Irq, MockNvic,
+    };
+}
+
+/// Driving the virtual interrupt controller of single threaded
+/// environments, such as WebAssembly in the browser
+#[cfg(feature = "wasm")]
+pub mod wasm {
+    pub use crate::arch::{pend, set_handler, set_trace, Event, Irq, VirtualNvic};
+}
+
+#[cfg(all(armv6m, feature = "basepri"))]
+compile_error!("the `basepri` feature is not supported on ARMv6-M, which lacks BASEPRI");
+
+#[cfg(all(not(cortex_m_backend), feature = "basepri"))]
+compile_error!("the `basepri` feature is only supported on Cortex-M");
+
+#[cfg(all(not(cortex_m_backend), feature = "check-handlers"))]
+compile_error!("the `check-handlers` feature is only supported on Cortex-M");
+
+#[cfg(all(feature = "rp2040", feature = "stm32h7"))]
+compile_error!("the `rp2040` and `stm32h7` features are mutually exclusive");
+
+#[cfg(all(feature = "critical-section", feature = "basepri"))]
+compile_error!("the `critical-section` and `basepri` features are mutually exclusive");
+
+#[cfg(all(not(cortex_m_backend), feature = "trustzone"))]
+compile_error!("the `trustzone` feature is only supported on Cortex-M");
+
+#[cfg(all(not(cortex_m_backend), feature = "qemu"))]
+compile_error!("the `qemu` feature is only supported on Cortex-M");
+
+pub use arena::{Arena, FromBytes};
+pub use buffer::{DoubleBuffer, Latest, PingPong, TripleBuffer};
+#[cfg(feature = "heapless")]
+pub use collections::{SharedDeque, SharedString, SharedVec};
+pub use controller::{IntController, Line};
+pub use dma::{DescriptorRing, Descriptors, Dma, DmaBuffer};
+pub use generic::{Guards, Shared, Token};
+pub use grant::{GrantBuffer, Grants, ReadGrant, WriteGrant};
+pub use limit::{Debounce, Debounced, Edge, RateLimit, TokenBucket};
+pub use log::{Log, LogRing, MAX_RECORD};
+pub use peripheral::{Peripheral, Registers};
+pub use pool::{Handle, Pool, SlotPool};
+#[cfg(cortex_m_backend)]
+pub use queue::Blocking;
+pub use queue::{ByteRing, EventQueue, Mailbox, Mpsc, Queue, Spsc, Stream};
+pub use seqlock::{SeqCell, SeqLock, Watch, WatchCell, Watcher};
+pub use signal::{Counter, EventCounter, EventFlags, Events};
+pub use state::{StateMachine, TransitionError, Transitions};
+pub use stats::{Record, Snapshot, Stats};
+pub use timer::{Fired, TimerId, TimerWheel, Timers, TIMERS};
+
+/// The reasons an access to a `shared` data item can fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// `set_initial` has not been called for this item yet
+    Uninitialized,
+
+    /// The corresponding interrupt is currently active, so the data may
+    /// not be accessed from application context
+    InterruptActive,
+
+    /// The corresponding interrupt is not active, so the data may not be
+    /// accessed from interrupt context
+    WrongContext,
+
+    /// The data is already being accessed from interrupt context
+    Reentrant,
+
+    /// The corresponding interrupt is currently active, or a deferred call is
+    /// already waiting. The access did not take place, but may succeed if
+    /// retried later
+    WouldBlock,
+
+    /// The corresponding interrupt remained active until the deadline passed
+    Timeout,
+
+    /// The token was handed out by `reserve`, but `fill` has not been called
+    /// yet
+    NotFilled,
+
+    /// The vector table entry for the corresponding interrupt still points
+    /// at `DefaultHandler`
+    NoHandlerInstalled,
+
+    /// The collection is at capacity, so the element was not added
+    Full,
+
+    /// The handle does not belong to this pool, or its slot is no longer
+    /// in use
+    InvalidHandle,
+
+    /// Part of the data is lent out, such as to a `WriteGrant`, so it may
+    /// not be modified from application context until it is given back
+    Lent,
+
+    /// The application reads the data without masking the interrupt, as for
+    /// a `Queue`, so it may not be borrowed mutably, only through the
+    /// methods of its type
+    LockFree,
+}
+
+/// Helpers for poking the interrupt controller without holding a
+/// reference to it. These are used by the code generated by `shared!`, and
+/// are not part of the public interface of this crate.
+#[doc(hidden)]
+pub mod nvic {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::arch;
+    pub use crate::arch::Controller;
+    #[cfg(cortex_m_backend)]
+    use crate::CoreException;
+    use crate::{IntController, Line};
+
+    /// Interrupts which may be used to guard `shared` data.
+    ///
+    /// By default, this is implemented for every
+    /// `cortex_m::interrupt::InterruptNumber`, which includes the interrupt
+    /// enums of older PACs implementing `bare_metal::Nr`, as long as they are
+    /// `Copy`. With the `nr` feature, it is instead implemented for every
+    /// `bare_metal::Nr`.
+    ///
+    /// # Safety
+    ///
+    /// `number` must return the number of a device specific interrupt.
+    pub unsafe trait Interrupt {
+        /// The number of the interrupt
+        fn number(&self) -> u16;
+    }
+
+    #[cfg(not(feature = "nr"))]
+    unsafe impl<I> Interrupt for I
+    where
+        I: cortex_m::interrupt::InterruptNumber,
+    {
+        fn number(&self) -> u16 {
+            cortex_m::interrupt::InterruptNumber::number(*self)
+        }
+    }
+
+    #[cfg(feature = "nr")]
+    unsafe impl<I> Interrupt for I
+    where
+        I: cortex_m::interrupt::Nr,
+    {
+        fn number(&self) -> u16 {
+            u16::from(self.nr())
+        }
+    }
+
+    /// `AtomicBool::swap`, which is emulated with all interrupts masked on
+    /// targets without atomic read-modify-write instructions, such as AVR
+    #[inline]
+    pub fn swap(flag: &AtomicBool, value: bool) -> bool {
+        #[cfg(target_has_atomic = "8")]
+        {
+            flag.swap(value, Ordering::SeqCst)
+        }
+
+        #[cfg(not(target_has_atomic = "8"))]
+        {
+            arch::free(|_| {
+                let previous = flag.load(Ordering::SeqCst);
+                flag.store(value, Ordering::SeqCst);
+                previous
+            })
+        }
+    }
+
+    /// A hardware spinlock held while the data is accessed, so that the other
+    /// core of an RP2040 or dual-core STM32H7 can not access it at the same
+    /// time. This does nothing for items without a spinlock.
+    pub struct CoreLock {
+        #[cfg(any(feature = "rp2040", feature = "stm32h7"))]
+        spinlock: Option<u8>,
+    }
+
+    impl CoreLock {
+        /// Take the spinlock, if any. Returns `Error::Reentrant` if this core
+        /// already holds it.
+        ///
+        /// # Panics
+        ///
+        /// Panics if a spinlock is given without the `rp2040` or `stm32h7`
+        /// feature.
+        pub fn take(spinlock: Option<u8>) -> Result<Self, crate::Error> {
+            Self::take_inner(spinlock, false)
+        }
+
+        /// Like `take`, but succeeds without taking the spinlock again if this
+        /// core already holds it, for accesses which may be nested
+        pub fn take_nested(spinlock: Option<u8>) -> Result<Self, crate::Error> {
+            Self::take_inner(spinlock, true)
+        }
+
+        #[cfg(any(feature = "rp2040", feature = "stm32h7"))]
+        fn take_inner(spinlock: Option<u8>, nested: bool) -> Result<Self, crate::Error> {
+            let spinlock = match spinlock {
+                Some(n) if crate::spinlocks::lock(n, nested)? => Some(n),
+                _ => None,
+            };
+            Ok(CoreLock { spinlock })
+        }
+
+        #[cfg(not(any(feature = "rp2040", feature = "stm32h7")))]
+        fn take_inner(spinlock: Option<u8>, _nested: bool) -> Result<Self, crate::Error> {
+            assert!(
+                spinlock.is_none(),
+                "`spinlock = <n>` requires the `rp2040` or `stm32h7` feature"
+            );
+            Ok(CoreLock {})
+        }
+
+        /// Release the spinlock early. This must happen before the interrupts
+        /// are unmasked, as an interrupt on this core taking the same spinlock
+        /// would otherwise spin forever.
+        pub fn release(&mut self) {
+            #[cfg(any(feature = "rp2040", feature = "stm32h7"))]
+            {
+                if let Some(n) = self.spinlock.take() {
+                    crate::spinlocks::unlock(n);
+                }
+            }
+        }
+    }
+
+    impl Drop for CoreLock {
+        fn drop(&mut self) {
+            self.release();
+        }
+    }
+
+    /// Can an interrupt that has been preempted be detected? This is not
+    /// possible on ARMv6-M, which lacks the active bits of the NVIC.
+    pub fn detects_preempted() -> bool {
+        cfg!(not(armv6m))
+    }
+
+    /// The kind of code that is currently running
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub enum Active {
+        /// Thread mode, i.e. application context
+        Thread,
+        /// The interrupt or core exception with this CMSIS IRQ number
+        Interrupt(i16),
+    }
+
+    /// Read the currently active interrupt
+    pub fn active_irq() -> Active {
+        arch::active()
+    }
+
+    /// The maximum number of interrupts that may guard a single item
+    pub const MAX_INTERRUPTS: usize = 8;
+
+    /// The set of interrupts guarding a single item.
+    ///
+    /// Interrupts are stored by their CMSIS IRQ number, so device specific
+    /// interrupts are positive, and core exceptions are negative. Sets made
+    /// with `through` instead hold the lines of an external `IntController`.
+    #[derive(Clone, Copy)]
+    pub struct IrqSet {
+        irqns: [i16; MAX_INTERRUPTS],
+        len: usize,
+        global: bool,
+        controller: Option<&'static dyn IntController>,
+    }
+
+    impl IrqSet {
+        /// A set containing no interrupts
+        pub const fn empty() -> Self {
+            IrqSet {
+                irqns: [0; MAX_INTERRUPTS],
+                len: 0,
+                global: false,
+                controller: None,
+            }
+        }
+
+        /// A set containing each of these interrupts
+        pub fn of<I: Interrupt>(interrupts: &[I]) -> Self {
+            let mut set = IrqSet::empty();
+            for int in interrupts {
+                // Device specific interrupts are numbered 0..=495
+                set.push(int.number() as i16);
+            }
+            set
+        }
+
+        /// A set containing each of these lines of `controller`. Lines are
+        /// kept by their bit pattern, and are only ever handed back to the
+        /// controller, so every line number is valid.
+        fn lines(lines: &[Line], controller: &'static dyn IntController) -> Self {
+            let mut set = IrqSet::empty().through(controller);
+            for line in lines {
+                set.push(line.0 as i16);
+            }
+            set
+        }
+
+        /// Mask all interrupts with PRIMASK, rather than only the ones in
+        /// the set, when `mask` is called
+        pub fn mask_globally(mut self) -> Self {
+            self.global = true;
+            self
+        }
+
+        /// Treat the interrupts of the set as lines of `controller`, rather
+        /// than interrupts of the core
+        pub fn through(mut self, controller: &'static dyn IntController) -> Self {
+            self.controller = Some(controller);
+            self
+        }
+
+        /// Add an interrupt to the set, by its CMSIS IRQ number
+        ///
+        /// # Panics
+        ///
+        /// Panics if the set already contains `MAX_INTERRUPTS` interrupts.
+        pub fn push(&mut self, irqn: i16) {
+            assert!(
+                self.len < MAX_INTERRUPTS,
+                "too many interrupts guarding one item"
+            );
+            self.irqns[self.len] = irqn;
+            self.len += 1;
+        }
+
+        /// The CMSIS IRQ numbers of the interrupts in the set
+        pub fn irqns(&self) -> &[i16] {
+            &self.irqns[..self.len]
+        }
+
+        /// Is the code that is currently running one of the interrupts in
+        /// the set?
+        pub fn is_running(&self, active: Active) -> bool {
+            match (active, self.controller) {
+                (Active::Thread, _) => false,
+                (Active::Interrupt(_), Some(controller)) => self
+                    .irqns()
+                    .iter()
+                    .any(|&line| controller.is_active(line as u16)),
+                (Active::Interrupt(irqn), None) => self.irqns().contains(&irqn),
+            }
+        }
+
+        /// Can the interrupt be disabled on its own? Every line of an
+        /// external controller can.
+        fn can_disable(&self, irqn: i16) -> bool {
+            self.controller.is_some() || arch::can_disable(irqn)
+        }
+
+        /// Is the interrupt enabled?
+        fn is_enabled(&self, irqn: i16) -> bool {
+            match self.controller {
+                Some(controller) => controller.is_enabled(irqn as u16),
+                None => arch::is_enabled(irqn),
+            }
+        }
+
+        /// Does the set contain any interrupts which can not be disabled on
+        /// their own, such as core exceptions?
+        fn has_exceptions(&self) -> bool {
+            self.irqns().iter().any(|&irqn| !self.can_disable(irqn))
+        }
+
+        /// Is any interrupt in the set enabled?
+        pub fn any_enabled(&self) -> bool {
+            self.irqns().iter().any(|&irqn| self.is_enabled(irqn))
+        }
+
+        /// Is any interrupt in the set active (running, or preempted)?
+        pub fn any_active(&self) -> bool {
+            match self.controller {
+                Some(controller) => self
+                    .irqns()
+                    .iter()
+                    .any(|&line| controller.is_active(line as u16)),
+                None => self.irqns().iter().any(|&irqn| arch::is_active(irqn)),
+            }
+        }
+
+        /// Set the priority of every interrupt in the set. The priority is
+        /// the raw value written to the priority register, as with
+        /// `NVIC::set_priority`. Lines of an external controller have no
+        /// priority of their own, and are skipped.
+        ///
+        /// # Safety
+        ///
+        /// Changing priorities can break priority based critical sections,
+        /// such as those made with the `basepri` feature.
+        pub unsafe fn set_priority(&self, prio: u8) {
+            if self.controller.is_some() {
+                return;
+            }
+            for &irqn in self.irqns() {
+                arch::set_priority(irqn, prio);
+            }
+        }
+
+        /// Does every interrupt in the set have a handler installed? This is
+        /// always `true` without the `check-handlers` feature.
+        pub fn handlers_installed(&self) -> bool {
+            #[cfg(feature = "check-handlers")]
+            {
+                self.controller.is_some()
+                    || self
+                        .irqns()
+                        .iter()
+                        .all(|&irqn| arch::handler_installed(irqn))
+            }
+
+            #[cfg(not(feature = "check-handlers"))]
+            {
+                true
+            }
+        }
+
+        /// Is any interrupt in the set pending?
+        pub fn any_pending(&self) -> bool {
+            self.irqns().iter().any(|&irqn| match self.controller {
+                Some(controller) => controller.is_pending(irqn as u16),
+                None => arch::is_pending(irqn),
+            })
+        }
+
+        /// Set, or clear, the pending state of every interrupt in the set
+        pub fn set_pending(&self, pending: bool) {
+            for &irqn in self.irqns() {
+                match self.controller {
+                    Some(controller) => controller.set_pending(irqn as u16, pending),
+                    None => arch::set_pending(irqn, pending),
+                }
+            }
+        }
+
+        /// The interrupts of the set which are enabled. Core exceptions are
+        /// skipped, as with `disable`.
+        pub fn enabled(&self) -> IrqSet {
+            let mut enabled = IrqSet {
+                controller: self.controller,
+                ..IrqSet::empty()
+            };
+            for &irqn in self.irqns() {
+                if self.can_disable(irqn) && self.is_enabled(irqn) {
+                    enabled.push(irqn);
                 }
+            }
+            enabled
+        }
 
-                /// This method comes from `cortex-m::NVIC`
-                fn int_is_active<I>(interrupt: I) -> bool
-                    where I: Nr
-                {
-                    let nr = interrupt.nr();
-                    let mask = 1 << (nr % 32);
+        /// Disable every device specific interrupt in the set, returning the
+        /// set of interrupts that were enabled beforehand. Core exceptions
+        /// can not be disabled, and are skipped.
+        pub fn disable(&self) -> IrqSet {
+            let mut enabled = IrqSet {
+                controller: self.controller,
+                ..IrqSet::empty()
+            };
+            for &irqn in self.irqns() {
+                if !self.can_disable(irqn) {
+                    continue;
+                }
 
-                    // NOTE(unsafe) atomic read with no side effects
-                    unsafe { ((*NVIC::ptr()).iabr[usize::from(nr / 32)].read() & mask) == mask }
+                // theoretical race condition: if an interrupt enables this interrupt between
+                // the next line and the line after
+                if self.is_enabled(irqn) {
+                    match self.controller {
+                        Some(controller) => controller.disable(irqn as u16),
+                        None => arch::disable(irqn),
+                    }
+                    enabled.push(irqn);
                 }
+            }
+            enabled
+        }
+
+        /// Enable every device specific interrupt in the set, with exclusive
+        /// access to the interrupt controller
+        pub fn enable_with(&self, _controller: &mut Controller) {
+            self.enable();
+        }
 
-                /// This method comes from `cortex-m::NVIC`
-                fn disable_int<I>(interrupt: I)
-                    where I: Nr
-                {
-                    let nr = interrupt.nr();
+        /// Enable every device specific interrupt in the set
+        pub fn enable(&self) {
+            for &irqn in self.irqns() {
+                match self.controller {
+                    Some(controller) => controller.enable(irqn as u16),
+                    None if arch::can_disable(irqn) => arch::enable(irqn),
+                    None => {}
+                }
+            }
+        }
 
-                    unsafe { (*NVIC::ptr()).icer[usize::from(nr / 32)].write(1 << (nr % 32)) }
+        /// Prevent every interrupt in the set from running, until the
+        /// returned `Masked` is unmasked.
+        ///
+        /// If the set was made with `mask_globally`, all interrupts are
+        /// masked with PRIMASK. Otherwise, with the `basepri` feature, BASEPRI
+        /// is raised to the priority of the most urgent interrupt in the set.
+        /// Otherwise, or if any of the interrupts has priority 0 (which
+        /// BASEPRI can not mask), the interrupts are disabled in the NVIC.
+        /// Sets containing core exceptions, which can not be disabled, are
+        /// masked with PRIMASK instead.
+        ///
+        /// On RISC-V, PRIMASK is `mstatus.MIE`, and the interrupts are
+        /// disabled in the PLIC. Lines of an external controller are always
+        /// disabled in that controller, unless masked globally.
+        ///
+        /// With the `critical-section` feature, a critical section of the
+        /// `critical-section` crate is acquired instead, whatever the set.
+        pub fn mask(&self) -> Masked {
+            #[cfg(feature = "basepri")]
+            {
+                if !self.global {
+                    if let Some(ceiling) = self.ceiling() {
+                        return Masked::Basepri(arch::raise_priority(ceiling));
+                    }
                 }
+            }
+
+            if cfg!(feature = "critical-section") || self.global || self.has_exceptions() {
+                return Masked::Primask(mask_all());
+            }
+
+            Masked::Nvic(self.disable())
+        }
+
+        /// The priority of the most urgent interrupt in the set, or `None`
+        /// if it can not be masked with BASEPRI
+        #[cfg(feature = "basepri")]
+        fn ceiling(&self) -> Option<u8> {
+            if self.controller.is_some() {
+                return None;
+            }
+
+            let ceiling = self
+                .irqns()
+                .iter()
+                .map(|&irqn| arch::priority(irqn))
+                .min()?;
+
+            if ceiling == 0 {
+                None
+            } else {
+                Some(ceiling)
+            }
+        }
+    }
+
+    #[cfg(not(feature = "critical-section"))]
+    use crate::arch::{mask_all, restore_all, Restore};
+
+    /// The state restored when leaving a critical section
+    #[cfg(feature = "critical-section")]
+    type Restore = critical_section::RestoreState;
+
+    /// Acquire a critical section of the `critical-section` crate
+    #[cfg(feature = "critical-section")]
+    fn mask_all() -> Restore {
+        // NOTE(unsafe) released by `Masked::unmask`
+        unsafe { critical_section::acquire() }
+    }
+
+    /// Release a critical section acquired by `mask_all`
+    #[cfg(feature = "critical-section")]
+    unsafe fn restore_all(restore: Restore) {
+        critical_section::release(restore)
+    }
+
+    /// The masking done by `IrqSet::mask`
+    #[derive(Clone, Copy)]
+    pub enum Masked {
+        /// These interrupts were disabled in the interrupt controller
+        Nvic(IrqSet),
+        /// BASEPRI was raised from this value
+        #[cfg(feature = "basepri")]
+        Basepri(u8),
+        /// All interrupts were masked with PRIMASK, or a critical section
+        /// was acquired with the `critical-section` feature, and are
+        /// restored to this state when unmasked
+        Primask(Restore),
+    }
+
+    impl Masked {
+        /// Undo the masking
+        pub fn unmask(&self) {
+            match *self {
+                Masked::Nvic(ref enabled) => enabled.enable(),
+                #[cfg(feature = "basepri")]
+                Masked::Basepri(previous) => arch::restore_priority(previous),
+                Masked::Primask(restore) => unsafe { restore_all(restore) },
+            }
+        }
+    }
+
+    /// Wraps the interrupt expression given to `shared!`, which may either be
+    /// a single interrupt, an array of interrupts, or a core exception.
+    ///
+    /// Arrays and core exceptions are handled by inherent methods, which take
+    /// priority over the `Single` trait method used for single interrupts.
+    pub struct Interrupts<T>(pub T);
+
+    impl<I: Interrupt, const N: usize> Interrupts<[I; N]> {
+        /// Collect the interrupts into a set
+        pub fn set(&self) -> IrqSet {
+            IrqSet::of(&self.0)
+        }
+    }
+
+    impl<I: Interrupt, const N: usize> Interrupts<crate::GlobalMask<[I; N]>> {
+        /// Collect the interrupts into a set, which is masked with PRIMASK
+        pub fn set(&self) -> IrqSet {
+            IrqSet::of(&(self.0).0).mask_globally()
+        }
+    }
+
+    #[cfg(cortex_m_backend)]
+    impl Interrupts<CoreException> {
+        /// Collect the exception into a set
+        pub fn set(&self) -> IrqSet {
+            let mut set = IrqSet::empty();
+            set.push(self.0.irqn());
+            set
+        }
+    }
+
+    /// Wraps the interrupt expression given to `shared!` with the
+    /// `controller = <expr>` option, which is either a single `Line`, or an
+    /// array of them. Unlike with `Interrupts`, the set is only ever made
+    /// together with the controller the lines belong to.
+    pub struct Lines<T>(pub T);
 
-                /// This method comes from `cortex-m::NVIC`
-                fn enable_int<I>(interrupt: I)
-                    where I: Nr
-                {
-                    let nr = interrupt.nr();
+    impl Lines<Line> {
+        /// Collect the line of `controller` into a set
+        pub fn set(&self, controller: &'static dyn IntController) -> IrqSet {
+            IrqSet::lines(core::slice::from_ref(&self.0), controller)
+        }
+    }
+
+    impl<const N: usize> Lines<[Line; N]> {
+        /// Collect the lines of `controller` into a set
+        pub fn set(&self, controller: &'static dyn IntController) -> IrqSet {
+            IrqSet::lines(&self.0, controller)
+        }
+    }
+
+    /// Collects a single interrupt into a set. See `Interrupts`.
+    pub trait Single {
+        /// Collect the interrupt into a set
+        fn set(&self) -> IrqSet;
+    }
+
+    impl<I: Interrupt> Single for Interrupts<I> {
+        fn set(&self) -> IrqSet {
+            IrqSet::of(core::slice::from_ref(&self.0))
+        }
+    }
+}
+
+/// Guard a `shared` item by masking all interrupts with PRIMASK during
+/// application context accesses, rather than only the listed interrupts.
+///
+/// This is cheaper than masking the interrupts individually, and is
+/// intended for items which are only accessed very briefly. The listed
+/// interrupts are still the only ones granted interrupt context access.
+///
+/// ```rust,ignore
+/// shared!(
+///     (TIMESTAMP, u32, shared::GlobalMask([Interrupt::RADIO, Interrupt::TIMER0])),
+/// );
+/// ```
+pub struct GlobalMask<T>(pub T);
+
+/// Core exceptions which may guard a `shared` item, in place of a device
+/// specific interrupt.
+///
+/// As these can not be disabled in the NVIC, application context access to
+/// items guarded by a core exception masks all interrupts with PRIMASK (or
+/// with BASEPRI, with the `basepri` feature). Checking whether a core
+/// exception is active requires SHCSR, so this is not supported on ARMv6-M.
+///
+/// ```rust,ignore
+/// shared!(
+///     (UPTIME, u64, shared::CoreException::SysTick, 0),
+/// );
+/// ```
+#[cfg(cortex_m_backend)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreException {
+    /// The supervisor call exception
+    SVCall,
+    /// The pendable service call exception
+    PendSV,
+    /// The system timer exception
+    SysTick,
+}
+
+/// Low level access to the storage backing an item declared with `shared!`.
+///
+/// This is implemented by the token types generated by `shared!`, and is
+/// used to provide the accessors that only make sense for some data types.
+/// It is not intended to be implemented by hand.
+///
+/// # Safety
+///
+/// `storage` must point to a static that is only ever accessed through the
+/// interrupt discipline enforced by the generated token type.
+pub unsafe trait SharedItem {
+    /// The type of the shared data
+    type Data;
+
+    /// A pointer to the static storage backing the shared data. The data
+    /// behind it is only valid once `is_initialized` returns `true`.
+    fn storage() -> *mut Self::Data;
 
-                    unsafe { (*NVIC::ptr()).iser[usize::from(nr / 32)].write(1 << (nr % 32)) }
+    /// Whether the shared data has been initialized
+    fn is_initialized() -> bool;
+
+    /// Set the initial value of the shared data, returning the token. This
+    /// is the same as the generated `set_initial_with` method.
+    fn init_with<F>(f: F) -> Result<Self, F>
+    where
+        Self: Sized,
+        F: FnOnce() -> Self::Data;
+
+    /// Access the shared data from application context, disabling the
+    /// corresponding interrupt for the duration of the closure. This is
+    /// the same as the generated `modify_app_context` method.
+    fn access_app_context<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Self::Data) -> R;
+
+    /// Access the shared data from the corresponding interrupt. This is the
+    /// same as the generated `modify_int_context` method.
+    fn access_int_context<F, R>(f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Self::Data) -> R;
+
+    /// Access the shared data from the corresponding interrupt like
+    /// `access_int_context`, even while part of it is lent out. This is used
+    /// to end the loan, such as at the end of a DMA transfer.
+    ///
+    /// # Safety
+    ///
+    /// The closure must not touch the part of the data which is lent out
+    /// until the loan has ended.
+    unsafe fn reclaim_int_context<F, R>(f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Self::Data) -> R;
+
+    /// Inspect the shared data from application context, disabling the
+    /// corresponding interrupt for the duration of the closure. This is the
+    /// same as the generated `read_app_context` method.
+    fn inspect_app_context<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&Self::Data) -> R;
+
+    /// Inspect the shared data from the corresponding interrupt. Unlike the
+    /// generated `read_int_context` method, this can not be nested, and
+    /// holds the data as exclusively as `access_int_context` does.
+    ///
+    /// Data types which the application reads without masking the interrupt
+    /// are accessed through this from the interrupt, so that both sides only
+    /// ever hold shared references, and two updates never overlap.
+    fn inspect_int_context<F, R>(f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&Self::Data) -> R;
+}
+
+/// Types which are read by the CPU with a single, untearable load.
+///
+/// This is only implemented for primitives no wider than 32 bits, and no
+/// wider than a pointer, which covers every supported cortex-m target.
+///
+/// # Safety
+///
+/// Implementors must be aligned and no wider than a machine word, and
+/// `load` must read them through the atomic type of the same size.
+pub unsafe trait WordSized: Copy {
+    /// Read the value behind `ptr` with a single atomic load
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads, and only ever written atomically, or
+    /// while no other context can read it.
+    #[doc(hidden)]
+    unsafe fn load(ptr: *const Self) -> Self;
+}
+
+macro_rules! word_sized {
+    ($($(#[$cfg:meta])* $ty:ty => $atomic:ident,)+) => {
+        $(
+            $(#[$cfg])*
+            unsafe impl WordSized for $ty {
+                unsafe fn load(ptr: *const Self) -> Self {
+                    // NOTE(unsafe) the atomic type has the same size and
+                    // alignment as the primitive
+                    (*(ptr as *const core::sync::atomic::$atomic)).load(Ordering::SeqCst)
                 }
             }
+        )+
+    };
+}
+
+word_sized! {
+    u8 => AtomicU8,
+    i8 => AtomicI8,
+    u16 => AtomicU16,
+    i16 => AtomicI16,
+    bool => AtomicBool,
+    #[cfg(not(target_pointer_width = "16"))]
+    u32 => AtomicU32,
+    #[cfg(not(target_pointer_width = "16"))]
+    i32 => AtomicI32,
+    #[cfg(target_pointer_width = "32")]
+    usize => AtomicUsize,
+    #[cfg(target_pointer_width = "32")]
+    isize => AtomicIsize,
+}
+
+#[cfg(not(target_pointer_width = "16"))]
+unsafe impl WordSized for char {
+    unsafe fn load(ptr: *const Self) -> Self {
+        // NOTE(unsafe) the value behind `ptr` is a valid `char`
+        char::from_u32_unchecked(u32::load(ptr as *const u32))
+    }
+}
+
+/// Lock-free reads of small shared data items
+pub trait Peek: SharedItem {
+    /// Read the current value of the shared data with a single load,
+    /// without touching the NVIC. This may be called from any context.
+    ///
+    /// Returns `Error::Uninitialized` if `set_initial` has not been called.
+    fn peek() -> Result<Self::Data, Error>;
+}
+
+impl<S> Peek for S
+where
+    S: SharedItem,
+    S::Data: WordSized,
+{
+    fn peek() -> Result<Self::Data, Error> {
+        // NOTE(unsafe) the data is only ever written while the owning
+        // interrupt is disabled or active, and the value is read with a
+        // single atomic load, so it can never be observed half-written
+        if S::is_initialized() {
+            Ok(unsafe { S::Data::load(S::storage()) })
+        } else {
+            Err(Error::Uninitialized)
         }
     }
 }
+
+/// Moving data out of shared items holding an `Option`
+pub trait Take<T>: SharedItem<Data = Option<T>> {
+    /// Take the value out of the shared data from application context,
+    /// leaving `None` in its place.
+    fn take(&mut self) -> Result<Option<T>, Error>;
+}
+
+impl<S, T> Take<T> for S
+where
+    S: SharedItem<Data = Option<T>>,
+{
+    fn take(&mut self) -> Result<Option<T>, Error> {
+        self.access_app_context(Option::take)
+    }
+}
+
+/// Shared items that may logically be empty.
+///
+/// Declaring an item as `Option<T>` with an initial value of `None` means
+/// that an interrupt firing before the application has provided a value
+/// observes an empty item, rather than an `Error::Uninitialized`:
+///
+/// ```rust,ignore
+/// shared!(
+///     (CONNECTION, Option<Connection>, Interrupt::RADIO, None),
+/// );
+/// ```
+pub trait Optional<T>: SharedItem<Data = Option<T>> {
+    /// Whether the shared data holds a value, checked from application
+    /// context
+    fn is_some(&mut self) -> Result<bool, Error>;
+
+    /// Store a value in the shared data from application context, returning
+    /// the previous value, if any
+    fn insert(&mut self, value: T) -> Result<Option<T>, Error>;
+
+    /// Whether the shared data holds a value, checked from interrupt context
+    fn is_some_int_context() -> Result<bool, Error>;
+
+    /// Store a value in the shared data from interrupt context, returning
+    /// the previous value, if any
+    fn insert_int_context(value: T) -> Result<Option<T>, Error>;
+
+    /// Take the value out of the shared data from interrupt context,
+    /// leaving `None` in its place
+    fn take_int_context() -> Result<Option<T>, Error>;
+}
+
+impl<S, T> Optional<T> for S
+where
+    S: SharedItem<Data = Option<T>>,
+{
+    fn is_some(&mut self) -> Result<bool, Error> {
+        self.access_app_context(|data| data.is_some())
+    }
+
+    fn insert(&mut self, value: T) -> Result<Option<T>, Error> {
+        self.access_app_context(|data| data.replace(value))
+    }
+
+    fn is_some_int_context() -> Result<bool, Error> {
+        S::access_int_context(|data| data.is_some())
+    }
+
+    fn insert_int_context(value: T) -> Result<Option<T>, Error> {
+        S::access_int_context(|data| data.replace(value))
+    }
+
+    fn take_int_context() -> Result<Option<T>, Error> {
+        S::access_int_context(Option::take)
+    }
+}
+
+/// All-or-nothing modification of shared items holding `Clone` data
+pub trait Transactional: SharedItem {
+    /// Modify the shared data from application context. If the closure
+    /// returns `Err`, the shared data is restored to the value it had
+    /// before the closure was called.
+    ///
+    /// The outer `Result` reports whether the data could be accessed at
+    /// all, the inner one is the result returned by the closure.
+    fn modify_transactional<F, R, E>(&mut self, f: F) -> Result<Result<R, E>, Error>
+    where
+        F: FnOnce(&mut Self::Data) -> Result<R, E>;
+}
+
+impl<S> Transactional for S
+where
+    S: SharedItem,
+    S::Data: Clone,
+{
+    fn modify_transactional<F, R, E>(&mut self, f: F) -> Result<Result<R, E>, Error>
+    where
+        F: FnOnce(&mut Self::Data) -> Result<R, E>,
+    {
+        self.access_app_context(|data| {
+            let backup = data.clone();
+            let ret = f(data);
+            if ret.is_err() {
+                *data = backup;
+            }
+            ret
+        })
+    }
+}
+
+/// Copying shared items holding `Clone` data out without using the stack
+pub trait CloneInto: SharedItem {
+    /// Clone the shared data into `dest` from application context. The
+    /// corresponding interrupt is disabled while the data is copied.
+    ///
+    /// This avoids returning large values from a closure, which would
+    /// place a temporary copy on the stack.
+    fn clone_into(&mut self, dest: &mut Self::Data) -> Result<(), Error>;
+}
+
+impl<S> CloneInto for S
+where
+    S: SharedItem,
+    S::Data: Clone,
+{
+    fn clone_into(&mut self, dest: &mut Self::Data) -> Result<(), Error> {
+        self.access_app_context(|data| dest.clone_from(data))
+    }
+}
+
+/// Projecting shared items onto a single field of the shared data
+pub trait Project: SharedItem + Sized {
+    /// Create a view of a single field of the shared data, such as
+    /// `token.project(|s| &mut s.tx_count)`.
+    ///
+    /// Accesses through the projection only disable the corresponding
+    /// interrupt for as long as it takes to operate on that field, keeping
+    /// critical sections short when only part of a large struct is needed.
+    fn project<U>(&mut self, project: fn(&mut Self::Data) -> &mut U) -> Projection<'_, Self, U>;
+}
+
+impl<S> Project for S
+where
+    S: SharedItem,
+{
+    fn project<U>(&mut self, project: fn(&mut Self::Data) -> &mut U) -> Projection<'_, Self, U> {
+        Projection {
+            token: self,
+            project,
+        }
+    }
+}
+
+/// A view of a single field of a `shared` data item, created with
+/// `Project::project`
+pub struct Projection<'a, S: SharedItem, U> {
+    token: &'a mut S,
+    project: fn(&mut S::Data) -> &mut U,
+}
+
+impl<'a, S: SharedItem, U> Projection<'a, S, U> {
+    /// Access the field from the application (non-interrupt) context.
+    /// See `modify_app_context` for details.
+    pub fn modify_app_context<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut U) -> R,
+    {
+        let project = self.project;
+        self.token.access_app_context(|data| f(project(data)))
+    }
+
+    /// Inspect the field from the application (non-interrupt) context.
+    /// See `read_app_context` for details.
+    pub fn read_app_context<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&U) -> R,
+    {
+        self.modify_app_context(|field| f(field))
+    }
+}
+
+/// Collections that elements can be removed from one at a time.
+///
+/// Implement this for your queue type to use `DrainWith::drain_with`.
+pub trait Pop {
+    /// The type of the elements held by the collection
+    type Item;
+
+    /// Remove the next element from the collection, if there is one
+    fn pop(&mut self) -> Option<Self::Item>;
+}
+
+impl<T> Pop for Option<T> {
+    type Item = T;
+
+    fn pop(&mut self) -> Option<T> {
+        self.take()
+    }
+}
+
+/// Draining shared collections with a bounded interrupt latency
+pub trait DrainWith: SharedItem
+where
+    Self::Data: Pop,
+{
+    /// Remove every element from the shared collection from application
+    /// context, passing each one to the closure.
+    ///
+    /// The corresponding interrupt is only disabled while each element is
+    /// removed, and is restored while the closure runs, so draining a large
+    /// collection does not hold the interrupt off for the whole operation.
+    ///
+    /// Returns the number of elements drained.
+    fn drain_with<F>(&mut self, f: F) -> Result<usize, Error>
+    where
+        F: FnMut(<Self::Data as Pop>::Item);
+}
+
+impl<S> DrainWith for S
+where
+    S: SharedItem,
+    S::Data: Pop,
+{
+    fn drain_with<F>(&mut self, mut f: F) -> Result<usize, Error>
+    where
+        F: FnMut(<Self::Data as Pop>::Item),
+    {
+        let mut drained = 0;
+        while let Some(elem) = self.access_app_context(Pop::pop)? {
+            f(elem);
+            drained += 1;
+        }
+        Ok(drained)
+    }
+}
+
+/// Exchanging the data of two shared items holding the same type
+pub trait Swap<O>: SharedItem
+where
+    O: SharedItem<Data = Self::Data>,
+{
+    /// Swap the data of this item with the data of `other` from application
+    /// context. Both corresponding interrupts are disabled while the values
+    /// are exchanged.
+    fn swap(&mut self, other: &mut O) -> Result<(), Error>;
+}
+
+impl<S, O> Swap<O> for S
+where
+    S: SharedItem,
+    O: SharedItem<Data = S::Data>,
+{
+    fn swap(&mut self, other: &mut O) -> Result<(), Error> {
+        self.access_app_context(|a| other.access_app_context(|b| core::mem::swap(a, b)))?
+    }
+}
+
+/// Initializing shared items holding `Default` data
+pub trait InitDefault: SharedItem + Sized {
+    /// Set the initial value of the shared data to its default value. This
+    /// has the same requirements as `set_initial`.
+    ///
+    /// Returns `None` if the data could not be initialized.
+    fn init_default() -> Option<Self>;
+}
+
+impl<S> InitDefault for S
+where
+    S: SharedItem,
+    S::Data: Default,
+{
+    fn init_default() -> Option<Self> {
+        S::init_with(Default::default).ok()
+    }
+}
+
+/// Shared items whose data lives in storage provided by the user.
+///
+/// Declaring an item as `&'static mut T` means the crate only manages the
+/// access discipline, while the backing memory can come from
+/// `cortex_m::singleton!`, a buffer placed in a specific RAM bank by the
+/// linker, or a memory pool:
+///
+/// ```rust,ignore
+/// shared!(
+///     (DMA_BUF, &'static mut [u8; 1024], Interrupt::UARTE0_UARTE0),
+/// );
+///
+/// let buf = cortex_m::singleton!(: [u8; 1024] = [0; 1024]).unwrap();
+/// let mut token = DMA_BUF::set_initial_static(buf).unwrap();
+/// token.modify_app_context(|buf| buf[0] = 0x42).unwrap();
+/// ```
+pub trait StaticStorage<T: 'static>: SharedItem<Data = &'static mut T> + Sized {
+    /// Set the initial value of the shared data to a reference to `buf`.
+    /// This has the same requirements as `set_initial`, and hands `buf`
+    /// back if the data could not be initialized.
+    fn set_initial_static(buf: &'static mut T) -> Result<Self, &'static mut T>;
+}
+
+impl<S, T> StaticStorage<T> for S
+where
+    S: SharedItem<Data = &'static mut T>,
+    T: 'static,
+{
+    fn set_initial_static(buf: &'static mut T) -> Result<Self, &'static mut T> {
+        S::init_with(move || buf).map_err(|f| f())
+    }
+}
+
+/// Re-exports of the extension traits implemented for `shared` data items
+pub mod prelude {
+    pub use crate::{
+        CloneInto, Counter, Debounced, Descriptors, Dma, DrainWith, Events, Grants, InitDefault,
+        Latest, Log, Mpsc, Optional, Peek, PingPong, Pop, Project, RateLimit, Record, Registers,
+        SeqLock, SlotPool, Spsc, StaticStorage, Swap, Take, Timers, Transactional, Transitions,
+        Watch,
+    };
+
+    #[cfg(cortex_m_backend)]
+    pub use crate::Blocking;
+
+    #[cfg(feature = "heapless")]
+    pub use crate::{SharedDeque, SharedString, SharedVec};
+}
+
+/// Checks of whether part of the data of a `shared` item is lent out, such
+/// as to a `WriteGrant`, and of whether the data is reached without masking.
+/// These are used by the code generated by `shared!`, and are not part of
+/// the public interface of this crate.
+///
+/// The generated code calls `is_lent` on a `&Probe` of the data, which
+/// resolves to `ProbeLent` for data types implementing `Lend`, and to
+/// `ProbeNever` for every other type. In the same way, `is_lock_free` is
+/// called on a `&Kind` of the type of the data.
+#[doc(hidden)]
+pub mod lend {
+    /// Data types which may lend part of themselves out
+    pub trait Lend {
+        /// Whether part of the data is currently lent out
+        fn is_lent(&self) -> bool;
+    }
+
+    /// The data of an item, whose type is checked for `Lend`
+    pub struct Probe<'a, T>(pub &'a T);
+
+    /// The check made for data types implementing `Lend`
+    pub trait ProbeLent {
+        /// Whether part of the data is currently lent out
+        fn is_lent(&self) -> bool;
+    }
+
+    impl<T: Lend> ProbeLent for Probe<'_, T> {
+        fn is_lent(&self) -> bool {
+            self.0.is_lent()
+        }
+    }
+
+    /// The check made for every other data type, which never lends
+    pub trait ProbeNever {
+        /// Whether part of the data is currently lent out
+        fn is_lent(&self) -> bool {
+            false
+        }
+    }
+
+    impl<T> ProbeNever for &Probe<'_, T> {}
+
+    /// Data types which the application reads without masking the
+    /// interrupts, such as a `Queue`, so that they may never be borrowed
+    /// mutably
+    pub trait LockFree {}
+
+    /// The type of the data of an item, which is checked for `LockFree`
+    pub struct Kind<T>(pub core::marker::PhantomData<T>);
+
+    /// The check made for data types implementing `LockFree`
+    pub trait ProbeLockFree {
+        /// Whether the data is reached without masking
+        fn is_lock_free(&self) -> bool {
+            true
+        }
+    }
+
+    impl<T: LockFree> ProbeLockFree for Kind<T> {}
+
+    /// The check made for every other data type, which is always masked
+    pub trait ProbeLocked {
+        /// Whether the data is reached without masking
+        fn is_lock_free(&self) -> bool {
+            false
+        }
+    }
+
+    impl<T> ProbeLocked for &Kind<T> {}
+}
+
+/// Replace the value behind `dest` with the result of calling `f` with the
+/// old value. This is used by the code generated by `shared!`.
+///
+/// If `f` panics, there is no value to leave behind, so the program is
+/// aborted by panicking a second time while unwinding.
+#[doc(hidden)]
+pub fn update_in_place<T, F>(dest: &mut T, f: F)
+where
+    F: FnOnce(T) -> T,
+{
+    struct AbortOnPanic;
+
+    impl Drop for AbortOnPanic {
+        fn drop(&mut self) {
+            panic!("panicked while updating shared data");
+        }
+    }
+
+    let bomb = AbortOnPanic;
+    unsafe {
+        let old = core::ptr::read(dest);
+        core::ptr::write(dest, f(old));
+    }
+    core::mem::forget(bomb);
+}
+
+/// The private field of the tokens generated by `shared!`, and of their
+/// read-only handles, with `ReadOnly` as the kind.
+///
+/// This implements `Debug`, `PartialEq`, `Eq`, `PartialOrd`, `Ord` and
+/// `Hash`, so that those may be derived for tokens, and for the structs
+/// holding them. It does not implement `Clone` or `Default`, which would
+/// allow a second token to be made, except for the `ReadOnly` kind, which
+/// can not be turned into a token.
+#[doc(hidden)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Unique<K = Exclusive>(core::marker::PhantomData<K>);
+
+/// The kind of `Unique` held by a token
+#[doc(hidden)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Exclusive;
+
+/// The kind of `Unique` held by a read-only handle, which may be copied
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReadOnly;
+
+impl Clone for Unique<ReadOnly> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for Unique<ReadOnly> {}
+
+impl<K> Unique<K> {
+    /// Create the field of a new token. This is used by the code generated
+    /// by `shared!`.
+    ///
+    /// # Safety
+    ///
+    /// The token struct is declared in the module invoking `shared!`, so its
+    /// field is visible there. This must only be called by the generated
+    /// code, once it has claimed the token, so that only one token exists,
+    /// or from the token, to make a read-only handle.
+    #[doc(hidden)]
+    pub const unsafe fn new() -> Self {
+        Unique(core::marker::PhantomData)
+    }
+}
+
+/// An RAII guard granting access to shared data from application context.
+///
+/// The corresponding interrupts are masked for as long as the guard is
+/// alive, and are unmasked when it is dropped.
+pub struct Guard<'a, T> {
+    data: &'a mut T,
+    flag: &'a AtomicBool,
+    masked: nvic::Masked,
+    core: nvic::CoreLock,
+}
+
+impl<'a, T> Guard<'a, T> {
+    /// Create a new guard. This is used by the code generated by `shared!`.
+    ///
+    /// The caller must have set the re-entrancy `flag` of the data, masked
+    /// the interrupts as described by `masked`, and taken the spinlock in
+    /// `core`. All of them are released when the guard is dropped.
+    #[doc(hidden)]
+    pub unsafe fn new(
+        data: &'a mut T,
+        flag: &'a AtomicBool,
+        masked: nvic::Masked,
+        core: nvic::CoreLock,
+    ) -> Self {
+        Guard {
+            data,
+            flag,
+            masked,
+            core,
+        }
+    }
+
+    /// Release the data, but leave the interrupts masked. The returned
+    /// masking must be undone later. This is used by the code generated by
+    /// `shared!`.
+    #[doc(hidden)]
+    pub fn hold(mut self) -> nvic::Masked {
+        self.flag.store(false, Ordering::SeqCst);
+        self.core.release();
+        let masked = self.masked;
+        core::mem::forget(self);
+        masked
+    }
+}
+
+impl<'a, T> core::ops::Deref for Guard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T> core::ops::DerefMut for Guard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T> Drop for Guard<'a, T> {
+    fn drop(&mut self) {
+        self.flag.store(false, Ordering::SeqCst);
+        self.core.release();
+        self.masked.unmask();
+    }
+}
+
+#[macro_export]
+macro_rules! shared {
+    (
+        @expand ($modvis:vis mod $ns:ident) $enum:tt
+        $([$(#[$cfg:meta])*] [$(#[$doc:meta])*] [$(#[$attr:meta])*] [$(#[$place:meta])*] [$vis:vis] [$($reader:ident)?] ($NAME:ident, $dat_ty:ty, $int:expr $(, $($opt:tt)*)?))+
+    ) => {
+        // The items are declared in the named module, where everything in
+        // scope of the macro is imported, and are then re-exported with the
+        // visibility of each entry
+        $modvis mod $ns {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $crate::shared!(
+                @expand () $enum
+                $([$(#[$cfg])*] [$(#[$doc])*] [$(#[$attr])*] [$(#[$place])*] [pub] [$($reader)?] ($NAME, $dat_ty, $int $(, $($opt)*)?))+
+            );
+        }
+
+        $(
+            $(#[$cfg])*
+            $vis use self::$ns::{$NAME $(, $reader)?};
+        )+
+    };
+    (
+        @expand () $enum:tt
+        $([$(#[$cfg:meta])*] [$(#[$doc:meta])*] [$(#[$attr:meta])*] [$(#[$place:meta])*] [$vis:vis] [$($reader:ident)?] ($NAME:ident, $dat_ty:ty, $int:expr $(, $($opt:tt)*)?))+
+    ) => {
+        // This is the primary interface to the shared data. The struct itself
+        // is actually an opaque zero sized type, with methods that grab data
+        // from the statics declared alongside its implementation. Each item
+        // keeps these in an anonymous constant of its own, so that several
+        // invocations of the macro may be used in the same module, and the
+        // types and interrupts are resolved in the scope the macro was used in
+        $crate::__shared_unique!($({[$(#[$cfg])*] $NAME})+);
+
+        $(
+            $(#[$cfg])*
+            $(#[$doc])*
+            #[doc = ""]
+            #[doc = ::core::concat!(
+                "The token for the `", ::core::stringify!($dat_ty), "` shared with `",
+                ::core::stringify!($int), "`, declared with `shared!`."
+            )]
+            $(#[$attr])*
+            $vis struct $NAME {
+                _private: $crate::Unique,
+            }
+
+            $crate::shared!(@reader [$(#[$cfg])*] $vis $NAME $dat_ty [$($reader)?]);
+
+            $(#[$cfg])*
+            const _: () = {
+                /// The interrupts guarding the item, and their configuration
+                struct __Guards;
+
+                unsafe impl $crate::Guards<$dat_ty> for __Guards {
+                    #[inline(always)]
+                    fn irqs(&self) -> $crate::nvic::IrqSet {
+                        $crate::__shared_initial!(
+                            @irqs ($crate::shared!(@interrupt $enum $int)) $($($opt)*)?
+                        )
+                    }
+
+                    #[inline(always)]
+                    fn priority(&self) -> Option<u8> {
+                        $crate::__shared_initial!(@priority $($($opt)*)?)
+                    }
+
+                    #[inline(always)]
+                    fn spinlock(&self) -> Option<u8> {
+                        $crate::__shared_initial!(@spinlock $($($opt)*)?)
+                    }
+
+                    fn is_lent(&self, data: &$dat_ty) -> bool {
+                        $crate::shared!(@lent data)
+                    }
+
+                    #[inline(always)]
+                    fn is_lock_free(&self) -> bool {
+                        $crate::shared!(@lock_free $dat_ty)
+                    }
+                }
+
+                /// The actual data structure that backs the shared data
+                $(#[$place])*
+                static __SHARED: $crate::Shared<$dat_ty, __Guards> =
+                    $crate::__shared_initial!(@new __Guards, $($($opt)*)?);
+
+                impl Drop for $NAME {
+                    fn drop(&mut self) {
+                        drop(::core::mem::ManuallyDrop::into_inner(self.token()));
+                    }
+                }
+
+                unsafe impl $crate::SharedItem for $NAME {
+                    type Data = $dat_ty;
+
+                    fn storage() -> *mut $dat_ty {
+                        __SHARED.storage()
+                    }
+
+                    fn is_initialized() -> bool {
+                        __SHARED.is_initialized()
+                    }
+
+                    fn init_with<F>(f: F) -> Result<Self, F>
+                    where
+                        F: FnOnce() -> $dat_ty,
+                    {
+                        Self::set_initial_with(f)
+                    }
+
+                    fn access_app_context<F, R>(&mut self, f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        self.token().modify_app_context(f)
+                    }
+
+                    fn access_int_context<F, R>(f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        __SHARED.modify_int_context(f)
+                    }
+
+                    unsafe fn reclaim_int_context<F, R>(f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        __SHARED.reclaim_int_context(f)
+                    }
+
+                    fn inspect_app_context<F, R>(&self, f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&$dat_ty) -> R,
+                    {
+                        __SHARED.inspect_app_context(f)
+                    }
+
+                    fn inspect_int_context<F, R>(f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&$dat_ty) -> R,
+                    {
+                        __SHARED.inspect_int_context(f)
+                    }
+                }
+
+                // Each method stands for the method of the same name of
+                // `Shared`, or of its `Token`, which this token stands for
+                $(#[$doc])*
+                impl $NAME {
+                    /// The static backing the item
+                    #[inline(always)]
+                    fn shared() -> &'static $crate::Shared<$dat_ty, __Guards> {
+                        &__SHARED
+                    }
+
+                    /// The token of the static, which is only dropped by the
+                    /// `Drop` implementation of this token
+                    #[inline(always)]
+                    fn token(&self) -> ::core::mem::ManuallyDrop<$crate::Token<'static, $dat_ty, __Guards>> {
+                        // NOTE(unsafe) this token was made from the token of the static
+                        ::core::mem::ManuallyDrop::new(unsafe { $crate::Token::new(&__SHARED) })
+                    }
+
+                    /// Stand for a token of the static that was just handed out
+                    fn claimed(token: $crate::Token<'static, $dat_ty, __Guards>) -> $NAME {
+                        ::core::mem::forget(token);
+                        $NAME { _private: unsafe { $crate::Unique::new() } }
+                    }
+
+                    /// Set the initial value of the shared data. This must be done
+                    /// from application context, not interrupt context.
+                    ///
+                    /// This function must be called before the `modify_*` methods
+                    /// can be used, otherwise they will return errors.
+                    pub fn set_initial(data: $dat_ty) -> Result<$NAME, $dat_ty> {
+                        __SHARED.set_initial(data).map(Self::claimed)
+                    }
+
+                    /// Set the initial value of the shared data to the value returned by
+                    /// the closure. This has the same requirements as `set_initial`.
+                    ///
+                    /// The closure is only called once it is known that initialization can
+                    /// succeed, and its result is written straight into the static, which
+                    /// avoids building large values on the stack first. If initialization
+                    /// is not possible, the closure is handed back.
+                    pub fn set_initial_with<F>(f: F) -> Result<$NAME, F>
+                    where
+                        F: FnOnce() -> $dat_ty,
+                    {
+                        __SHARED.set_initial_with(f).map(Self::claimed)
+                    }
+
+                    /// Set the initial value of the shared data from within a critical
+                    /// section, such as `cortex_m::interrupt::free`.
+                    ///
+                    /// Unlike `set_initial`, this may be called while the corresponding
+                    /// interrupt is enabled, as no interrupt can run until the critical
+                    /// section ends.
+                    pub fn set_initial_cs(data: $dat_ty, cs: &$crate::CriticalSection) -> Result<$NAME, $dat_ty> {
+                        __SHARED.set_initial_cs(data, cs).map(Self::claimed)
+                    }
+
+                    /// Set the initial value of the shared data, and then enable the
+                    /// corresponding interrupts.
+                    ///
+                    /// Like `set_initial`, this fails if any of the interrupts are already
+                    /// enabled. Core exceptions are not enabled. Taking the `NVIC` makes sure
+                    /// that nothing else is configuring interrupts at the same time.
+                    pub fn set_initial_nvic(data: $dat_ty, nvic: &mut $crate::nvic::Controller) -> Result<$NAME, $dat_ty> {
+                        __SHARED.set_initial_nvic(data, nvic).map(Self::claimed)
+                    }
+
+                    /// Set the initial value of the shared data, even if the corresponding
+                    /// interrupt is already enabled (for example by a bootloader).
+                    ///
+                    /// The interrupt is masked while the data is installed, in the same way
+                    /// as for `modify_app_context`, and is then unmasked.
+                    pub fn set_initial_masking(data: $dat_ty) -> Result<$NAME, $dat_ty> {
+                        __SHARED.set_initial_masking(data).map(Self::claimed)
+                    }
+
+                    /// Checks whether `set_initial_cs` would succeed. This is used by
+                    /// `init_all!`, and must be called from within a critical section.
+                    #[doc(hidden)]
+                    pub fn __can_init_masked() -> bool {
+                        __SHARED.__can_init_masked()
+                    }
+
+                    /// Check that a handler is installed for every corresponding interrupt,
+                    /// rather than `DefaultHandler`.
+                    ///
+                    /// The check is only made with the `check-handlers` feature, in which
+                    /// case `set_initial` and the other initializers also fail if this does.
+                    pub fn check_handler() -> Result<(), $crate::Error> {
+                        __SHARED.check_handler()
+                    }
+
+                    /// Reserve the token for the shared data without initializing it. The data
+                    /// is provided later with `fill`.
+                    ///
+                    /// This allows the token to be handed to a driver before the data itself
+                    /// exists. Until then, all accesses return `Error::NotFilled`. Returns
+                    /// `None` if the token has already been handed out, or the data is
+                    /// already initialized.
+                    pub fn reserve() -> Option<$NAME> {
+                        __SHARED.reserve().map(Self::claimed)
+                    }
+
+                    /// Provide the data for a token obtained with `reserve`. If the data
+                    /// has already been filled, it is handed back.
+                    ///
+                    /// The corresponding interrupt does not need to be disabled: it can not
+                    /// access the data until it has been completely written.
+                    pub fn fill(&mut self, data: $dat_ty) -> Result<(), $dat_ty> {
+                        self.token().fill(data)
+                    }
+
+                    /// Set the initial value of the shared data from within the corresponding
+                    /// interrupt, for data that originates there.
+                    ///
+                    /// No token is returned, as the interrupt has no use for it. Instead,
+                    /// application code may claim it afterwards with `take_token`. If this
+                    /// is not called from the corresponding interrupt, or the data has
+                    /// already been initialized, the data is handed back.
+                    pub fn set_initial_int_context(data: $dat_ty) -> Result<(), $dat_ty> {
+                        __SHARED.set_initial_int_context(data)
+                    }
+
+                    $crate::__shared_initial!(@pre_init $($($opt)*)?);
+
+                    /// Take the token for an item that was given an initial value in the
+                    /// `shared!` invocation, or with `set_initial_int_context`.
+                    ///
+                    /// Like `set_initial`, this only succeeds once, and only if the data has
+                    /// been initialized.
+                    pub fn take_token() -> Option<$NAME> {
+                        __SHARED.take_token().map(Self::claimed)
+                    }
+
+                    /// The corresponding interrupts which are currently enabled. This is
+                    /// used by `init_all!`.
+                    #[doc(hidden)]
+                    pub fn __enabled() -> $crate::nvic::IrqSet {
+                        __SHARED.irqs().enabled()
+                    }
+
+                    /// Checks whether `take_token` would succeed. This is used by the
+                    /// resources struct declared with `shared!`.
+                    #[doc(hidden)]
+                    pub fn __can_take() -> bool {
+                        __SHARED.__can_take()
+                    }
+
+                    /// Access the shared data from the application (non-interrupt) context.
+                    /// The interrupt must not be active when calling this function.
+                    ///
+                    /// During the scope of the closure, the corresponding interrupt will be
+                    /// disabled to prevent concurrent access. The value returned by the
+                    /// closure is passed back to the caller.
+                    pub fn modify_app_context<F, R>(&mut self, f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        self.token().modify_app_context(f)
+                    }
+
+                    /// Access the shared data from the application (non-interrupt) context,
+                    /// like `modify_app_context`, but leave the corresponding interrupt
+                    /// masked afterwards, until `release` is called.
+                    ///
+                    /// This may be called several times in a row, for example around
+                    /// writes to peripheral registers that the interrupt must not observe
+                    /// half way through. A single `release` unmasks the interrupt.
+                    pub fn modify_app_context_and_hold<F, R>(&mut self, f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        self.token().modify_app_context_and_hold(f)
+                    }
+
+                    /// Unmask the corresponding interrupt after `modify_app_context_and_hold`.
+                    /// This does nothing if the interrupt is not held.
+                    pub fn release(&mut self) {
+                        self.token().release()
+                    }
+
+                    /// Replace the shared data from the application (non-interrupt) context,
+                    /// returning the previous value.
+                    ///
+                    /// The corresponding interrupt will be disabled while the data is swapped.
+                    pub fn replace(&mut self, data: $dat_ty) -> Result<$dat_ty, $crate::Error> {
+                        self.token().replace(data)
+                    }
+
+                    /// Update the shared data by value from the application (non-interrupt)
+                    /// context.
+                    ///
+                    /// The current value is moved into the closure, and the value it returns
+                    /// is stored in its place. This is convenient for enum based state
+                    /// machines, where each transition consumes the previous state. The
+                    /// corresponding interrupt is disabled for the duration of the closure.
+                    pub fn update<F>(&mut self, f: F) -> Result<(), $crate::Error>
+                    where
+                        F: FnOnce($dat_ty) -> $dat_ty,
+                    {
+                        self.token().update(f)
+                    }
+
+                    /// Overwrite the shared data from the application (non-interrupt) context.
+                    ///
+                    /// The corresponding interrupt will be disabled while the data is written.
+                    /// The previous value is dropped after the interrupt has been restored.
+                    pub fn set(&mut self, data: $dat_ty) -> Result<(), $crate::Error> {
+                        self.token().set(data)
+                    }
+
+                    /// Recover the shared data from the application (non-interrupt) context,
+                    /// consuming the token.
+                    ///
+                    /// The corresponding interrupt is disabled, and is NOT re-enabled
+                    /// afterwards. This is intended for shutdown paths, where the interrupt
+                    /// is no longer needed.
+                    ///
+                    /// # Panics
+                    ///
+                    /// Panics if the corresponding interrupt is active, which can only
+                    /// happen if this is called from a higher priority interrupt, if
+                    /// the token was obtained with `reserve` and never filled, or if
+                    /// part of the data is lent out, such as to a `WriteGrant`.
+                    pub fn into_inner(self) -> $dat_ty {
+                        let token = ::core::mem::ManuallyDrop::into_inner(self.token());
+                        ::core::mem::forget(self);
+                        token.into_inner()
+                    }
+
+                    /// Tear down the shared data, consuming the token, so that `set_initial`
+                    /// may be called again.
+                    ///
+                    /// Like `into_inner`, this leaves the corresponding interrupt disabled,
+                    /// as required by `set_initial`. The old data is dropped. This is the
+                    /// same as dropping the token.
+                    ///
+                    /// # Panics
+                    ///
+                    /// Panics if the corresponding interrupt is active, which can only
+                    /// happen if this is called from a higher priority interrupt, or if
+                    /// part of the data is lent out, such as to a `WriteGrant`.
+                    pub fn reset(self) {
+                        drop(self);
+                    }
+
+                    /// Access the shared data from whichever context is currently running.
+                    ///
+                    /// The active vector is read from the SCB. In thread mode, this behaves
+                    /// like `modify_app_context`. Inside the corresponding interrupt, this
+                    /// behaves like `modify_int_context`. From any other interrupt or
+                    /// exception, `Error::WrongContext` is returned.
+                    ///
+                    /// This is useful for helper functions that are called from both
+                    /// contexts. Because no token is required, nested accesses from
+                    /// application context are rejected with `Error::Reentrant`.
+                    pub fn modify<F, R>(f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        __SHARED.modify(f)
+                    }
+
+                    /// Access the shared data from an interrupt other than the corresponding
+                    /// one.
+                    ///
+                    /// The corresponding interrupt is disabled for the duration of the closure,
+                    /// just like in `modify_app_context`. If the current interrupt has preempted
+                    /// the corresponding interrupt, it may be part way through an access, so
+                    /// `Error::InterruptActive` is returned. Calling this from application
+                    /// context or from the corresponding interrupt returns
+                    /// `Error::WrongContext`.
+                    ///
+                    /// On ARMv6-M, where preempted interrupts can not be detected, this always
+                    /// returns `Error::InterruptActive`.
+                    pub fn modify_from_other_int<F, R>(f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        __SHARED.modify_from_other_int(f)
+                    }
+
+                    /// Lock the shared data for access from the application (non-interrupt)
+                    /// context. The interrupt must not be active when calling this function.
+                    ///
+                    /// The corresponding interrupt will be disabled until the returned guard
+                    /// is dropped, at which point it will be re-enabled if necessary.
+                    pub fn lock_app_context(&mut self) -> Result<$crate::Guard<'_, $dat_ty>, $crate::Error> {
+                        __SHARED.lock()
+                    }
+
+                    /// Access the shared data from the application (non-interrupt) context,
+                    /// retrying while the interrupt is active until `deadline` has passed.
+                    ///
+                    /// `now` is called to read the current time from a monotonic time source,
+                    /// such as a `fugit` instant from a timer. If the interrupt is still
+                    /// active once `now() >= deadline`, `Error::Timeout` is returned.
+                    pub fn modify_app_context_until<N, I, F, R>(
+                        &mut self,
+                        now: N,
+                        deadline: I,
+                        f: F,
+                    ) -> Result<R, $crate::Error>
+                    where
+                        N: FnMut() -> I,
+                        I: PartialOrd,
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        self.token().modify_app_context_until(now, deadline, f)
+                    }
+
+                    /// Attempt to access the shared data from the application (non-interrupt)
+                    /// context, without treating contention as a failure.
+                    ///
+                    /// If the corresponding interrupt is currently active, the NVIC is left
+                    /// untouched and `Error::WouldBlock` is returned, so the caller may skip
+                    /// the access and retry later.
+                    pub fn try_modify_app_context<F, R>(&mut self, f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        self.token().try_modify_app_context(f)
+                    }
+
+                    /// Inspect the shared data from the application (non-interrupt) context.
+                    /// The interrupt must not be active when calling this function.
+                    ///
+                    /// This behaves like `modify_app_context`, but only hands out a shared
+                    /// reference to the data.
+                    pub fn read_app_context<F, R>(&self, f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&$dat_ty) -> R,
+                    {
+                        self.token().read_app_context(f)
+                    }
+
+                    /// Access the shared data from the interrupt context. This function will
+                    /// only work if the corresponding interrupt is currently active. This
+                    /// function is not re-entrant - you cannot grab the shared data more than
+                    /// once. The value returned by the closure is passed back to the caller.
+                    ///
+                    /// The data is marked as changed, for `take_if_changed`.
+                    pub fn modify_int_context<F, R>(f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        __SHARED.modify_int_context(f)
+                    }
+
+                    /// Inspect the shared data from the interrupt context. This function will
+                    /// only work if the corresponding interrupt is currently active.
+                    ///
+                    /// Unlike `modify_int_context`, this may be nested: any number of
+                    /// read-only accesses may be active at once, for example from a helper
+                    /// function called inside another `read_int_context` closure. Calling
+                    /// `modify_int_context` while a read is in progress (or vice versa)
+                    /// returns `Error::Reentrant`.
+                    pub fn read_int_context<F, R>(f: F) -> Result<R, $crate::Error>
+                    where
+                        F: FnOnce(&$dat_ty) -> R,
+                    {
+                        __SHARED.read_int_context(f)
+                    }
+
+                    /// Access the shared data from the application (non-interrupt) context,
+                    /// like `modify_app_context`, but only if it has been modified from
+                    /// interrupt context since the last call. Returns `Ok(None)` otherwise.
+                    ///
+                    /// Checking whether the data has changed does not touch the NVIC, so
+                    /// this is cheap to poll.
+                    pub fn take_if_changed<F, R>(&mut self, f: F) -> Result<Option<R>, $crate::Error>
+                    where
+                        F: FnOnce(&mut $dat_ty) -> R,
+                    {
+                        self.token().take_if_changed(f)
+                    }
+
+                    /// Store a function to be run on the shared data from interrupt context,
+                    /// and pend the corresponding interrupt. The function is run when the
+                    /// interrupt calls `run_deferred`.
+                    ///
+                    /// Only one call may be waiting at a time. If one already is,
+                    /// `Error::WouldBlock` is returned.
+                    pub fn defer(&mut self, f: fn(&mut $dat_ty)) -> Result<(), $crate::Error> {
+                        self.token().defer(f)
+                    }
+
+                    /// Run the function stored with `defer`, if any, from within the
+                    /// corresponding interrupt. Returns whether a function was run.
+                    ///
+                    /// This is intended to be called at the start or end of the interrupt
+                    /// handler.
+                    pub fn run_deferred() -> Result<bool, $crate::Error> {
+                        __SHARED.run_deferred()
+                    }
+
+                    /// Pend the corresponding interrupt, so that it runs as soon as it is
+                    /// enabled and not masked. This may be used to notify the interrupt of
+                    /// new data.
+                    pub fn pend(&self) {
+                        self.token().pend()
+                    }
+
+                    /// Clear the pending state of the corresponding interrupt
+                    pub fn unpend(&self) {
+                        self.token().unpend()
+                    }
+
+                    /// Is the corresponding interrupt pending?
+                    pub fn is_pending(&self) -> bool {
+                        self.token().is_pending()
+                    }
+
+                    /// Get a raw pointer to the shared data, skipping every check made by
+                    /// the other accessors.
+                    ///
+                    /// The NVIC is not read or written, and the re-entrancy flag is not
+                    /// touched. This is intended for the hottest interrupt handlers, where
+                    /// exclusive access can be proven by other means.
+                    ///
+                    /// # Safety
+                    ///
+                    /// `set_initial` must have been called, and the caller must guarantee
+                    /// that no other access to the data (checked or unchecked) takes place
+                    /// while the pointer is in use.
+                    pub unsafe fn access_unchecked() -> *mut $dat_ty {
+                        __SHARED.access_unchecked()
+                    }
+                }
+            };
+        )+
+    };
+    (@reader $cfg:tt $vis:vis $NAME:ident $dat_ty:ty []) => {};
+    (@reader [$(#[$cfg:meta])*] $vis:vis $NAME:ident $dat_ty:ty [$reader:ident]) => {
+        $(#[$cfg])*
+        #[doc = ::core::concat!(
+            "A read-only handle to the data of `", ::core::stringify!($NAME),
+            "`, created with `", ::core::stringify!($NAME), "::reader`."
+        )]
+        #[doc = ""]
+        #[doc = "It may be copied freely, such as into telemetry or display modules, but can"]
+        #[doc = "not modify the data, nor be turned back into the token."]
+        #[derive(Clone, Copy)]
+        $vis struct $reader {
+            _private: $crate::Unique<$crate::ReadOnly>,
+        }
+
+        $(#[$cfg])*
+        impl $reader {
+            /// Inspect the shared data from the application (non-interrupt) context.
+            /// This is the same as `read_app_context` of the token.
+            pub fn read_app_context<F, R>(&self, f: F) -> Result<R, $crate::Error>
+            where
+                F: FnOnce(&$dat_ty) -> R,
+            {
+                $NAME::shared().inspect_app_context(f)
+            }
+
+            /// Inspect the shared data from whichever context is currently running,
+            /// as with `modify` of the token type. From any interrupt other than the
+            /// corresponding ones, `Error::WrongContext` is returned.
+            pub fn read<F, R>(&self, f: F) -> Result<R, $crate::Error>
+            where
+                F: FnOnce(&$dat_ty) -> R,
+            {
+                $NAME::shared().read(f)
+            }
+        }
+
+        $(#[$cfg])*
+        impl $NAME {
+            /// Create a read-only handle to the shared data, which may be copied and
+            /// handed out freely
+            pub fn reader(&self) -> $reader {
+                // NOTE(unsafe) a handle is only made from the token
+                $reader { _private: unsafe { $crate::Unique::new() } }
+            }
+        }
+    };
+    (@lent $data:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::lend::{ProbeLent as _, ProbeNever as _};
+        (&$crate::lend::Probe($data)).is_lent()
+    }};
+    (@lock_free $dat_ty:ty) => {{
+        #[allow(unused_imports)]
+        use $crate::lend::{ProbeLocked as _, ProbeLockFree as _};
+        (&$crate::lend::Kind::<$dat_ty>(::core::marker::PhantomData)).is_lock_free()
+    }};
+    (@interrupt () $int:expr) => {
+        $int
+    };
+    (@interrupt ($enum:path) $int:expr) => {{
+        // The variants of the interrupt enum may be named on their own
+        #[allow(unused_imports)]
+        use $enum::*;
+        $int
+    }};
+    ($($entries:tt)+) => {
+        $crate::__shared_entries!(@header [() () [] ()] $($entries)+);
+    };
+}
+
+/// Translates the entries of `shared!` into its items, one at a time.
+/// Entries may be written as tuples, or in the block form:
+///
+/// ```rust,ignore
+/// shared! {
+///     /// Packets received by the radio
+///     pub RADIO_PKTS: usize => Interrupt::RADIO,
+///     pub(crate) TICKS: u32 = 0 => Interrupt::TIMER0,
+///     EVENTS: u32 = 0 => Interrupt::GPIOTE, priority = 3 << 5,
+/// }
+/// ```
+///
+/// The `cfg` attributes of each entry are applied to everything generated
+/// for it, so that an item may only exist in some configurations. A
+/// `link_section` attribute places the static holding its data, such as in
+/// a RAM bank reachable by DMA. Its doc comments are applied to its token,
+/// and to the methods of the token, and its other attributes to the token,
+/// as is its visibility.
+///
+/// The entries are collected after the module they are to be declared in,
+/// the enum their interrupts are variants of, the inner attributes of the
+/// invocation, and the struct bundling their tokens. The inner attributes
+/// are applied to every token, as if they were given for each entry, and
+/// the others are `()` if they were not given.
+///
+/// Unlike tuples, whose tokens are always `pub`, an entry in the block form
+/// without a visibility is private to the module the macro was used in. The
+/// options of an entry follow its interrupt and initial value, in any
+/// order. The trailing commas of entries, and of the elements of tuples,
+/// may be left out. Malformed entries, such as those missing a comma or
+/// given an unknown option, are reported with a `compile_error!` naming
+/// the item.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __shared_entries {
+    // The module, interrupt enum, inner attributes and resources struct come
+    // first, in that order
+    (@header [() () [] ()] $modvis:vis mod $ns:ident; $($rest:tt)+) => {
+        $crate::__shared_entries!(@header [($modvis mod $ns) () [] ()] $($rest)+);
+    };
+    (@header [$ns:tt () [] ()] interrupts = $enum:path; $($rest:tt)+) => {
+        $crate::__shared_entries!(@header [$ns ($enum) [] ()] $($rest)+);
+    };
+    (@header [$ns:tt $enum:tt [$($global:tt)*] ()] #![$attr:meta] $($rest:tt)+) => {
+        $crate::__shared_entries!(@header [$ns $enum [$($global)* #[$attr]] ()] $($rest)+);
+    };
+    (
+        @header [$ns:tt $enum:tt $global:tt ()]
+        $(#[$attr:meta])* $vis:vis struct $res:ident; $($rest:tt)+
+    ) => {
+        $crate::__shared_entries!(
+            @header [$ns $enum $global ($(#[$attr])* $vis struct $res)] $($rest)+
+        );
+    };
+    (@header [$($header:tt)*] $($rest:tt)+) => {
+        $crate::__shared_entries!([$($header)*] $($rest)+);
+    };
+
+    ([$ns:tt $enum:tt $global:tt $res:tt $($done:tt)*]) => {
+        $crate::shared!(@expand $ns $enum $($done)*);
+        $crate::__shared_resources!($res $($done)*);
+    };
+    ([$ns:tt $enum:tt $global:tt $res:tt $($done:tt)*] $($rest:tt)+) => {
+        $crate::__shared_entries!(
+            @attrs [$ns $enum $global $res $($done)*] [] [] $global [] $($rest)+
+        );
+    };
+    (
+        @attrs $done:tt [$($cfg:tt)*] $docs:tt $attrs:tt $place:tt
+        #[cfg($($pred:tt)*)] $($rest:tt)+
+    ) => {
+        $crate::__shared_entries!(
+            @attrs $done [$($cfg)* #[cfg($($pred)*)]] $docs $attrs $place $($rest)+
+        );
+    };
+    (
+        @attrs $done:tt $cfg:tt [$($docs:tt)*] $attrs:tt $place:tt
+        #[doc = $doc:expr] $($rest:tt)+
+    ) => {
+        $crate::__shared_entries!(
+            @attrs $done $cfg [$($docs)* #[doc = $doc]] $attrs $place $($rest)+
+        );
+    };
+    (
+        @attrs $done:tt $cfg:tt $docs:tt $attrs:tt [$($place:tt)*]
+        #[link_section = $section:literal] $($rest:tt)+
+    ) => {
+        $crate::__shared_entries!(
+            @attrs $done $cfg $docs $attrs [$($place)* #[link_section = $section]] $($rest)+
+        );
+    };
+    (
+        @attrs $done:tt $cfg:tt $docs:tt [$($attrs:tt)*] $place:tt
+        #[$attr:meta] $($rest:tt)+
+    ) => {
+        $crate::__shared_entries!(
+            @attrs $done $cfg $docs [$($attrs)* #[$attr]] $place $($rest)+
+        );
+    };
+    (
+        @attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt
+        ($NAME:ident, $dat_ty:ty, $int:expr $(, $($opt:tt)*)?) $(, $($rest:tt)*)?
+    ) => {
+        $crate::__shared_entries!(
+            @init $done $cfg $docs $attrs $place [pub] ($NAME, $dat_ty, $int)
+            (tuple [$($($rest)*)?]) $($($opt)*)?
+        );
+    };
+    (@attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt ($NAME:ident, $dat_ty:ty $(,)?) $($rest:tt)*) => {
+        ::core::compile_error!(::core::concat!(
+            "the shared item `", ::core::stringify!($NAME), "` is missing its interrupt, as in `(",
+            ::core::stringify!($NAME), ", ", ::core::stringify!($dat_ty), ", Interrupt::RADIO)`"
+        ));
+    };
+    (@attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt ($NAME:ident $(,)?) $($rest:tt)*) => {
+        ::core::compile_error!(::core::concat!(
+            "the shared item `", ::core::stringify!($NAME), "` is missing its type and interrupt, ",
+            "as in `(", ::core::stringify!($NAME), ", u32, Interrupt::RADIO)`"
+        ));
+    };
+    (
+        @attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt
+        ($NAME:ident, $dat_ty:ty, $int:expr $(, $($opt:tt)*)?) $unexpected:tt $($rest:tt)*
+    ) => {
+        ::core::compile_error!(::core::concat!(
+            "expected `,` after the shared item `", ::core::stringify!($NAME), "`, found `",
+            ::core::stringify!($unexpected), "`"
+        ));
+    };
+    (@attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt ($NAME:ident $($item:tt)*) $($rest:tt)*) => {
+        ::core::compile_error!(::core::concat!(
+            "the shared item `", ::core::stringify!($NAME), "` is malformed, expected its name, ",
+            "type and interrupt separated by commas, as in `(", ::core::stringify!($NAME),
+            ", u32, Interrupt::RADIO)`"
+        ));
+    };
+    (
+        @attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt
+        $vis:vis $NAME:ident : $dat_ty:ty = $init:expr => $int:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::__shared_entries!(
+            @options $done $cfg $docs $attrs $place [$vis] ($NAME, $dat_ty, $int, $init) [] [] [] []
+            (block) $($($rest)*)?
+        );
+    };
+    (
+        @attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt
+        $vis:vis $NAME:ident : $dat_ty:ty => $int:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::__shared_entries!(
+            @options $done $cfg $docs $attrs $place [$vis] ($NAME, $dat_ty, $int) [] [] [] []
+            (block) $($($rest)*)?
+        );
+    };
+    (
+        @attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt
+        $vis:vis $NAME:ident : $dat_ty:ty $(= $init:expr)? => $($rest:tt)+
+    ) => {
+        ::core::compile_error!(::core::concat!(
+            "expected `,` after the interrupt of the shared item `", ::core::stringify!($NAME),
+            "`, before its options or the next item"
+        ));
+    };
+    (
+        @attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt
+        $vis:vis $NAME:ident : $dat_ty:ty $(= $init:expr)? $(, $($rest:tt)*)?
+    ) => {
+        ::core::compile_error!(::core::concat!(
+            "the shared item `", ::core::stringify!($NAME), "` is missing its interrupt, as in `",
+            ::core::stringify!($NAME), ": ", ::core::stringify!($dat_ty), " => Interrupt::RADIO`"
+        ));
+    };
+    (@attrs $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $unexpected:tt $($rest:tt)*) => {
+        ::core::compile_error!(::core::concat!(
+            "expected a shared item, as in `(NAME, u32, Interrupt::RADIO)` or ",
+            "`NAME: u32 => Interrupt::RADIO`, found `", ::core::stringify!($unexpected), "`"
+        ));
+    };
+
+    // The initial value of a tuple is the element following its interrupt,
+    // unless that is already one of the options
+    (@init $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt $item:tt $mode:tt) => {
+        $crate::__shared_entries!(@options $done $cfg $docs $attrs $place $vis $item [] [] [] [] $mode);
+    };
+    (
+        @init $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt $item:tt $mode:tt
+        $option:ident = $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(
+            @options $done $cfg $docs $attrs $place $vis $item [] [] [] [] $mode $option = $($rest)*
+        );
+    };
+    (
+        @init $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($($item:tt)*) $mode:tt
+        $init:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::__shared_entries!(
+            @options $done $cfg $docs $attrs $place $vis ($($item)*, $init) [] [] [] [] $mode $($($rest)*)?
+        );
+    };
+
+    // The options may be given in any order, but each only once. They are
+    // collected separately, and passed on in the order `__shared_initial!`
+    // expects
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        [$($prio:tt)+] $lock:tt $ctl:tt $reader:tt $mode:tt priority = $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(@twice $NAME priority);
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        $prio:tt [$($lock:tt)+] $ctl:tt $reader:tt $mode:tt spinlock = $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(@twice $NAME spinlock);
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        $prio:tt $lock:tt [$($ctl:tt)+] $reader:tt $mode:tt controller = $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(@twice $NAME controller);
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        $prio:tt $lock:tt $ctl:tt [$($reader:tt)+] $mode:tt reader = $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(@twice $NAME reader);
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt $item:tt
+        [] $lock:tt $ctl:tt $reader:tt $mode:tt priority = $prio:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::__shared_entries!(
+            @options $done $cfg $docs $attrs $place $vis $item [, priority = $prio] $lock $ctl $reader $mode
+            $($($rest)*)?
+        );
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt $item:tt
+        $prio:tt [] $ctl:tt $reader:tt $mode:tt spinlock = $lock:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::__shared_entries!(
+            @options $done $cfg $docs $attrs $place $vis $item $prio [, spinlock = $lock] $ctl $reader $mode
+            $($($rest)*)?
+        );
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt $item:tt
+        $prio:tt $lock:tt [] $reader:tt $mode:tt controller = $ctl:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::__shared_entries!(
+            @options $done $cfg $docs $attrs $place $vis $item $prio $lock [, controller = $ctl] $reader $mode
+            $($($rest)*)?
+        );
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt $item:tt
+        $prio:tt $lock:tt $ctl:tt [] $mode:tt reader = $reader:ident $(, $($rest:tt)*)?
+    ) => {
+        $crate::__shared_entries!(
+            @options $done $cfg $docs $attrs $place $vis $item $prio $lock $ctl [$reader] $mode
+            $($($rest)*)?
+        );
+    };
+
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        $prio:tt $lock:tt $ctl:tt $reader:tt $mode:tt priority = $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(@comma $NAME priority);
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        $prio:tt $lock:tt $ctl:tt $reader:tt $mode:tt spinlock = $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(@comma $NAME spinlock);
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        $prio:tt $lock:tt $ctl:tt $reader:tt $mode:tt controller = $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(@comma $NAME controller);
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        $prio:tt $lock:tt $ctl:tt $reader:tt $mode:tt reader = $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(@comma $NAME reader);
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        $prio:tt $lock:tt $ctl:tt $reader:tt $mode:tt $option:ident = $($rest:tt)*
+    ) => {
+        ::core::compile_error!(::core::concat!(
+            "unknown option `", ::core::stringify!($option), "` for the shared item `",
+            ::core::stringify!($NAME), "`, expected `priority`, `spinlock`, `controller` or `reader`"
+        ));
+    };
+
+    // A tuple ends with its last option, and an entry of the block form
+    // with the first token which does not start an option
+    (
+        @options [$($done:tt)*] $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($($item:tt)*)
+        [$($prio:tt)*] [$($lock:tt)*] [$($ctl:tt)*] $reader:tt (tuple [$($rest:tt)*])
+    ) => {
+        $crate::__shared_entries!(
+            [$($done)* $cfg $docs $attrs $place $vis $reader ($($item)* $($prio)* $($lock)* $($ctl)*)] $($rest)*
+        );
+    };
+    (
+        @options $done:tt $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($NAME:ident $($item:tt)*)
+        $prio:tt $lock:tt $ctl:tt $reader:tt (tuple $outer:tt) $unexpected:tt $($rest:tt)*
+    ) => {
+        ::core::compile_error!(::core::concat!(
+            "unexpected `", ::core::stringify!($unexpected), "` in the shared item `",
+            ::core::stringify!($NAME), "`, expected `priority = ..`, `spinlock = ..`, ",
+            "`controller = ..` or `reader = ..`"
+        ));
+    };
+    (
+        @options [$($done:tt)*] $cfg:tt $docs:tt $attrs:tt $place:tt $vis:tt ($($item:tt)*)
+        [$($prio:tt)*] [$($lock:tt)*] [$($ctl:tt)*] $reader:tt (block) $($rest:tt)*
+    ) => {
+        $crate::__shared_entries!(
+            [$($done)* $cfg $docs $attrs $place $vis $reader ($($item)* $($prio)* $($lock)* $($ctl)*)] $($rest)*
+        );
+    };
+    (@comma $NAME:ident $option:ident) => {
+        ::core::compile_error!(::core::concat!(
+            "expected `,` after `", ::core::stringify!($option), "` of the shared item `",
+            ::core::stringify!($NAME), "`, before its other options or the next item"
+        ));
+    };
+    (@twice $NAME:ident $option:ident) => {
+        ::core::compile_error!(::core::concat!(
+            "`", ::core::stringify!($option), "` is given more than once for the shared item `",
+            ::core::stringify!($NAME), "`"
+        ));
+    };
+}
+
+/// Reports the items of a `shared!` invocation which share a name, before
+/// the many errors about their conflicting implementations.
+///
+/// Every item is compared with the items following it by a macro of its
+/// own, which only matches its name. Each comparison is subject to the
+/// `cfg` attributes of both items, so that an item may be declared
+/// differently in exclusive configurations.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __shared_unique {
+    () => {};
+    ({[$(#[$cfg:meta])*] $first:ident} $({[$(#[$rcfg:meta])*] $rest:ident})*) => {
+        $(#[$cfg])*
+        const _: () = {
+            #[allow(unused_macros)]
+            macro_rules! __shared_same {
+                ($first) => {
+                    ::core::compile_error!(::core::concat!(
+                        "the shared item `", ::core::stringify!($first),
+                        "` is declared more than once"
+                    ));
+                };
+                $(($rest) => {};)*
+            }
+
+            $(
+                $(#[$rcfg])*
+                __shared_same!($rest);
+            )*
+        };
+
+        $crate::__shared_unique!($({[$(#[$rcfg])*] $rest})*);
+    };
+}
+
+/// Declares the struct bundling the tokens of every item of a `shared!`
+/// invocation, if one was named, sorting the items by whether they were
+/// given an initial value.
+///
+/// ```rust,ignore
+/// shared! {
+///     pub struct Resources;
+///
+///     pub RADIO_PKTS: usize => Interrupt::RADIO,
+///     pub TICKS: u32 = 0 => Interrupt::TIMER0,
+/// }
+///
+/// let Resources { RADIO_PKTS: pkts, TICKS: ticks } = Resources::init(0).unwrap();
+/// ```
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __shared_resources {
+    (() $($items:tt)*) => {};
+    (($($res:tt)+) $($items:tt)*) => {
+        $crate::__shared_resources!(@sort ($($res)+) [] [] [] $($items)*);
+    };
+    (
+        @sort $res:tt [$($fields:tt)*] [$($params:tt)*] $taken:tt
+        [$(#[$cfg:meta])*] $docs:tt $attrs:tt $place:tt [$vis:vis] $reader:tt
+        ($NAME:ident, $dat_ty:ty, $int:expr $(, $option:ident = $($opt:tt)*)?)
+        $($rest:tt)*
+    ) => {
+        $crate::__shared_resources!(
+            @sort $res
+            [$($fields)* $(#[$cfg])* $vis $NAME: $NAME,]
+            [$($params)* {[$(#[$cfg])*] $NAME: $dat_ty}]
+            $taken
+            $($rest)*
+        );
+    };
+    (
+        @sort $res:tt [$($fields:tt)*] $params:tt [$($taken:tt)*]
+        [$(#[$cfg:meta])*] $docs:tt $attrs:tt $place:tt [$vis:vis] $reader:tt
+        ($NAME:ident, $dat_ty:ty, $int:expr, $($opt:tt)*)
+        $($rest:tt)*
+    ) => {
+        $crate::__shared_resources!(
+            @sort $res
+            [$($fields)* $(#[$cfg])* $vis $NAME: $NAME,]
+            $params
+            [$($taken)* {[$(#[$cfg])*] $NAME}]
+            $($rest)*
+        );
+    };
+    (
+        @sort ($(#[$attr:meta])* $vis:vis struct $res:ident)
+        [$($fields:tt)*]
+        [$({[$(#[$pcfg:meta])*] $param:ident: $param_ty:ty})*]
+        [$({[$(#[$tcfg:meta])*] $taken:ident})*]
+    ) => {
+        $(#[$attr])*
+        #[allow(non_snake_case)]
+        $vis struct $res {
+            $($fields)*
+        }
+
+        impl $res {
+            /// Take the tokens of every item at once, within a single critical
+            /// section. The items without an initial value are initialized with
+            /// the arguments, in the order they were declared.
+            ///
+            /// As with `init_all!`, either every token is taken, or none of them
+            /// are and `None` is returned.
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            pub fn init($($(#[$pcfg])* $param: $param_ty),*) -> Option<Self> {
+                $crate::__interrupt_free(|cs| {
+                    $(
+                        $(#[$pcfg])*
+                        {
+                            if !<$param>::__can_init_masked() {
+                                return None;
+                            }
+                        }
+                    )*
+                    $(
+                        $(#[$tcfg])*
+                        {
+                            if !<$taken>::__can_take() {
+                                return None;
+                            }
+                        }
+                    )*
+                    Some($res {
+                        $($(#[$pcfg])* $param: <$param>::set_initial_cs($param, cs).ok()?,)*
+                        $($(#[$tcfg])* $taken: <$taken>::take_token()?,)*
+                    })
+                })
+            }
+        }
+    };
+}
+
+/// Interprets the optional elements of a `shared!` item, after the
+/// interrupt: an initial value, then `priority = <expr>`, then
+/// `spinlock = <expr>`, and then `controller = <expr>`.
+///
+/// Produces the `Shared` value of the static backing the item, its
+/// `pre_init` function, the priority of its interrupts, its spinlock, and
+/// the external controller of its interrupts.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __shared_initial {
+    (@new $guards:ident,) => {
+        $crate::Shared::new($guards)
+    };
+    (@new $guards:ident, priority = $($rest:tt)*) => {
+        $crate::Shared::new($guards)
+    };
+    (@new $guards:ident, spinlock = $($rest:tt)*) => {
+        $crate::Shared::new($guards)
+    };
+    (@new $guards:ident, controller = $($rest:tt)*) => {
+        $crate::Shared::new($guards)
+    };
+    (@new $guards:ident, $init:expr $(, $($rest:tt)*)?) => {
+        $crate::Shared::with_initial($guards, $init)
+    };
+    (@priority) => {
+        ::core::option::Option::None::<u8>
+    };
+    (@priority priority = $prio:expr $(, $($rest:tt)*)?) => {
+        ::core::option::Option::Some::<u8>($prio)
+    };
+    (@priority spinlock = $($rest:tt)*) => {
+        ::core::option::Option::None::<u8>
+    };
+    (@priority controller = $($rest:tt)*) => {
+        ::core::option::Option::None::<u8>
+    };
+    (@priority $init:expr $(, $($rest:tt)*)?) => {
+        $crate::__shared_initial!(@priority $($($rest)*)?)
+    };
+    (@spinlock) => {
+        ::core::option::Option::None::<u8>
+    };
+    (@spinlock spinlock = $lock:expr $(, $($rest:tt)*)?) => {
+        ::core::option::Option::Some::<u8>($lock)
+    };
+    (@spinlock controller = $($rest:tt)*) => {
+        ::core::option::Option::None::<u8>
+    };
+    (@spinlock priority = $prio:expr $(, $($rest:tt)*)?) => {
+        $crate::__shared_initial!(@spinlock $($($rest)*)?)
+    };
+    (@spinlock $init:expr $(, $($rest:tt)*)?) => {
+        $crate::__shared_initial!(@spinlock $($($rest)*)?)
+    };
+    (@irqs $int:tt) => {{
+        #[allow(unused_imports)]
+        use $crate::nvic::Single;
+        $crate::nvic::Interrupts($int).set()
+    }};
+    (@irqs $int:tt controller = $controller:expr $(,)?) => {
+        $crate::nvic::Lines($int).set($controller)
+    };
+    (@irqs $int:tt priority = $prio:expr $(, $($rest:tt)*)?) => {
+        $crate::__shared_initial!(@irqs $int $($($rest)*)?)
+    };
+    (@irqs $int:tt spinlock = $lock:expr $(, $($rest:tt)*)?) => {
+        $crate::__shared_initial!(@irqs $int $($($rest)*)?)
+    };
+    (@irqs $int:tt $init:expr $(, $($rest:tt)*)?) => {
+        $crate::__shared_initial!(@irqs $int $($($rest)*)?)
+    };
+    (@pre_init) => {};
+    (@pre_init priority = $($rest:tt)*) => {};
+    (@pre_init spinlock = $($rest:tt)*) => {};
+    (@pre_init controller = $($rest:tt)*) => {};
+    (@pre_init $init:expr $(, $($rest:tt)*)?) => {
+        /// Initialize the shared data with the initial value given in the
+        /// `shared!` invocation, before RAM has been initialized.
+        ///
+        /// This is intended to be called from a `cortex-m-rt` `#[pre_init]`
+        /// function, so that interrupts left enabled by a bootloader or
+        /// softdevice can never observe uninitialized data, even while the
+        /// `.data` and `.bss` sections are being initialized. The runtime
+        /// will then write the same initial values again.
+        ///
+        /// # Safety
+        ///
+        /// This must only be called before `.data` and `.bss` have been
+        /// initialized. Any changes made to the data by interrupts before
+        /// `main` is reached are overwritten by the runtime.
+        pub unsafe fn pre_init() {
+            __SHARED.pre_init($init)
+        }
+    };
+}
+
+/// Initialize several `shared` data items at once, within a single
+/// critical section.
+///
+/// Either every item is initialized, and a tuple of their tokens is
+/// returned, or none of them are and `None` is returned. No interrupt can
+/// observe a partially initialized system. The data expressions are only
+/// evaluated once every item is known to be ready for initialization.
+///
+/// ```rust,ignore
+/// let (pkts, clock) = init_all!(
+///     (RADIO_PKTS, 0),
+///     (WALL_CLOCK, 0),
+/// ).unwrap();
+/// ```
+#[macro_export]
+macro_rules! init_all {
+    ($(($item:ty, $data:expr)),+ $(,)?) => {
+        $crate::__interrupt_free(|cs| {
+            if !($(<$item>::__can_init_masked())&&+) {
+                return None;
+            }
+
+            // An item listed twice is only refused once its first entry has
+            // been initialized. Dropping the tokens made so far then disables
+            // their interrupts, which are enabled again if they were before.
+            let enabled = [$(<$item>::__enabled()),+];
+            let tokens = (|| Some(($(<$item>::set_initial_cs($data, cs).ok()?,)+)))();
+            if tokens.is_none() {
+                for irqs in &enabled {
+                    irqs.enable();
+                }
+            }
+            tokens
+        })
+    };
+}
+
+/// Access several `shared` data items at once from application context.
+///
+/// All of the corresponding interrupts are disabled before the closure is
+/// run, giving a consistent view of every item, and are restored to their
+/// previous state afterwards. The value returned by the closure is passed
+/// back to the caller.
+///
+/// ```rust,ignore
+/// let mut pkts = RADIO_PKTS::set_initial(0).unwrap();
+/// let mut clock = WALL_CLOCK::set_initial(0).unwrap();
+///
+/// let rate = with_all!((pkts, clock) => |p, c| {
+///     *p / *c
+/// }).unwrap();
+/// ```
+#[macro_export]
+macro_rules! with_all {
+    (($($token:expr),+ $(,)?) => |$($arg:ident),+ $(,)?| $body:expr) => {{
+        let mut locked = || -> ::core::result::Result<_, $crate::Error> {
+            $(
+                let mut $arg = $token.lock_app_context()?;
+            )+
+            $(
+                let $arg: &mut _ = &mut *$arg;
+            )+
+            Ok($body)
+        };
+        locked()
+    }};
+}
@@ -0,0 +1,228 @@
+//! Small utilities ticked by an interrupt and queried by the application:
+//! rate limiting and debouncing
+
+use crate::{Error, SharedItem};
+
+/// A token bucket, refilled by the ticks of an interrupt, which limits how
+/// often the application may do something.
+///
+/// This is used as the data type of a `shared` item guarded by the
+/// ticking interrupt, and is accessed through the `RateLimit` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     // Bursts of up to 5 messages, and one more every 100 ticks
+///     (UPLINK, shared::TokenBucket, Interrupt::RTC0, shared::TokenBucket::new(5, 100)),
+/// );
+///
+/// #[interrupt]
+/// fn RTC0() {
+///     UPLINK::tick_int_context().unwrap();
+/// }
+///
+/// if token.try_acquire().unwrap() {
+///     send_message();
+/// }
+/// ```
+pub struct TokenBucket {
+    tokens: u32,
+    capacity: u32,
+    period: u32,
+    ticks: u32,
+}
+
+impl TokenBucket {
+    /// Create a new, full bucket holding up to `capacity` tokens, which
+    /// gains a token every `period` ticks
+    pub const fn new(capacity: u32, period: u32) -> Self {
+        assert!(period != 0, "a token bucket must refill");
+        TokenBucket {
+            tokens: capacity,
+            capacity,
+            period,
+            ticks: 0,
+        }
+    }
+
+    /// The number of tokens currently in the bucket
+    pub fn tokens(&self) -> u32 {
+        self.tokens
+    }
+
+    /// Advance by one tick, adding a token every `period` ticks
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+        if self.ticks >= self.period {
+            self.ticks = 0;
+            self.tokens = (self.tokens + 1).min(self.capacity);
+        }
+    }
+
+    /// Take `count` tokens, if the bucket holds that many
+    pub fn try_acquire_n(&mut self, count: u32) -> bool {
+        match self.tokens.checked_sub(count) {
+            Some(left) => {
+                self.tokens = left;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Taking tokens from a shared `TokenBucket`
+pub trait RateLimit: SharedItem<Data = TokenBucket> {
+    /// Take a token from application context, returning whether there was
+    /// one
+    fn try_acquire(&mut self) -> Result<bool, Error>;
+
+    /// Take `count` tokens at once from application context, returning
+    /// whether there were that many. No tokens are taken otherwise.
+    fn try_acquire_n(&mut self, count: u32) -> Result<bool, Error>;
+
+    /// Advance the bucket by one tick from the corresponding interrupt
+    fn tick_int_context() -> Result<(), Error>;
+}
+
+impl<S> RateLimit for S
+where
+    S: SharedItem<Data = TokenBucket>,
+{
+    fn try_acquire(&mut self) -> Result<bool, Error> {
+        self.try_acquire_n(1)
+    }
+
+    fn try_acquire_n(&mut self, count: u32) -> Result<bool, Error> {
+        self.access_app_context(|bucket| bucket.try_acquire_n(count))
+    }
+
+    fn tick_int_context() -> Result<(), Error> {
+        S::access_int_context(TokenBucket::tick)
+    }
+}
+
+/// A change of the debounced state of an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// The input became active
+    Pressed,
+    /// The input became inactive
+    Released,
+}
+
+/// The debounced state of an input, such as a button, sampled by the ticks
+/// of an interrupt.
+///
+/// This is used as the data type of a `shared` item guarded by the
+/// sampling interrupt, and is accessed through the `Debounced` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     // The button must read the same for 20 ticks in a row
+///     (BUTTON, shared::Debounce, Interrupt::TIMER1, shared::Debounce::new(20)),
+/// );
+///
+/// #[interrupt]
+/// fn TIMER1() {
+///     BUTTON::sample_int_context(button_pin_is_low()).unwrap();
+/// }
+///
+/// if let Some(shared::Edge::Pressed) = token.take_edge().unwrap() {
+///     next_menu_item();
+/// }
+/// ```
+///
+/// Only the last edge is kept until the application takes it, so a press
+/// and release between two queries is reported as the release.
+pub struct Debounce {
+    stable: bool,
+    candidate: bool,
+    count: u32,
+    window: u32,
+    edge: Option<Edge>,
+}
+
+impl Debounce {
+    /// Create a new, released input, which changes state once `window`
+    /// samples in a row disagree with it
+    pub const fn new(window: u32) -> Self {
+        Debounce {
+            stable: false,
+            candidate: false,
+            count: 0,
+            window,
+            edge: None,
+        }
+    }
+
+    /// Whether the input is pressed, once debounced
+    pub fn is_pressed(&self) -> bool {
+        self.stable
+    }
+
+    /// Add a sample of the raw input, returning the edge it completed, if
+    /// any
+    pub fn sample(&mut self, pressed: bool) -> Option<Edge> {
+        if pressed == self.stable {
+            self.count = 0;
+            return None;
+        }
+
+        if pressed != self.candidate {
+            self.candidate = pressed;
+            self.count = 0;
+        }
+
+        self.count += 1;
+        if self.count < self.window {
+            return None;
+        }
+
+        self.stable = pressed;
+        self.count = 0;
+        let edge = if pressed {
+            Edge::Pressed
+        } else {
+            Edge::Released
+        };
+        self.edge = Some(edge);
+        Some(edge)
+    }
+
+    /// Take the last edge, if it was not taken yet
+    pub fn take_edge(&mut self) -> Option<Edge> {
+        self.edge.take()
+    }
+}
+
+/// Sampling and querying a shared `Debounce`
+pub trait Debounced: SharedItem<Data = Debounce> {
+    /// Whether the input is pressed, once debounced, checked from
+    /// application context
+    fn is_pressed(&mut self) -> Result<bool, Error>;
+
+    /// Take the last edge from application context, if it was not taken
+    /// yet
+    fn take_edge(&mut self) -> Result<Option<Edge>, Error>;
+
+    /// Add a sample of the raw input from the corresponding interrupt,
+    /// returning the edge it completed, if any
+    fn sample_int_context(pressed: bool) -> Result<Option<Edge>, Error>;
+}
+
+impl<S> Debounced for S
+where
+    S: SharedItem<Data = Debounce>,
+{
+    fn is_pressed(&mut self) -> Result<bool, Error> {
+        self.access_app_context(|input| input.is_pressed())
+    }
+
+    fn take_edge(&mut self) -> Result<Option<Edge>, Error> {
+        self.access_app_context(Debounce::take_edge)
+    }
+
+    fn sample_int_context(pressed: bool) -> Result<Option<Edge>, Error> {
+        S::access_int_context(|input| input.sample(pressed))
+    }
+}
@@ -0,0 +1,199 @@
+//! A ring of log records written by interrupts and printed by the
+//! application
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::lend::LockFree;
+use crate::{ByteRing, Error, SharedItem};
+
+/// The longest record, in bytes. Longer records are truncated.
+pub const MAX_RECORD: usize = 128;
+
+/// A record being formatted, behind its length byte
+struct Frame {
+    buf: [u8; MAX_RECORD + 1],
+    len: usize,
+}
+
+impl Frame {
+    fn new() -> Self {
+        Frame {
+            buf: [0; MAX_RECORD + 1],
+            len: 0,
+        }
+    }
+
+    /// The length byte, followed by the record
+    fn framed(&mut self) -> &[u8] {
+        self.buf[0] = self.len as u8;
+        &self.buf[..=self.len]
+    }
+}
+
+impl fmt::Write for Frame {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(MAX_RECORD - self.len);
+        self.buf[1 + self.len..][..count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+/// A ring of `N` bytes holding log records, appended by the interrupts and
+/// drained by the application, such as for printing from handlers during
+/// bring-up.
+///
+/// This is used as the data type of a `shared` item listing every
+/// interrupt which logs, and is accessed through the `Log` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     (LOG, shared::LogRing<1024>, [Interrupt::RADIO, Interrupt::TIMER0], shared::LogRing::new()),
+/// );
+///
+/// #[interrupt]
+/// fn RADIO() {
+///     LOG::log_int_context(format_args!("crc error on channel {}", channel())).unwrap();
+/// }
+///
+/// token.drain(|record| rtt_print(record)).unwrap();
+/// let lost = token.dropped().unwrap();
+/// ```
+///
+/// Each record is formatted before it is appended, with all interrupts
+/// masked only while its bytes are copied, so interrupts of different
+/// priorities may log. A record which does not fit in the ring is dropped
+/// as a whole, and counted.
+pub struct LogRing<const N: usize> {
+    ring: ByteRing<N>,
+    dropped: AtomicU32,
+}
+
+impl<const N: usize> LogRing<N> {
+    /// Create a new, empty ring
+    pub const fn new() -> Self {
+        LogRing {
+            ring: ByteRing::to_app(),
+            dropped: AtomicU32::new(0),
+        }
+    }
+
+    /// Append a framed record, or count it as dropped
+    ///
+    /// # Safety
+    ///
+    /// Only one producer may call this at a time.
+    unsafe fn append(&self, framed: &[u8]) {
+        if N - self.ring.len() < framed.len() {
+            let dropped = self.dropped.load(Ordering::SeqCst);
+            self.dropped
+                .store(dropped.saturating_add(1), Ordering::SeqCst);
+            return;
+        }
+
+        self.ring.push_slice(framed);
+    }
+}
+
+impl<const N: usize> LockFree for LogRing<N> {}
+
+impl<const N: usize> Default for LogRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Appending records to, and draining them from, a shared `LogRing`
+pub trait Log<const N: usize>: SharedItem<Data = LogRing<N>> {
+    /// Call `f` with every record appended so far, oldest first, from
+    /// application context, removing them from the ring
+    fn drain<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&[u8]);
+
+    /// The number of records dropped because the ring was full, since the
+    /// ring was created
+    fn dropped(&mut self) -> Result<u32, Error>;
+
+    /// Append formatted text from any of the corresponding interrupts
+    fn log_int_context(args: fmt::Arguments) -> Result<(), Error>;
+
+    /// Append a record of raw bytes from any of the corresponding
+    /// interrupts, such as a fixed size binary record
+    fn write_record_int_context(bytes: &[u8]) -> Result<(), Error>;
+}
+
+/// The ring shared with the interrupts, reached without masking them
+fn app_ring<S, const N: usize>(_token: &mut S) -> Result<&LogRing<N>, Error>
+where
+    S: SharedItem<Data = LogRing<N>>,
+{
+    if !S::is_initialized() {
+        return Err(Error::Uninitialized);
+    }
+
+    // NOTE(unsafe) the ring is initialized, and only shared references are
+    // made to it while it is used
+    Ok(unsafe { &*S::storage() })
+}
+
+/// Append a record formatted into `frame` from interrupt context
+fn append<S, const N: usize>(frame: &mut Frame) -> Result<(), Error>
+where
+    S: SharedItem<Data = LogRing<N>>,
+{
+    let framed = frame.framed();
+
+    // As for an `EventQueue`, the interrupts of the item must not preempt
+    // each other while one of them holds it
+    crate::__interrupt_free(|_| {
+        S::inspect_int_context(|log| {
+            // NOTE(unsafe) the producers are serialized by the critical
+            // section
+            unsafe { log.append(framed) }
+        })
+    })
+}
+
+impl<S, const N: usize> Log<N> for S
+where
+    S: SharedItem<Data = LogRing<N>>,
+{
+    fn drain<F>(&mut self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        let log = app_ring(self)?;
+        let mut record = [0; MAX_RECORD];
+        let mut len = [0];
+
+        // NOTE(unsafe) the token is the only consumer, and records are
+        // appended whole, length byte first
+        while unsafe { log.ring.pop_slice(&mut len) } == 1 {
+            let len = usize::from(len[0]);
+            unsafe { log.ring.pop_slice(&mut record[..len]) };
+            f(&record[..len]);
+        }
+        Ok(())
+    }
+
+    fn dropped(&mut self) -> Result<u32, Error> {
+        Ok(app_ring(self)?.dropped.load(Ordering::SeqCst))
+    }
+
+    fn log_int_context(args: fmt::Arguments) -> Result<(), Error> {
+        let mut frame = Frame::new();
+        // Formatting into a `Frame` never fails, it truncates instead
+        let _ = fmt::write(&mut frame, args);
+        append::<S, N>(&mut frame)
+    }
+
+    fn write_record_int_context(bytes: &[u8]) -> Result<(), Error> {
+        let mut frame = Frame::new();
+        let len = bytes.len().min(MAX_RECORD);
+        frame.buf[1..=len].copy_from_slice(&bytes[..len]);
+        frame.len = len;
+        append::<S, N>(&mut frame)
+    }
+}
@@ -0,0 +1,109 @@
+//! Software interrupts and event generator units of the nRF52 series
+//!
+//! Each `SWIn` interrupt is shared with the event generator unit `EGUn`.
+//! Binding a `shared` item to one of them allows the application to
+//! schedule work at the priority of that interrupt:
+//!
+//! ```rust,ignore
+//! use shared::nrf52::Swi;
+//!
+//! shared!(
+//!     (WORK, Option<Command>, Swi::SWI1, None, priority = 3 << 5),
+//! );
+//!
+//! Swi::SWI1.enable_channel(0);
+//! token.insert(Command::Flush).unwrap();
+//! Swi::SWI1.trigger(0);
+//!
+//! #[interrupt]
+//! fn SWI1_EGU1() {
+//!     if Swi::SWI1.take_triggered(0) {
+//!         WORK::take_int_context().unwrap();
+//!     }
+//! }
+//! ```
+//!
+//! When the channels are not needed, `pend` on the token is enough to run
+//! the interrupt.
+
+use core::ptr;
+
+/// The base address of `EGU0`. The others follow at 0x1000 byte intervals
+const EGU_BASE: usize = 0x4001_4000;
+
+/// The offset of `EVENTS_TRIGGERED[0]`
+const EVENTS_TRIGGERED: usize = 0x100;
+
+/// The offset of `INTENSET`
+const INTENSET: usize = 0x304;
+
+/// The number of channels of each event generator unit
+pub const CHANNELS: u8 = 16;
+
+/// The software interrupts of the nRF52 series, and their event generator
+/// units. These may be used as the interrupt of a `shared` item.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Swi {
+    /// `SWI0_EGU0`
+    SWI0,
+    /// `SWI1_EGU1`
+    SWI1,
+    /// `SWI2_EGU2`
+    SWI2,
+    /// `SWI3_EGU3`
+    SWI3,
+    /// `SWI4_EGU4`
+    SWI4,
+    /// `SWI5_EGU5`
+    SWI5,
+}
+
+unsafe impl cortex_m::interrupt::Nr for Swi {
+    fn nr(&self) -> u8 {
+        20 + *self as u8
+    }
+}
+
+impl Swi {
+    /// The address of the register at `offset` in the event generator unit
+    fn register(self, offset: usize) -> *mut u32 {
+        (EGU_BASE + 0x1000 * self as usize + offset) as *mut u32
+    }
+
+    /// The address of a per channel register, starting at `offset`
+    fn channel_register(self, offset: usize, channel: u8) -> *mut u32 {
+        assert!(channel < CHANNELS);
+        self.register(offset + 4 * usize::from(channel))
+    }
+
+    /// Make triggering `channel` fire the interrupt
+    pub fn enable_channel(self, channel: u8) {
+        assert!(channel < CHANNELS);
+
+        // NOTE(unsafe) INTENSET is write-one-to-set, so this doesn't race
+        unsafe { ptr::write_volatile(self.register(INTENSET), 1 << channel) }
+    }
+
+    /// Trigger `channel` of the event generator unit. If the channel is
+    /// enabled, this fires the interrupt.
+    pub fn trigger(self, channel: u8) {
+        // NOTE(unsafe) writing to a task register has no other side effects
+        unsafe { ptr::write_volatile(self.channel_register(0, channel), 1) }
+    }
+
+    /// Check whether `channel` has been triggered, and clear it if so. This
+    /// is intended to be called from within the interrupt.
+    pub fn take_triggered(self, channel: u8) -> bool {
+        let event = self.channel_register(EVENTS_TRIGGERED, channel);
+
+        // NOTE(unsafe) each channel has its own event register
+        unsafe {
+            if ptr::read_volatile(event) == 0 {
+                return false;
+            }
+            ptr::write_volatile(event, 0);
+        }
+        true
+    }
+}
@@ -0,0 +1,90 @@
+//! Peripherals owned by a shared item, whose registers are reached under
+//! the same discipline as any other shared data
+
+use core::ops::Deref;
+
+use crate::{Error, SharedItem};
+
+/// An owned peripheral from a peripheral access crate, such as `TIMER0`,
+/// moved into a shared item so that both the application and the
+/// corresponding interrupt can reach its registers.
+///
+/// Peripherals can not be created in a `const` context, so the item is
+/// declared without an initial value, and filled in with `set_initial`
+/// once the peripherals have been taken:
+///
+/// ```rust,ignore
+/// shared!(
+///     (TIMER, shared::Peripheral<nrf52840_pac::TIMER0>, Interrupt::TIMER0),
+/// );
+///
+/// let dp = nrf52840_pac::Peripherals::take().unwrap();
+/// let mut timer = TIMER::set_initial(shared::Peripheral::new(dp.TIMER0)).ok().unwrap();
+/// timer.modify_registers(|timer| timer.tasks_start.write(|w| unsafe { w.bits(1) })).unwrap();
+///
+/// #[interrupt]
+/// fn TIMER0() {
+///     TIMER::modify_registers_int_context(|timer| {
+///         timer.events_compare[0].reset();
+///     }).unwrap();
+/// }
+/// ```
+pub struct Peripheral<P> {
+    periph: P,
+}
+
+impl<P> Peripheral<P> {
+    /// Wrap an owned peripheral
+    pub const fn new(periph: P) -> Self {
+        Peripheral { periph }
+    }
+
+    /// The owned peripheral, for example to hand it to a HAL driver
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.periph
+    }
+
+    /// Give the owned peripheral back
+    pub fn into_inner(self) -> P {
+        self.periph
+    }
+}
+
+/// Reaching the registers of a shared `Peripheral`.
+///
+/// Register blocks of peripheral access crates are accessed through shared
+/// references, with each register behind a cell. The exclusive access to
+/// the peripheral is guaranteed by the closure running under the same rules
+/// as `modify_app_context` and `modify_int_context`, so a read-modify-write
+/// of a register can not be interleaved with one from the other context.
+pub trait Registers<P: Deref>: SharedItem<Data = Peripheral<P>> {
+    /// Access the registers from application context, disabling the
+    /// corresponding interrupt for the duration of the closure
+    fn modify_registers<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&P::Target) -> R;
+
+    /// Access the registers from the corresponding interrupt
+    fn modify_registers_int_context<F, R>(f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&P::Target) -> R;
+}
+
+impl<S, P: Deref> Registers<P> for S
+where
+    S: SharedItem<Data = Peripheral<P>>,
+{
+    fn modify_registers<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&P::Target) -> R,
+    {
+        self.access_app_context(|peripheral| f(&peripheral.periph))
+    }
+
+    fn modify_registers_int_context<F, R>(f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&P::Target) -> R,
+    {
+        S::access_int_context(|peripheral| f(&peripheral.periph))
+    }
+}
@@ -0,0 +1,276 @@
+//! A pool of fixed size slots, allocated by an interrupt and freed by the
+//! application
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::lend::LockFree;
+use crate::{Error, SharedItem};
+
+/// A fixed number of slots for values of type `T`, such as packets, which
+/// are allocated by the interrupt and freed by the application.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `SlotPool` trait. Allocating a slot returns a `Handle`,
+/// which is small enough to be passed to the application through a
+/// `Queue`:
+///
+/// ```rust,ignore
+/// shared!(
+///     (PACKETS, shared::Pool<Packet, 8>, Interrupt::RADIO, shared::Pool::new()),
+///     (RECEIVED, shared::Queue<shared::Handle<8>, 8>, Interrupt::RADIO, shared::Queue::to_app()),
+/// );
+///
+/// #[interrupt]
+/// fn RADIO() {
+///     if let Ok(handle) = PACKETS::alloc_int_context(read_packet()).unwrap() {
+///         if let Err(handle) = RECEIVED::enqueue_int_context(handle).unwrap() {
+///             // Nothing can free the slot from here
+///             core::mem::forget(handle);
+///         }
+///     }
+/// }
+///
+/// while let Some(mut handle) = received.dequeue().unwrap() {
+///     process(packets.get_mut(&mut handle).unwrap());
+///     packets.free(handle).unwrap();
+/// }
+/// ```
+///
+/// Each slot only ever moves from free to used in the interrupt, and from
+/// used to free in the application, so neither side masks the interrupt.
+///
+/// Using a handle with another pool, including a pool which replaced the
+/// one it came from, or once its slot was freed, returns
+/// `Error::InvalidHandle`. With debug assertions enabled, dropping a
+/// `Handle` without freeing it panics.
+pub struct Pool<T, const N: usize> {
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    used: [AtomicBool; N],
+    id: AtomicU32,
+}
+
+/// The identity given to the next pool to allocate a slot. Identities
+/// start at 1, as 0 marks a pool which has not been given one yet.
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+impl<T, const N: usize> Pool<T, N> {
+    /// A slot which holds no value
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: UnsafeCell<MaybeUninit<T>> = UnsafeCell::new(MaybeUninit::uninit());
+
+    /// The state of a free slot
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FREE: AtomicBool = AtomicBool::new(false);
+
+    /// Create a new pool, with every slot free
+    pub const fn new() -> Self {
+        Pool {
+            slots: [Self::EMPTY; N],
+            used: [Self::FREE; N],
+            id: AtomicU32::new(0),
+        }
+    }
+
+    /// The number of slots in the pool
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// The number of slots currently allocated. Once every handle has been
+    /// freed, this is zero, so it can be checked for leaks at shutdown.
+    pub fn in_use(&self) -> usize {
+        self.used
+            .iter()
+            .filter(|used| used.load(Ordering::SeqCst))
+            .count()
+    }
+
+    /// The identity of the pool, which its handles carry. This is taken
+    /// from `NEXT_ID` the first time it is needed, so that every pool value
+    /// has its own, even one replacing another in the same static.
+    fn id(&self) -> u32 {
+        let id = self.id.load(Ordering::SeqCst);
+        if id != 0 {
+            return id;
+        }
+
+        crate::__interrupt_free(|_| {
+            let id = NEXT_ID.load(Ordering::SeqCst);
+            NEXT_ID.store(id.wrapping_add(1).max(1), Ordering::SeqCst);
+            self.id.store(id, Ordering::SeqCst);
+            id
+        })
+    }
+
+    /// Move `value` into a free slot, or return it if there is none
+    ///
+    /// # Safety
+    ///
+    /// Only the interrupt may call this.
+    unsafe fn alloc(&self, value: T) -> Result<Handle<N>, T> {
+        let index = match self
+            .used
+            .iter()
+            .position(|used| !used.load(Ordering::SeqCst))
+        {
+            Some(index) => index,
+            None => return Err(value),
+        };
+
+        (*self.slots[index].get()).write(value);
+        self.used[index].store(true, Ordering::SeqCst);
+        Ok(Handle::new(index as u16, self.id()))
+    }
+
+    /// A pointer to the value in the slot of `handle`, if the handle
+    /// belongs to this pool and its slot is in use
+    fn slot(&self, handle: &Handle<N>) -> Result<*mut T, Error> {
+        let index = usize::from(handle.index);
+        if handle.pool != self.id.load(Ordering::SeqCst)
+            || index >= N
+            || !self.used[index].load(Ordering::SeqCst)
+        {
+            return Err(Error::InvalidHandle);
+        }
+        Ok(self.slots[index].get() as *mut T)
+    }
+}
+
+impl<T, const N: usize> LockFree for Pool<T, N> {}
+
+impl<T, const N: usize> Default for Pool<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Pool<T, N> {
+    fn drop(&mut self) {
+        for (slot, used) in self.slots.iter_mut().zip(self.used.iter()) {
+            if used.load(Ordering::SeqCst) {
+                // NOTE(unsafe) the slot is in use, so it holds a value
+                unsafe { ptr::drop_in_place(slot.get_mut().as_mut_ptr()) };
+            }
+        }
+    }
+}
+
+/// An allocated slot of a `Pool` with `N` slots, which must be given back
+/// to `SlotPool::free`.
+///
+/// A handle can not be copied, so a slot can not be freed twice through
+/// it. It carries the identity of its pool, so it can not be used with
+/// any other. Dropping a handle leaks its slot, and panics with debug
+/// assertions enabled; use `core::mem::forget` to leak a slot on purpose.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Handle<const N: usize> {
+    index: u16,
+    pool: u32,
+}
+
+impl<const N: usize> Handle<N> {
+    fn new(index: u16, pool: u32) -> Self {
+        Handle { index, pool }
+    }
+
+    /// The index of the slot in the pool
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Turn the handle into the index of its slot, for example to store it
+    /// in a register, without freeing the slot
+    pub fn into_index(self) -> u16 {
+        let index = self.index;
+        core::mem::forget(self);
+        index
+    }
+
+    /// Recreate a handle from the index returned by `into_index`
+    ///
+    /// # Safety
+    ///
+    /// The index must come from `into_index` on a handle of `pool`, and
+    /// only one handle may be recreated from it.
+    pub unsafe fn from_index<T>(pool: &Pool<T, N>, index: u16) -> Self {
+        Handle::new(index, pool.id.load(Ordering::SeqCst))
+    }
+}
+
+impl<const N: usize> Drop for Handle<N> {
+    fn drop(&mut self) {
+        debug_assert!(false, "pool slot {} was leaked", self.index);
+    }
+}
+
+/// Allocating and freeing the slots of a shared `Pool`
+pub trait SlotPool<T, const N: usize>: SharedItem<Data = Pool<T, N>> {
+    /// Access the value in an allocated slot from application context,
+    /// without masking the interrupt
+    fn get_mut<'a>(&'a mut self, handle: &'a mut Handle<N>) -> Result<&'a mut T, Error>;
+
+    /// Free a slot from application context, returning the value it held.
+    /// If the handle can not be freed here, for example because it belongs
+    /// to another pool, it is returned with the error, so the slot is not
+    /// leaked.
+    fn free(&mut self, handle: Handle<N>) -> Result<T, (Error, Handle<N>)>;
+
+    /// Allocate a slot holding `value` from the corresponding interrupt. The
+    /// inner `Result` returns the value if every slot is in use.
+    fn alloc_int_context(value: T) -> Result<Result<Handle<N>, T>, Error>;
+}
+
+/// The pool shared with the interrupt, reached without masking the
+/// interrupt
+fn app_pool<S, T, const N: usize>(_token: &mut S) -> Result<&Pool<T, N>, Error>
+where
+    S: SharedItem<Data = Pool<T, N>>,
+{
+    if !S::is_initialized() {
+        return Err(Error::Uninitialized);
+    }
+
+    // NOTE(unsafe) the pool is initialized, and only shared references are
+    // made to it while it is used
+    Ok(unsafe { &*S::storage() })
+}
+
+impl<S, T, const N: usize> SlotPool<T, N> for S
+where
+    S: SharedItem<Data = Pool<T, N>>,
+{
+    fn get_mut<'a>(&'a mut self, handle: &'a mut Handle<N>) -> Result<&'a mut T, Error> {
+        let pool = app_pool(self)?;
+
+        // NOTE(unsafe) the slot is in use, and the handle borrowed here is
+        // its only owner
+        Ok(unsafe { &mut *pool.slot(handle)? })
+    }
+
+    fn free(&mut self, handle: Handle<N>) -> Result<T, (Error, Handle<N>)> {
+        let pool = match app_pool(self) {
+            Ok(pool) => pool,
+            Err(error) => return Err((error, handle)),
+        };
+        let slot = match pool.slot(&handle) {
+            Ok(slot) => slot,
+            Err(error) => return Err((error, handle)),
+        };
+        let index = usize::from(handle.into_index());
+
+        // NOTE(unsafe) the slot is in use, and its handle is consumed here
+        let value = unsafe { ptr::read(slot) };
+        pool.used[index].store(false, Ordering::SeqCst);
+        Ok(value)
+    }
+
+    fn alloc_int_context(value: T) -> Result<Result<Handle<N>, T>, Error> {
+        S::inspect_int_context(|pool| {
+            // NOTE(unsafe) this is the corresponding interrupt
+            unsafe { pool.alloc(value) }
+        })
+    }
+}
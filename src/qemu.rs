@@ -0,0 +1,124 @@
+//! Running tests of `shared` items in QEMU, such as `qemu-system-arm` with
+//! the `lm3s6965evb` machine, reporting the results through semihosting
+//!
+//! ```rust,ignore
+//! #[entry]
+//! fn main() -> ! {
+//!     let mut token = COUNTER::set_initial_nvic(0, &mut p.NVIC).unwrap();
+//!
+//!     shared::qemu::pend(Interrupt::UART0);
+//!     shared::qemu::check("handler ran", token.read_app_context(|c| *c == 1) == Ok(true));
+//!
+//!     shared::qemu::finish()
+//! }
+//!
+//! #[interrupt]
+//! fn UART0() {
+//!     COUNTER::modify_int_context(|c| *c += 1).unwrap();
+//! }
+//! ```
+//!
+//! QEMU must be started with `-semihosting-config enable=on,target=native`.
+//! On the target, a panic prints its message and exits QEMU with a failure,
+//! so `assert!` may be used in tests and handlers.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::nvic::{Interrupt, Interrupts, Single};
+
+/// `SYS_WRITEC`, writing the character pointed to by the argument
+const SYS_WRITEC: usize = 0x03;
+
+/// `SYS_EXIT`, ending the session with the reason given as argument
+const SYS_EXIT: usize = 0x18;
+
+/// `ADP_Stopped_ApplicationExit`, reported by `exit` on success
+const APPLICATION_EXIT: usize = 0x2_0026;
+
+/// `ADP_Stopped_RunTimeErrorUnknown`, reported by `exit` on failure
+const RUN_TIME_ERROR: usize = 0x2_0023;
+
+/// Whether a `check` has failed
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Make a semihosting call
+unsafe fn call(operation: usize, argument: usize) -> usize {
+    let result;
+    core::arch::asm!(
+        "bkpt #0xab",
+        inout("r0") operation => result,
+        in("r1") argument,
+        options(nostack),
+    );
+    result
+}
+
+/// The host console, which may be written to with `write!`
+pub struct Stdout;
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // NOTE(unsafe) the host only reads the character
+            unsafe { call(SYS_WRITEC, &byte as *const u8 as usize) };
+        }
+        Ok(())
+    }
+}
+
+/// Exit QEMU, reporting success or failure to the host
+pub fn exit(success: bool) -> ! {
+    let reason = if success {
+        APPLICATION_EXIT
+    } else {
+        RUN_TIME_ERROR
+    };
+
+    // NOTE(unsafe) this ends the session, so nothing else can observe it
+    unsafe { call(SYS_EXIT, reason) };
+
+    // Only reached if semihosting is not enabled
+    loop {
+        cortex_m::asm::nop();
+    }
+}
+
+/// Pend the interrupt, and wait for the pending state to be seen by the
+/// core. If the interrupt is enabled, unmasked and more urgent than the
+/// caller, its handler has run when this returns.
+pub fn pend<I>(int: I)
+where
+    I: Interrupt,
+{
+    Interrupts(int).set().set_pending(true);
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// Print the outcome of a test on the host console. `finish` exits with a
+/// failure if any test did not pass.
+pub fn check(name: &str, passed: bool) {
+    use core::fmt::Write;
+
+    if !passed {
+        FAILED.store(true, Ordering::SeqCst);
+    }
+    let outcome = if passed { "ok" } else { "FAILED" };
+    let _ = writeln!(Stdout, "test {} ... {}", name, outcome);
+}
+
+/// Exit QEMU, with a failure if any `check` failed
+pub fn finish() -> ! {
+    exit(!FAILED.load(Ordering::SeqCst))
+}
+
+#[cfg(target_os = "none")]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    cortex_m::interrupt::disable();
+    let _ = writeln!(Stdout, "{}", info);
+    exit(false)
+}
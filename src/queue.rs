@@ -0,0 +1,532 @@
+//! A single producer, single consumer queue between the application and an
+//! interrupt, with element and slice based ends, and a queue of events from
+//! several interrupts to the application
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::lend::LockFree;
+use crate::{Error, SharedItem};
+
+/// A fixed capacity queue, with one end in the application and the other
+/// in the corresponding interrupt.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `Spsc` trait. The direction is chosen when the queue is
+/// created:
+///
+/// ```rust,ignore
+/// shared!(
+///     (RX, shared::Queue<u8, 64>, Interrupt::UARTE0_UARTE0, shared::Queue::to_app()),
+/// );
+///
+/// #[interrupt]
+/// fn UARTE0_UARTE0() {
+///     let _ = RX::enqueue_int_context(read_byte()).unwrap();
+/// }
+///
+/// while let Some(byte) = token.dequeue().unwrap() {
+///     process(byte);
+/// }
+/// ```
+///
+/// Each end only ever moves its own index, so the application end never
+/// masks the interrupt, and both ends only hold shared references to the
+/// queue. A queue must hold at least one element.
+pub struct Queue<T, const N: usize> {
+    buf: UnsafeCell<MaybeUninit<[T; N]>>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    to_app: bool,
+}
+
+impl<T, const N: usize> Queue<T, N> {
+    /// Rejects queues which could not hold any element, whose indices could
+    /// not be reduced to a slot
+    const NOT_EMPTY: () = assert!(N > 0, "a queue must hold at least one element");
+
+    /// Create a new, empty queue, filled by the interrupt and drained by
+    /// the application
+    pub const fn to_app() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::NOT_EMPTY;
+        Queue {
+            buf: UnsafeCell::new(MaybeUninit::uninit()),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            to_app: true,
+        }
+    }
+
+    /// Create a new, empty queue, filled by the application and drained by
+    /// the interrupt
+    pub const fn to_int() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::NOT_EMPTY;
+        Queue {
+            buf: UnsafeCell::new(MaybeUninit::uninit()),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            to_app: false,
+        }
+    }
+
+    /// The maximum number of elements the queue can hold
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// The number of elements currently in the queue
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::SeqCst);
+        self.tail.load(Ordering::SeqCst).wrapping_sub(head)
+    }
+
+    /// Whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the queue is full
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    /// A pointer to the slot for the element at `index`
+    fn slot(&self, index: usize) -> *mut T {
+        // NOTE(unsafe) the index is reduced to be within the buffer
+        unsafe { (self.buf.get() as *mut T).add(index % N) }
+    }
+
+    /// Add an element at the tail, or return it if the queue is full
+    ///
+    /// # Safety
+    ///
+    /// Only the producing end may call this.
+    unsafe fn push(&self, item: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::SeqCst);
+        if tail.wrapping_sub(self.head.load(Ordering::SeqCst)) == N {
+            return Err(item);
+        }
+
+        ptr::write(self.slot(tail), item);
+        self.tail.store(tail.wrapping_add(1), Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Remove the element at the head, if any
+    ///
+    /// # Safety
+    ///
+    /// Only the consuming end may call this.
+    unsafe fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::SeqCst);
+        if head == self.tail.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let item = ptr::read(self.slot(head));
+        self.head.store(head.wrapping_add(1), Ordering::SeqCst);
+        Some(item)
+    }
+}
+
+impl<T: Copy, const N: usize> Queue<T, N> {
+    /// Copy as many elements from `items` as fit to the tail, returning how
+    /// many were copied
+    ///
+    /// # Safety
+    ///
+    /// Only the producing end may call this.
+    pub(crate) unsafe fn push_slice(&self, items: &[T]) -> usize {
+        let tail = self.tail.load(Ordering::SeqCst);
+        let free = N - tail.wrapping_sub(self.head.load(Ordering::SeqCst));
+        let count = items.len().min(free);
+
+        // The free space may wrap around the end of the buffer
+        let first = count.min(N - tail % N);
+        ptr::copy_nonoverlapping(items.as_ptr(), self.slot(tail), first);
+        ptr::copy_nonoverlapping(items[first..].as_ptr(), self.slot(0), count - first);
+
+        self.tail.store(tail.wrapping_add(count), Ordering::SeqCst);
+        count
+    }
+
+    /// Copy as many elements from the head as fit into `out`, returning how
+    /// many were copied
+    ///
+    /// # Safety
+    ///
+    /// Only the consuming end may call this.
+    pub(crate) unsafe fn pop_slice(&self, out: &mut [T]) -> usize {
+        let head = self.head.load(Ordering::SeqCst);
+        let used = self.tail.load(Ordering::SeqCst).wrapping_sub(head);
+        let count = out.len().min(used);
+
+        // The elements may wrap around the end of the buffer
+        let first = count.min(N - head % N);
+        ptr::copy_nonoverlapping(self.slot(head), out.as_mut_ptr(), first);
+        ptr::copy_nonoverlapping(self.slot(0), out[first..].as_mut_ptr(), count - first);
+
+        self.head.store(head.wrapping_add(count), Ordering::SeqCst);
+        count
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        // NOTE(unsafe) both ends are exclusively borrowed
+        while unsafe { self.pop() }.is_some() {}
+    }
+}
+
+impl<T, const N: usize> LockFree for Queue<T, N> {}
+
+/// The application end of a shared `Queue`, reached without masking the
+/// interrupt
+fn app_end<S, T, const N: usize>(_token: &mut S) -> Result<&Queue<T, N>, Error>
+where
+    S: SharedItem<Data = Queue<T, N>>,
+{
+    if !S::is_initialized() {
+        return Err(Error::Uninitialized);
+    }
+
+    // NOTE(unsafe) the queue is initialized, and only shared references
+    // are made to it while the ends are used
+    Ok(unsafe { &*S::storage() })
+}
+
+/// Pushing to and popping from a shared `Queue`.
+///
+/// Operations on the wrong end of the queue, such as `enqueue` on a queue
+/// made with `to_app`, return `Error::WrongContext`. The inner `Result` of
+/// the `enqueue` methods returns the element if the queue is full.
+pub trait Spsc<T, const N: usize>: SharedItem<Data = Queue<T, N>> {
+    /// Add an element from application context, to a queue made with
+    /// `to_int`
+    fn enqueue(&mut self, item: T) -> Result<Result<(), T>, Error>;
+
+    /// Remove the oldest element from application context, from a queue
+    /// made with `to_app`
+    fn dequeue(&mut self) -> Result<Option<T>, Error>;
+
+    /// Add an element from the corresponding interrupt, to a queue made
+    /// with `to_app`
+    fn enqueue_int_context(item: T) -> Result<Result<(), T>, Error>;
+
+    /// Remove the oldest element from the corresponding interrupt, from a
+    /// queue made with `to_int`
+    fn dequeue_int_context() -> Result<Option<T>, Error>;
+}
+
+impl<S, T, const N: usize> Spsc<T, N> for S
+where
+    S: SharedItem<Data = Queue<T, N>>,
+{
+    fn enqueue(&mut self, item: T) -> Result<Result<(), T>, Error> {
+        let queue = app_end(self)?;
+        if queue.to_app {
+            return Err(Error::WrongContext);
+        }
+
+        // NOTE(unsafe) the token is the only producer in application context
+        Ok(unsafe { queue.push(item) })
+    }
+
+    fn dequeue(&mut self) -> Result<Option<T>, Error> {
+        let queue = app_end(self)?;
+        if !queue.to_app {
+            return Err(Error::WrongContext);
+        }
+
+        // NOTE(unsafe) the token is the only consumer in application context
+        Ok(unsafe { queue.pop() })
+    }
+
+    fn enqueue_int_context(item: T) -> Result<Result<(), T>, Error> {
+        S::inspect_int_context(|queue| {
+            if !queue.to_app {
+                return Err(Error::WrongContext);
+            }
+
+            // NOTE(unsafe) the interrupt is the producer of this queue
+            Ok(unsafe { queue.push(item) })
+        })?
+    }
+
+    fn dequeue_int_context() -> Result<Option<T>, Error> {
+        S::inspect_int_context(|queue| {
+            if queue.to_app {
+                return Err(Error::WrongContext);
+            }
+
+            // NOTE(unsafe) the interrupt is the consumer of this queue
+            Ok(unsafe { queue.pop() })
+        })?
+    }
+}
+
+/// A shared ring buffer of bytes, for streaming data such as that of a UART
+/// through the `Stream` trait
+///
+/// ```rust,ignore
+/// shared!(
+///     (TX, shared::ByteRing<128>, Interrupt::UARTE0_UARTE0, shared::ByteRing::to_int()),
+/// );
+///
+/// let mut rest = &b"hello, world"[..];
+/// while !rest.is_empty() {
+///     rest = &rest[token.write(rest).unwrap()..];
+/// }
+///
+/// #[interrupt]
+/// fn UARTE0_UARTE0() {
+///     let mut chunk = [0; 16];
+///     let len = TX::read_int_context(&mut chunk).unwrap();
+///     start_transmit(&chunk[..len]);
+/// }
+/// ```
+pub type ByteRing<const N: usize> = Queue<u8, N>;
+
+/// Writing and reading slices of elements to and from a shared `Queue`,
+/// such as a `ByteRing`.
+///
+/// These copy as many elements as fit, or are available, and return how
+/// many were copied, which may be zero. Like the `Spsc` methods, operations
+/// on the wrong end of the queue return `Error::WrongContext`.
+pub trait Stream<T: Copy, const N: usize>: SharedItem<Data = Queue<T, N>> {
+    /// Write elements from application context, to a queue made with
+    /// `to_int`
+    fn write(&mut self, items: &[T]) -> Result<usize, Error>;
+
+    /// Read the oldest elements from application context, from a queue made
+    /// with `to_app`
+    fn read(&mut self, out: &mut [T]) -> Result<usize, Error>;
+
+    /// Write elements from the corresponding interrupt, to a queue made
+    /// with `to_app`
+    fn write_int_context(items: &[T]) -> Result<usize, Error>;
+
+    /// Read the oldest elements from the corresponding interrupt, from a
+    /// queue made with `to_int`
+    fn read_int_context(out: &mut [T]) -> Result<usize, Error>;
+}
+
+impl<S, T: Copy, const N: usize> Stream<T, N> for S
+where
+    S: SharedItem<Data = Queue<T, N>>,
+{
+    fn write(&mut self, items: &[T]) -> Result<usize, Error> {
+        let queue = app_end(self)?;
+        if queue.to_app {
+            return Err(Error::WrongContext);
+        }
+
+        // NOTE(unsafe) the token is the only producer in application context
+        Ok(unsafe { queue.push_slice(items) })
+    }
+
+    fn read(&mut self, out: &mut [T]) -> Result<usize, Error> {
+        let queue = app_end(self)?;
+        if !queue.to_app {
+            return Err(Error::WrongContext);
+        }
+
+        // NOTE(unsafe) the token is the only consumer in application context
+        Ok(unsafe { queue.pop_slice(out) })
+    }
+
+    fn write_int_context(items: &[T]) -> Result<usize, Error> {
+        S::inspect_int_context(|queue| {
+            if !queue.to_app {
+                return Err(Error::WrongContext);
+            }
+
+            // NOTE(unsafe) the interrupt is the producer of this queue
+            Ok(unsafe { queue.push_slice(items) })
+        })?
+    }
+
+    fn read_int_context(out: &mut [T]) -> Result<usize, Error> {
+        S::inspect_int_context(|queue| {
+            if queue.to_app {
+                return Err(Error::WrongContext);
+            }
+
+            // NOTE(unsafe) the interrupt is the consumer of this queue
+            Ok(unsafe { queue.pop_slice(out) })
+        })?
+    }
+}
+
+/// A shared slot for handing single values from an interrupt to the
+/// application, which may wait for them with `Blocking::recv_blocking`
+///
+/// ```rust,ignore
+/// shared!(
+///     (RESULT, shared::Mailbox<Sample>, Interrupt::SAADC, shared::Mailbox::to_app()),
+/// );
+///
+/// #[interrupt]
+/// fn SAADC() {
+///     let _ = RESULT::enqueue_int_context(read_sample()).unwrap();
+/// }
+///
+/// start_conversion();
+/// let sample = token.recv_blocking().unwrap();
+/// ```
+pub type Mailbox<T> = Queue<T, 1>;
+
+/// Waiting for the interrupt to fill a shared `Queue`, such as a `Mailbox`
+#[cfg(cortex_m_backend)]
+pub trait Blocking<T, const N: usize>: SharedItem<Data = Queue<T, N>> {
+    /// Remove the oldest element from application context, from a queue
+    /// made with `to_app`, sleeping with `WFI` until there is one.
+    ///
+    /// The queue is checked with all interrupts masked, and the core sleeps
+    /// before they are unmasked again, so an element added just after the
+    /// check still wakes it. This must not be called with interrupts
+    /// masked, as the interrupt could then never run.
+    fn recv_blocking(&mut self) -> Result<T, Error>;
+}
+
+#[cfg(cortex_m_backend)]
+impl<S, T, const N: usize> Blocking<T, N> for S
+where
+    S: SharedItem<Data = Queue<T, N>>,
+{
+    fn recv_blocking(&mut self) -> Result<T, Error> {
+        let queue = app_end(self)?;
+        if !queue.to_app {
+            return Err(Error::WrongContext);
+        }
+
+        loop {
+            let item = cortex_m::interrupt::free(|_| {
+                // NOTE(unsafe) the token is the only consumer in application
+                // context
+                let item = unsafe { queue.pop() };
+                if item.is_none() {
+                    // A pending interrupt wakes the core even while masked,
+                    // and runs once the critical section ends
+                    cortex_m::asm::wfi();
+                }
+                item
+            });
+
+            if let Some(item) = item {
+                return Ok(item);
+            }
+        }
+    }
+}
+
+/// A fixed capacity queue of events, pushed by several interrupts and
+/// drained by the application.
+///
+/// This is used as the data type of a `shared` item listing every
+/// interrupt which reports events, and is accessed through the `Mpsc`
+/// trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     (EVENTS, shared::EventQueue<Event, 16>,
+///         [Interrupt::GPIOTE, Interrupt::UARTE0_UARTE0, Interrupt::RTC0],
+///         shared::EventQueue::new()),
+/// );
+///
+/// #[interrupt]
+/// fn GPIOTE() {
+///     let _ = EVENTS::post_int_context(Event::Button).unwrap();
+/// }
+///
+/// #[interrupt]
+/// fn RTC0() {
+///     let _ = EVENTS::post_int_context(Event::Tick).unwrap();
+/// }
+///
+/// while let Some(event) = token.next_event().unwrap() {
+///     handle(event);
+/// }
+/// ```
+///
+/// The interrupts may have different priorities, so each push is made with
+/// all interrupts masked, for as long as it takes to copy one event. The
+/// application never masks any interrupt to take events.
+pub struct EventQueue<T, const N: usize> {
+    queue: Queue<T, N>,
+}
+
+impl<T, const N: usize> EventQueue<T, N> {
+    /// Create a new, empty event queue
+    pub const fn new() -> Self {
+        EventQueue {
+            queue: Queue::to_app(),
+        }
+    }
+
+    /// The maximum number of events the queue can hold
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// The number of events currently in the queue
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl<T, const N: usize> Default for EventQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> LockFree for EventQueue<T, N> {}
+
+/// Posting events to, and taking them from, a shared `EventQueue`
+pub trait Mpsc<T, const N: usize>: SharedItem<Data = EventQueue<T, N>> {
+    /// Take the oldest event from application context
+    fn next_event(&mut self) -> Result<Option<T>, Error>;
+
+    /// Add an event from any of the corresponding interrupts. The inner
+    /// `Result` returns the event if the queue is full.
+    fn post_int_context(event: T) -> Result<Result<(), T>, Error>;
+}
+
+impl<S, T, const N: usize> Mpsc<T, N> for S
+where
+    S: SharedItem<Data = EventQueue<T, N>>,
+{
+    fn next_event(&mut self) -> Result<Option<T>, Error> {
+        if !S::is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+
+        // NOTE(unsafe) the queue is initialized, only shared references are
+        // made to it while it is used, and the token is the only consumer
+        let events = unsafe { &*S::storage() };
+        Ok(unsafe { events.queue.pop() })
+    }
+
+    fn post_int_context(event: T) -> Result<Result<(), T>, Error> {
+        // Masking every interrupt keeps the interrupts of the item from
+        // preempting each other while one of them holds it, which would
+        // otherwise be reported as `Error::Reentrant`
+        crate::__interrupt_free(|_| {
+            S::inspect_int_context(|events| {
+                // NOTE(unsafe) the producers are serialized by the critical
+                // section
+                unsafe { events.queue.push(event) }
+            })
+        })
+    }
+}
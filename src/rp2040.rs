@@ -0,0 +1,85 @@
+//! Sharing data between the two cores of the RP2040
+//!
+//! Masking an interrupt only affects the core doing the masking, so data
+//! touched by an interrupt handled on one core, and by the application on
+//! the other, is not protected by the NVIC alone. Items given a hardware
+//! spinlock of the SIO additionally hold that spinlock for the duration of
+//! every access:
+//!
+//! ```rust,ignore
+//! shared!(
+//!     (SAMPLES, Samples, Interrupt::ADC_IRQ_FIFO, Samples::new(), spinlock = 3),
+//! );
+//! ```
+//!
+//! Each item should use its own spinlock. Spinlock 31 is used by the
+//! `critical-section` implementation of `rp2040-hal`, and should be avoided.
+//! Accessing several items with spinlocks at once, such as with `with_all!`,
+//! may deadlock if the other core takes the same spinlocks in another order.
+
+use core::ptr;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::Error;
+
+/// The base address of the SIO
+const SIO_BASE: usize = 0xD000_0000;
+
+/// The offset of `CPUID`, which reads as the number of the current core
+const CPUID: usize = 0x000;
+
+/// The offset of `SPINLOCK0`. The others follow at 4 byte intervals
+const SPINLOCK0: usize = 0x100;
+
+/// The number of hardware spinlocks
+pub const SPINLOCKS: u8 = 32;
+
+/// The core holding each spinlock, plus one, or 0 if it is not held by a
+/// `shared` item
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_OWNER: AtomicU8 = AtomicU8::new(0);
+static OWNERS: [AtomicU8; SPINLOCKS as usize] = [NO_OWNER; SPINLOCKS as usize];
+
+/// The number of the core this code is running on
+pub fn core() -> u8 {
+    // NOTE(unsafe) atomic read with no side effects
+    unsafe { ptr::read_volatile((SIO_BASE + CPUID) as *const u32) as u8 }
+}
+
+/// The address of the spinlock
+fn spinlock(n: u8) -> *mut u32 {
+    assert!(n < SPINLOCKS);
+    (SIO_BASE + SPINLOCK0 + 4 * usize::from(n)) as *mut u32
+}
+
+/// Take the spinlock, spinning while the other core holds it. Returns
+/// `Ok(false)` without taking it if this core already holds it and
+/// `nested` is set, and `Error::Reentrant` if it is not.
+pub(crate) fn lock(n: u8, nested: bool) -> Result<bool, Error> {
+    let owner = core() + 1;
+    loop {
+        // NOTE(unsafe) reading a spinlock claims it if it is free
+        if unsafe { ptr::read_volatile(spinlock(n)) } != 0 {
+            OWNERS[usize::from(n)].store(owner, Ordering::SeqCst);
+            return Ok(true);
+        }
+
+        // The owner is only set while the spinlock is held, so this can only
+        // match if this core took it, and was then preempted
+        if OWNERS[usize::from(n)].load(Ordering::SeqCst) == owner {
+            return if nested {
+                Ok(false)
+            } else {
+                Err(Error::Reentrant)
+            };
+        }
+    }
+}
+
+/// Release a spinlock taken by `lock`
+pub(crate) fn unlock(n: u8) {
+    OWNERS[usize::from(n)].store(0, Ordering::SeqCst);
+
+    // NOTE(unsafe) writing any value releases the spinlock
+    unsafe { ptr::write_volatile(spinlock(n), 1) }
+}
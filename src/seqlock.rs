@@ -0,0 +1,271 @@
+//! Latest value cells written by an interrupt and read by the application
+//! without masking, retrying reads which raced a write
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use crate::lend::LockFree;
+use crate::{Error, SharedItem};
+
+/// A value written by the interrupt, and read by the application, guarded
+/// by a sequence number instead of by masking the interrupt.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `SeqLock` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     (TELEMETRY, shared::SeqCell<Telemetry>, Interrupt::RADIO, shared::SeqCell::new(Telemetry::EMPTY)),
+/// );
+///
+/// #[interrupt]
+/// fn RADIO() {
+///     TELEMETRY::store_int_context(read_telemetry()).unwrap();
+/// }
+///
+/// let telemetry = token.load().unwrap();
+/// ```
+///
+/// The sequence number is odd while the interrupt writes the value. A read
+/// which is preempted by a write is retried, so the interrupt never waits
+/// for the application. A read which starts during a write returns
+/// `Error::WouldBlock` instead of waiting for it, as the reader may itself
+/// have preempted the write, which then can not finish until it returns.
+///
+/// This suits `Copy` types too large to be read with a single load, such
+/// as telemetry structs of a few dozen bytes, which are written far less
+/// often than they take to read.
+pub struct SeqCell<T: Copy> {
+    seq: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+impl<T: Copy> SeqCell<T> {
+    /// Create a new cell holding `value`
+    pub const fn new(value: T) -> Self {
+        SeqCell {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Read the value, retrying until no write happened meanwhile. Returns
+    /// `Error::WouldBlock` if a write is in progress.
+    fn load(&self) -> Result<T, Error> {
+        loop {
+            let before = self.seq.load(Ordering::SeqCst);
+            if before & 1 != 0 {
+                return Err(Error::WouldBlock);
+            }
+            fence(Ordering::Acquire);
+
+            // NOTE(unsafe) the copy may be torn by a write, in which case
+            // the sequence number changed and the copy is never used
+            let value = unsafe { ptr::read_volatile(self.value.get() as *const MaybeUninit<T>) };
+
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::SeqCst) == before {
+                // NOTE(unsafe) no write happened during the copy
+                return Ok(unsafe { value.assume_init() });
+            }
+        }
+    }
+
+    /// Replace the value
+    ///
+    /// # Safety
+    ///
+    /// Only one writer may call this at a time.
+    unsafe fn store(&self, value: T) {
+        let seq = self.seq.load(Ordering::SeqCst);
+        self.seq.store(seq.wrapping_add(1), Ordering::SeqCst);
+        fence(Ordering::Release);
+
+        ptr::write_volatile(self.value.get(), value);
+
+        fence(Ordering::Release);
+        self.seq.store(seq.wrapping_add(2), Ordering::SeqCst);
+    }
+}
+
+impl<T: Copy> LockFree for SeqCell<T> {}
+
+/// Reading and writing a shared `SeqCell`
+pub trait SeqLock<T: Copy>: SharedItem<Data = SeqCell<T>> {
+    /// Read the latest value from application context, without masking
+    /// the interrupt. Returns `Error::WouldBlock` if the interrupt is in the
+    /// middle of a write, which can only be seen from another core.
+    fn load(&mut self) -> Result<T, Error>;
+
+    /// Read the value from the corresponding interrupt
+    fn load_int_context() -> Result<T, Error>;
+
+    /// Replace the value from the corresponding interrupt
+    fn store_int_context(value: T) -> Result<(), Error>;
+}
+
+impl<S, T: Copy> SeqLock<T> for S
+where
+    S: SharedItem<Data = SeqCell<T>>,
+{
+    fn load(&mut self) -> Result<T, Error> {
+        if !S::is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+
+        // NOTE(unsafe) the cell is initialized, and only shared references
+        // are made to it while it is used
+        unsafe { &*S::storage() }.load()
+    }
+
+    fn load_int_context() -> Result<T, Error> {
+        S::inspect_int_context(|cell| cell.load())?
+    }
+
+    fn store_int_context(value: T) -> Result<(), Error> {
+        // NOTE(unsafe) the interrupt is the only writer
+        S::inspect_int_context(|cell| unsafe { cell.store(value) })
+    }
+}
+
+/// A value written by the interrupt, and watched by any number of readers
+/// in the application, each of which tracks the last version it saw with a
+/// `Watcher`.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `Watch` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     (PRESSURE, shared::WatchCell<u32>, Interrupt::SAADC, shared::WatchCell::new(0)),
+/// );
+///
+/// #[interrupt]
+/// fn SAADC() {
+///     PRESSURE::send_int_context(read_pressure()).unwrap();
+/// }
+///
+/// static mut DISPLAY: shared::Watcher = shared::Watcher::new();
+/// static mut LOGGER: shared::Watcher = shared::Watcher::new();
+///
+/// if let Some(pressure) = PRESSURE::get_if_newer(unsafe { &mut DISPLAY }).unwrap() {
+///     show(pressure);
+/// }
+/// ```
+///
+/// Like a `SeqCell`, reads never mask the interrupt, and are retried if a
+/// write happened meanwhile. As they do not change the cell, readers do not
+/// need the token, and may run in any context. A read from a higher
+/// priority interrupt which preempted a write returns `Error::WouldBlock`.
+pub struct WatchCell<T: Copy> {
+    cell: SeqCell<T>,
+}
+
+impl<T: Copy> WatchCell<T> {
+    /// Create a new cell holding `value`, which watchers have not seen yet
+    pub const fn new(value: T) -> Self {
+        WatchCell {
+            cell: SeqCell::new(value),
+        }
+    }
+
+    /// The version of the value, which changes with every write
+    fn version(&self) -> usize {
+        self.cell.seq.load(Ordering::SeqCst) >> 1
+    }
+
+    /// Read the value, and the version it had. Returns `Error::WouldBlock`
+    /// if a write is in progress.
+    fn load(&self) -> Result<(T, usize), Error> {
+        loop {
+            let before = self.version();
+            let value = self.cell.load()?;
+            if self.version() == before {
+                return Ok((value, before));
+            }
+        }
+    }
+}
+
+impl<T: Copy> LockFree for WatchCell<T> {}
+
+/// The last version of a `WatchCell` seen by one of its readers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watcher {
+    seen: Option<usize>,
+}
+
+impl Watcher {
+    /// Create a watcher which has not seen any value yet
+    pub const fn new() -> Self {
+        Watcher { seen: None }
+    }
+}
+
+impl Default for Watcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writing and watching a shared `WatchCell`
+pub trait Watch<T: Copy>: SharedItem<Data = WatchCell<T>> {
+    /// Whether the value changed since `watcher` last saw it
+    fn has_changed(watcher: &Watcher) -> Result<bool, Error>;
+
+    /// The value, if it changed since `watcher` last saw it, which then
+    /// counts as seen
+    fn get_if_newer(watcher: &mut Watcher) -> Result<Option<T>, Error>;
+
+    /// The value, which then counts as seen by `watcher`
+    fn get(watcher: &mut Watcher) -> Result<T, Error>;
+
+    /// Replace the value from the corresponding interrupt
+    fn send_int_context(value: T) -> Result<(), Error>;
+}
+
+/// The watched cell, which may be read from any context
+fn watched<'a, S, T: Copy>() -> Result<&'a WatchCell<T>, Error>
+where
+    S: SharedItem<Data = WatchCell<T>>,
+{
+    if !S::is_initialized() {
+        return Err(Error::Uninitialized);
+    }
+
+    // NOTE(unsafe) the cell is initialized, and only shared references are
+    // made to it while it is used
+    Ok(unsafe { &*S::storage() })
+}
+
+impl<S, T: Copy> Watch<T> for S
+where
+    S: SharedItem<Data = WatchCell<T>>,
+{
+    fn has_changed(watcher: &Watcher) -> Result<bool, Error> {
+        Ok(watcher.seen != Some(watched::<S, T>()?.version()))
+    }
+
+    fn get_if_newer(watcher: &mut Watcher) -> Result<Option<T>, Error> {
+        let (value, version) = watched::<S, T>()?.load()?;
+        if watcher.seen == Some(version) {
+            return Ok(None);
+        }
+
+        watcher.seen = Some(version);
+        Ok(Some(value))
+    }
+
+    fn get(watcher: &mut Watcher) -> Result<T, Error> {
+        let (value, version) = watched::<S, T>()?.load()?;
+        watcher.seen = Some(version);
+        Ok(value)
+    }
+
+    fn send_int_context(value: T) -> Result<(), Error> {
+        // NOTE(unsafe) the interrupt is the only writer
+        S::inspect_int_context(|watch| unsafe { watch.cell.store(value) })
+    }
+}
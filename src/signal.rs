@@ -0,0 +1,242 @@
+//! Cheap signals from an interrupt to the application, kept in a single
+//! atomic word
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::lend::LockFree;
+use crate::{Error, SharedItem};
+
+/// A set of event bits, set by the interrupt and consumed by the
+/// application.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `Events` trait:
+///
+/// ```rust,ignore
+/// const RX_DONE: u32 = 1 << 0;
+/// const TX_DONE: u32 = 1 << 1;
+///
+/// shared!(
+///     (UART_EVENTS, shared::EventFlags, Interrupt::UARTE0_UARTE0, shared::EventFlags::new()),
+/// );
+///
+/// #[interrupt]
+/// fn UARTE0_UARTE0() {
+///     UART_EVENTS::set_bits_int_context(RX_DONE).unwrap();
+/// }
+///
+/// let events = token.fetch_and_clear().unwrap();
+/// if events & RX_DONE != 0 {
+///     handle_rx();
+/// }
+/// ```
+///
+/// On targets with atomic read-modify-write instructions, neither side
+/// masks the interrupt. Elsewhere, such as on ARMv6-M, `fetch_and_clear`
+/// disables the interrupt for a load and a store.
+pub struct EventFlags {
+    bits: AtomicU32,
+}
+
+impl EventFlags {
+    /// Create a new set of event flags, with no bits set
+    pub const fn new() -> Self {
+        EventFlags {
+            bits: AtomicU32::new(0),
+        }
+    }
+}
+
+impl LockFree for EventFlags {}
+
+impl Default for EventFlags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A count of events, incremented by the interrupt and read and cleared by
+/// the application.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `Counter` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     (PULSES, shared::EventCounter, Interrupt::GPIOTE, shared::EventCounter::new()),
+/// );
+///
+/// #[interrupt]
+/// fn GPIOTE() {
+///     PULSES::increment_int_context().unwrap();
+/// }
+///
+/// let rpm = token.read_and_clear().unwrap() * 60;
+/// ```
+///
+/// Like `EventFlags`, neither side masks the interrupt on targets with
+/// atomic read-modify-write instructions. The count wraps on overflow.
+pub struct EventCounter {
+    count: AtomicU32,
+}
+
+impl EventCounter {
+    /// Create a new counter, starting at zero
+    pub const fn new() -> Self {
+        EventCounter {
+            count: AtomicU32::new(0),
+        }
+    }
+}
+
+impl LockFree for EventCounter {}
+
+impl Default for EventCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Data kept in a single atomic word
+trait Word {
+    fn word(&self) -> &AtomicU32;
+}
+
+impl Word for EventFlags {
+    fn word(&self) -> &AtomicU32 {
+        &self.bits
+    }
+}
+
+impl Word for EventCounter {
+    fn word(&self) -> &AtomicU32 {
+        &self.count
+    }
+}
+
+/// The data shared with the interrupt, reached without masking the
+/// interrupt
+fn app_data<S: SharedItem>(_token: &mut S) -> Result<&S::Data, Error> {
+    if !S::is_initialized() {
+        return Err(Error::Uninitialized);
+    }
+
+    // NOTE(unsafe) the data is initialized, and only ever accessed through
+    // atomics
+    Ok(unsafe { &*S::storage() })
+}
+
+/// Swap the word shared with the interrupt for zero from application
+/// context, returning its previous value
+fn take_word<S>(token: &mut S) -> Result<u32, Error>
+where
+    S: SharedItem,
+    S::Data: Word,
+{
+    #[cfg(target_has_atomic = "32")]
+    {
+        Ok(app_data(token)?.word().swap(0, Ordering::SeqCst))
+    }
+
+    #[cfg(not(target_has_atomic = "32"))]
+    {
+        // Without atomic read-modify-write, the interrupt must not update
+        // the word between the load and the store
+        token.inspect_app_context(|data| {
+            let value = data.word().load(Ordering::SeqCst);
+            data.word().store(0, Ordering::SeqCst);
+            value
+        })
+    }
+}
+
+/// Setting and consuming the bits of a shared `EventFlags`
+pub trait Events: SharedItem<Data = EventFlags> {
+    /// Take every bit set since the last call, from application context,
+    /// leaving none set
+    fn fetch_and_clear(&mut self) -> Result<u32, Error>;
+
+    /// The bits currently set, read from application context without
+    /// clearing them
+    fn bits(&mut self) -> Result<u32, Error>;
+
+    /// Set bits from the corresponding interrupt, in addition to those
+    /// already set
+    fn set_bits_int_context(bits: u32) -> Result<(), Error>;
+}
+
+impl<S> Events for S
+where
+    S: SharedItem<Data = EventFlags>,
+{
+    fn fetch_and_clear(&mut self) -> Result<u32, Error> {
+        take_word(self)
+    }
+
+    fn bits(&mut self) -> Result<u32, Error> {
+        Ok(app_data(self)?.bits.load(Ordering::SeqCst))
+    }
+
+    fn set_bits_int_context(bits: u32) -> Result<(), Error> {
+        S::inspect_int_context(|flags| {
+            #[cfg(target_has_atomic = "32")]
+            flags.bits.fetch_or(bits, Ordering::SeqCst);
+
+            // Otherwise, the application only ever clears the word with the
+            // interrupt unable to run in between
+            #[cfg(not(target_has_atomic = "32"))]
+            flags
+                .bits
+                .store(flags.bits.load(Ordering::SeqCst) | bits, Ordering::SeqCst);
+        })
+    }
+}
+
+/// Counting events with a shared `EventCounter`
+pub trait Counter: SharedItem<Data = EventCounter> {
+    /// Take the number of events counted since the last call, from
+    /// application context, restarting the count at zero
+    fn read_and_clear(&mut self) -> Result<u32, Error>;
+
+    /// The number of events counted since the last `read_and_clear`, read
+    /// from application context without clearing it
+    fn count(&mut self) -> Result<u32, Error>;
+
+    /// Count one event from the corresponding interrupt
+    fn increment_int_context() -> Result<(), Error>;
+
+    /// Count several events at once from the corresponding interrupt
+    fn add_int_context(events: u32) -> Result<(), Error>;
+}
+
+impl<S> Counter for S
+where
+    S: SharedItem<Data = EventCounter>,
+{
+    fn read_and_clear(&mut self) -> Result<u32, Error> {
+        take_word(self)
+    }
+
+    fn count(&mut self) -> Result<u32, Error> {
+        Ok(app_data(self)?.count.load(Ordering::SeqCst))
+    }
+
+    fn increment_int_context() -> Result<(), Error> {
+        Self::add_int_context(1)
+    }
+
+    fn add_int_context(events: u32) -> Result<(), Error> {
+        S::inspect_int_context(|counter| {
+            #[cfg(target_has_atomic = "32")]
+            counter.count.fetch_add(events, Ordering::SeqCst);
+
+            // As for `EventFlags`, the application otherwise only ever
+            // clears the count with the interrupt unable to run in between
+            #[cfg(not(target_has_atomic = "32"))]
+            counter.count.store(
+                counter.count.load(Ordering::SeqCst).wrapping_add(events),
+                Ordering::SeqCst,
+            );
+        })
+    }
+}
@@ -0,0 +1,124 @@
+//! A state machine shared between the application and an interrupt, which
+//! only moves along the edges it was created with
+
+use crate::{Error, SharedItem};
+
+/// The current state of a machine, and the edges it may move along.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `Transitions` trait:
+///
+/// ```rust,ignore
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// enum Link {
+///     Idle,
+///     Connecting,
+///     Connected,
+/// }
+///
+/// fn edges(from: Link, to: Link) -> bool {
+///     use Link::*;
+///     matches!(
+///         (from, to),
+///         (Idle, Connecting) | (Connecting, Connected) | (Connecting, Idle) | (Connected, Idle)
+///     )
+/// }
+///
+/// shared!(
+///     (LINK, shared::StateMachine<Link>, Interrupt::RADIO, shared::StateMachine::new(Link::Idle, edges)),
+/// );
+///
+/// #[interrupt]
+/// fn RADIO() {
+///     let _ = LINK::transition_int_context(Link::Connecting, Link::Connected).unwrap();
+/// }
+///
+/// token.transition(Link::Idle, Link::Connecting).unwrap().unwrap();
+/// ```
+pub struct StateMachine<S> {
+    state: S,
+    allowed: fn(S, S) -> bool,
+}
+
+impl<S: Copy + PartialEq> StateMachine<S> {
+    /// Create a new state machine in the `initial` state, which may move
+    /// from one state to another when `allowed` returns `true` for them
+    pub const fn new(initial: S, allowed: fn(S, S) -> bool) -> Self {
+        StateMachine {
+            state: initial,
+            allowed,
+        }
+    }
+
+    /// The current state
+    pub fn state(&self) -> S {
+        self.state
+    }
+
+    /// Move from `from` to `to`, if that is the current state and the edge
+    /// is allowed
+    fn transition(&mut self, from: S, to: S) -> Result<(), TransitionError<S>> {
+        if self.state != from {
+            return Err(TransitionError::NotIn(self.state));
+        }
+        if !(self.allowed)(from, to) {
+            return Err(TransitionError::Illegal);
+        }
+
+        self.state = to;
+        Ok(())
+    }
+}
+
+/// The reasons a transition of a `StateMachine` can be refused. The state
+/// is left unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionError<S> {
+    /// The machine is not in the state the transition starts from, but in
+    /// the given one
+    NotIn(S),
+
+    /// The machine may not move along this edge
+    Illegal,
+}
+
+/// Checked transitions of a shared `StateMachine`.
+///
+/// The outer `Result` reports whether the machine could be accessed at
+/// all, the inner one whether the transition took place.
+pub trait Transitions<S>: SharedItem<Data = StateMachine<S>> {
+    /// The current state, read from application context
+    fn state(&mut self) -> Result<S, Error>;
+
+    /// Move from `from` to `to` from application context, with the
+    /// interrupt disabled between checking and changing the state
+    fn transition(&mut self, from: S, to: S) -> Result<Result<(), TransitionError<S>>, Error>;
+
+    /// The current state, read from the corresponding interrupt
+    fn state_int_context() -> Result<S, Error>;
+
+    /// Move from `from` to `to` from the corresponding interrupt
+    fn transition_int_context(from: S, to: S) -> Result<Result<(), TransitionError<S>>, Error>;
+}
+
+impl<I, S> Transitions<S> for I
+where
+    I: SharedItem<Data = StateMachine<S>>,
+    S: Copy + PartialEq,
+{
+    fn state(&mut self) -> Result<S, Error> {
+        self.access_app_context(|machine| machine.state())
+    }
+
+    fn transition(&mut self, from: S, to: S) -> Result<Result<(), TransitionError<S>>, Error> {
+        self.access_app_context(|machine| machine.transition(from, to))
+    }
+
+    fn state_int_context() -> Result<S, Error> {
+        I::access_int_context(|machine| machine.state())
+    }
+
+    fn transition_int_context(from: S, to: S) -> Result<Result<(), TransitionError<S>>, Error> {
+        I::access_int_context(|machine| machine.transition(from, to))
+    }
+}
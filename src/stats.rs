@@ -0,0 +1,137 @@
+//! Statistics over samples recorded by an interrupt, such as latencies or
+//! ADC readings
+
+use crate::{Error, SharedItem};
+
+/// The statistics of the samples recorded since the last reset, with an
+/// optional histogram of `BUCKETS` buckets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot<const BUCKETS: usize = 0> {
+    /// The number of samples
+    pub count: u32,
+    /// The smallest sample, or `u32::MAX` if there were none
+    pub min: u32,
+    /// The largest sample, or 0 if there were none
+    pub max: u32,
+    /// The sum of the samples
+    pub sum: u64,
+    /// The number of samples in each bucket of the histogram
+    pub buckets: [u32; BUCKETS],
+}
+
+impl<const BUCKETS: usize> Snapshot<BUCKETS> {
+    /// The statistics of no samples at all
+    pub const EMPTY: Self = Snapshot {
+        count: 0,
+        min: u32::MAX,
+        max: 0,
+        sum: 0,
+        buckets: [0; BUCKETS],
+    };
+
+    /// The mean of the samples, rounded down, if there were any
+    pub fn mean(&self) -> Option<u32> {
+        if self.count == 0 {
+            None
+        } else {
+            Some((self.sum / u64::from(self.count)) as u32)
+        }
+    }
+}
+
+/// Statistics accumulated by the interrupt, and collected by the
+/// application.
+///
+/// This is used as the data type of a `shared` item, and is accessed
+/// through the `Record` trait:
+///
+/// ```rust,ignore
+/// shared!(
+///     (LATENCY, shared::Stats<8>, Interrupt::TIMER0, shared::Stats::with_histogram(10)),
+/// );
+///
+/// #[interrupt]
+/// fn TIMER0() {
+///     LATENCY::record_int_context(cycles_since_compare()).unwrap();
+/// }
+///
+/// let latency = token.snapshot_and_reset().unwrap();
+/// log(latency.min, latency.max, latency.mean(), latency.buckets);
+/// ```
+///
+/// Recording a sample only takes a few comparisons and additions. The
+/// counts and the sum saturate rather than wrap.
+pub struct Stats<const BUCKETS: usize = 0> {
+    current: Snapshot<BUCKETS>,
+    width: u32,
+}
+
+impl<const BUCKETS: usize> Stats<BUCKETS> {
+    /// Create new statistics, without a histogram
+    pub const fn new() -> Self {
+        Stats::with_histogram(u32::MAX)
+    }
+
+    /// Create new statistics, with a histogram whose buckets are `width`
+    /// wide. The last bucket also counts every larger sample.
+    pub const fn with_histogram(width: u32) -> Self {
+        assert!(width != 0, "the buckets of a histogram can not be empty");
+        Stats {
+            current: Snapshot::EMPTY,
+            width,
+        }
+    }
+
+    /// Add a sample to the statistics
+    pub fn record(&mut self, sample: u32) {
+        let current = &mut self.current;
+        current.count = current.count.saturating_add(1);
+        current.min = current.min.min(sample);
+        current.max = current.max.max(sample);
+        current.sum = current.sum.saturating_add(u64::from(sample));
+
+        if let Some(last) = BUCKETS.checked_sub(1) {
+            let bucket = ((sample / self.width) as usize).min(last);
+            current.buckets[bucket] = current.buckets[bucket].saturating_add(1);
+        }
+    }
+
+    /// The statistics recorded so far
+    pub fn snapshot(&self) -> Snapshot<BUCKETS> {
+        self.current
+    }
+
+    /// Take the statistics recorded so far, and start over
+    pub fn snapshot_and_reset(&mut self) -> Snapshot<BUCKETS> {
+        core::mem::replace(&mut self.current, Snapshot::EMPTY)
+    }
+}
+
+impl<const BUCKETS: usize> Default for Stats<BUCKETS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Recording samples into shared `Stats`, and collecting them
+pub trait Record<const BUCKETS: usize>: SharedItem<Data = Stats<BUCKETS>> {
+    /// Take the statistics recorded so far from application context, and
+    /// start over. The interrupt is disabled while they are copied.
+    fn snapshot_and_reset(&mut self) -> Result<Snapshot<BUCKETS>, Error>;
+
+    /// Add a sample from the corresponding interrupt
+    fn record_int_context(sample: u32) -> Result<(), Error>;
+}
+
+impl<S, const BUCKETS: usize> Record<BUCKETS> for S
+where
+    S: SharedItem<Data = Stats<BUCKETS>>,
+{
+    fn snapshot_and_reset(&mut self) -> Result<Snapshot<BUCKETS>, Error> {
+        self.access_app_context(Stats::snapshot_and_reset)
+    }
+
+    fn record_int_context(sample: u32) -> Result<(), Error> {
+        S::access_int_context(|stats| stats.record(sample))
+    }
+}
@@ -0,0 +1,120 @@
+//! Sharing data between the Cortex-M7 and Cortex-M4 cores of dual-core
+//! STM32H7 parts
+//!
+//! Masking an interrupt only affects the core doing the masking, so data
+//! touched by one core and by an interrupt handled on the other is not
+//! protected by the NVIC alone. Items given a hardware semaphore of the HSEM
+//! additionally hold that semaphore for the duration of every access, from
+//! either core:
+//!
+//! ```rust,ignore
+//! shared!(
+//!     (MAILBOX, Mailbox, Interrupt::HSEM1, Mailbox::new(), spinlock = 3),
+//! );
+//! ```
+//!
+//! Both cores must be built with the same `shared!` invocation, and their
+//! linker scripts must place its statics at the same addresses, in memory
+//! visible to both, such as the AXI SRAM. Each item should use its own
+//! semaphore, and semaphores used by the HAL, such as for the clock
+//! configuration, should be avoided.
+
+use core::ptr;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::Error;
+
+/// The base address of the HSEM
+const HSEM_BASE: usize = 0x5802_6400;
+
+/// The offset of `HSEM_R0`, written to release a semaphore. The others
+/// follow at 4 byte intervals
+const R0: usize = 0x000;
+
+/// The offset of `HSEM_RLR0`, read to take a semaphore in a single step.
+/// The others follow at 4 byte intervals
+const RLR0: usize = 0x080;
+
+/// The `LOCK` bit of `HSEM_Rx` and `HSEM_RLRx`
+const LOCK: u32 = 1 << 31;
+
+/// The address of `CPUID` in the system control block
+const CPUID: usize = 0xE000_ED00;
+
+/// The part number of the Cortex-M7 in `CPUID`
+const CORTEX_M7: u32 = 0xC27;
+
+/// The `COREID` of the Cortex-M7 in the HSEM
+const CM7_ID: u8 = 3;
+
+/// The `COREID` of the Cortex-M4 in the HSEM
+const CM4_ID: u8 = 1;
+
+/// The number of hardware semaphores
+pub const SEMAPHORES: u8 = 32;
+
+/// The core holding each semaphore, or 0 if it is not held by a `shared`
+/// item
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_OWNER: AtomicU8 = AtomicU8::new(0);
+static OWNERS: [AtomicU8; SEMAPHORES as usize] = [NO_OWNER; SEMAPHORES as usize];
+
+/// The `COREID` of the core this code is running on, as used by the HSEM
+pub fn core() -> u8 {
+    // NOTE(unsafe) atomic read with no side effects
+    let cpuid = unsafe { ptr::read_volatile(CPUID as *const u32) };
+    if (cpuid >> 4) & 0xFFF == CORTEX_M7 {
+        CM7_ID
+    } else {
+        CM4_ID
+    }
+}
+
+/// The address of the register of the semaphore, in the registers starting
+/// at `offset`
+fn semaphore(offset: usize, n: u8) -> *mut u32 {
+    assert!(n < SEMAPHORES);
+    (HSEM_BASE + offset + 4 * usize::from(n)) as *mut u32
+}
+
+/// Take the semaphore, spinning while the other core holds it. Returns
+/// `Ok(false)` without taking it if this core already holds it and
+/// `nested` is set, and `Error::Reentrant` if it is not.
+pub(crate) fn lock(n: u8, nested: bool) -> Result<bool, Error> {
+    let owner = core();
+    let taken = u32::from(owner) << 8 | LOCK;
+    loop {
+        // The HSEM grants a semaphore to a core it already belongs to, so the
+        // owner is checked first, with interrupts masked so that a
+        // preempting access can not take it in between
+        let attempt = crate::__interrupt_free(|_| {
+            if OWNERS[usize::from(n)].load(Ordering::SeqCst) == owner {
+                return Some(if nested {
+                    Ok(false)
+                } else {
+                    Err(Error::Reentrant)
+                });
+            }
+
+            // NOTE(unsafe) reading RLR takes the semaphore if it is free
+            if unsafe { ptr::read_volatile(semaphore(RLR0, n)) } == taken {
+                OWNERS[usize::from(n)].store(owner, Ordering::SeqCst);
+                return Some(Ok(true));
+            }
+
+            None
+        });
+
+        if let Some(result) = attempt {
+            return result;
+        }
+    }
+}
+
+/// Release a semaphore taken by `lock`
+pub(crate) fn unlock(n: u8) {
+    OWNERS[usize::from(n)].store(0, Ordering::SeqCst);
+
+    // NOTE(unsafe) writing the owning core with LOCK cleared releases it
+    unsafe { ptr::write_volatile(semaphore(R0, n), u32::from(core()) << 8) }
+}
@@ -0,0 +1,218 @@
+//! Software timers, scheduled by the application and advanced by a timer
+//! interrupt
+
+use crate::{Error, SharedItem};
+
+/// The number of timers a `TimerWheel` can hold
+pub const TIMERS: usize = 32;
+
+/// One of the timers of a `TimerWheel`, numbered below `TIMERS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(pub u8);
+
+impl TimerId {
+    /// The bit of the timer in the masks of the wheel
+    fn bit(self) -> u32 {
+        assert!(usize::from(self.0) < TIMERS, "no such timer");
+        1 << self.0
+    }
+}
+
+/// A hashed timer wheel with `SLOTS` slots, counting the ticks of a timer
+/// interrupt.
+///
+/// This is used as the data type of a `shared` item guarded by the timer
+/// interrupt, and is accessed through the `Timers` trait. The application
+/// schedules timers, the interrupt advances the wheel by one tick at a
+/// time, and the application then drains the timers which fired:
+///
+/// ```rust,ignore
+/// const BLINK: shared::TimerId = shared::TimerId(0);
+/// const TIMEOUT: shared::TimerId = shared::TimerId(1);
+///
+/// shared!(
+///     (TIMERS, shared::TimerWheel<16>, Interrupt::RTC0, shared::TimerWheel::new()),
+/// );
+///
+/// #[interrupt]
+/// fn RTC0() {
+///     clear_tick_event();
+///     TIMERS::tick_int_context().unwrap();
+/// }
+///
+/// token.schedule(BLINK, 500).unwrap();
+/// loop {
+///     for timer in token.drain_fired().unwrap() {
+///         match timer {
+///             BLINK => toggle_led(),
+///             TIMEOUT => give_up(),
+///             _ => {}
+///         }
+///     }
+/// }
+/// ```
+///
+/// A timer is kept in the slot its deadline falls in, so each tick only
+/// looks at the timers of one slot. Timers further than `SLOTS` ticks away
+/// stay in their slot for the rounds in between.
+pub struct TimerWheel<const SLOTS: usize> {
+    now: u32,
+    slots: [u32; SLOTS],
+    deadlines: [u32; TIMERS],
+    fired: u32,
+}
+
+impl<const SLOTS: usize> TimerWheel<SLOTS> {
+    /// Create a new wheel at tick zero, with no timers scheduled
+    pub const fn new() -> Self {
+        TimerWheel {
+            now: 0,
+            slots: [0; SLOTS],
+            deadlines: [0; TIMERS],
+            fired: 0,
+        }
+    }
+
+    /// The number of ticks since the wheel was created, which wraps on
+    /// overflow
+    pub fn now(&self) -> u32 {
+        self.now
+    }
+
+    /// Whether the timer is scheduled and has not fired yet
+    pub fn is_scheduled(&self, timer: TimerId) -> bool {
+        self.slots.iter().any(|slot| slot & timer.bit() != 0)
+    }
+
+    /// The slot holding timers due at `tick`
+    fn slot(tick: u32) -> usize {
+        tick as usize % SLOTS
+    }
+
+    /// Fire the timer `delay` ticks from now, replacing any earlier
+    /// schedule. A delay of zero fires the timer straight away.
+    pub fn schedule(&mut self, timer: TimerId, delay: u32) {
+        self.cancel(timer);
+
+        if delay == 0 {
+            self.fired |= timer.bit();
+            return;
+        }
+
+        let deadline = self.now.wrapping_add(delay);
+        self.deadlines[usize::from(timer.0)] = deadline;
+        self.slots[Self::slot(deadline)] |= timer.bit();
+    }
+
+    /// Stop the timer, and forget that it fired if it was not drained yet.
+    /// Returns whether the timer was scheduled.
+    pub fn cancel(&mut self, timer: TimerId) -> bool {
+        let scheduled = self.is_scheduled(timer);
+        for slot in self.slots.iter_mut() {
+            *slot &= !timer.bit();
+        }
+        self.fired &= !timer.bit();
+        scheduled
+    }
+
+    /// Advance the wheel by one tick, returning whether any timer fired
+    pub fn tick(&mut self) -> bool {
+        self.now = self.now.wrapping_add(1);
+
+        let slot = &mut self.slots[Self::slot(self.now)];
+        let mut due = 0;
+        for timer in Fired(*slot) {
+            if self.deadlines[usize::from(timer.0)] == self.now {
+                due |= timer.bit();
+            }
+        }
+
+        *slot &= !due;
+        self.fired |= due;
+        due != 0
+    }
+
+    /// Take the timers which fired since the last call
+    pub fn drain_fired(&mut self) -> Fired {
+        Fired(core::mem::replace(&mut self.fired, 0))
+    }
+}
+
+impl<const SLOTS: usize> Default for TimerWheel<SLOTS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The timers which fired, as returned by `drain_fired`, in order of their
+/// numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fired(u32);
+
+impl Fired {
+    /// Whether no timer fired
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether the timer fired
+    pub fn contains(&self, timer: TimerId) -> bool {
+        self.0 & timer.bit() != 0
+    }
+}
+
+impl Iterator for Fired {
+    type Item = TimerId;
+
+    fn next(&mut self) -> Option<TimerId> {
+        if self.0 == 0 {
+            return None;
+        }
+
+        let timer = self.0.trailing_zeros() as u8;
+        self.0 &= self.0 - 1;
+        Some(TimerId(timer))
+    }
+}
+
+/// Scheduling and draining the timers of a shared `TimerWheel`.
+///
+/// The application side methods disable the timer interrupt while they
+/// change the wheel.
+pub trait Timers<const SLOTS: usize>: SharedItem<Data = TimerWheel<SLOTS>> {
+    /// Fire the timer `delay` ticks from now, from application context
+    fn schedule(&mut self, timer: TimerId, delay: u32) -> Result<(), Error>;
+
+    /// Stop the timer from application context, returning whether it was
+    /// scheduled
+    fn cancel(&mut self, timer: TimerId) -> Result<bool, Error>;
+
+    /// Take the timers which fired since the last call, from application
+    /// context
+    fn drain_fired(&mut self) -> Result<Fired, Error>;
+
+    /// Advance the wheel by one tick from the timer interrupt, returning
+    /// whether any timer fired
+    fn tick_int_context() -> Result<bool, Error>;
+}
+
+impl<S, const SLOTS: usize> Timers<SLOTS> for S
+where
+    S: SharedItem<Data = TimerWheel<SLOTS>>,
+{
+    fn schedule(&mut self, timer: TimerId, delay: u32) -> Result<(), Error> {
+        self.access_app_context(|wheel| wheel.schedule(timer, delay))
+    }
+
+    fn cancel(&mut self, timer: TimerId) -> Result<bool, Error> {
+        self.access_app_context(|wheel| wheel.cancel(timer))
+    }
+
+    fn drain_fired(&mut self) -> Result<Fired, Error> {
+        self.access_app_context(TimerWheel::drain_fired)
+    }
+
+    fn tick_int_context() -> Result<bool, Error> {
+        S::access_int_context(TimerWheel::tick)
+    }
+}
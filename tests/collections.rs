@@ -0,0 +1,83 @@
+//! Host tests of the accessors for `heapless` collections, run against the
+//! simulated interrupt controller:
+//!
+//! ```text
+//! cargo test --features std,heapless
+//! ```
+//!
+//! Each test uses an interrupt of its own, so they may run at once.
+
+use heapless::{Deque, String, Vec};
+
+use shared::mock::{fire, Irq, MockNvic};
+use shared::prelude::*;
+use shared::{shared, Error};
+
+shared!(
+    (SAMPLES, heapless::Vec<u16, 2>, Irq(5)),
+    (EVENTS, heapless::Deque<u8, 2>, Irq(6)),
+    (LINE, heapless::String<8>, Irq(7)),
+);
+
+fn nvic() -> MockNvic {
+    // NOTE(unsafe) each test only enables its own interrupt
+    unsafe { MockNvic::steal() }
+}
+
+#[test]
+fn vec() {
+    let mut token = SAMPLES::set_initial_nvic(Vec::new(), &mut nvic()).unwrap();
+
+    fire(Irq(5), || {
+        assert_eq!(SAMPLES::push_int_context(1), Ok(()));
+        assert_eq!(SAMPLES::push_int_context(2), Ok(()));
+        assert_eq!(SAMPLES::push_int_context(3), Err(Error::Full));
+    });
+    assert_eq!(SAMPLES::push_int_context(3), Err(Error::WrongContext));
+
+    assert_eq!(token.pop(), Ok(Some(2)));
+    assert_eq!(token.push(4), Ok(()));
+    assert_eq!(token.push(5), Err(Error::Full));
+
+    let mut drained = Vec::<u16, 2>::new();
+    assert_eq!(
+        token.drain_with(|sample| drained.push(sample).unwrap()),
+        Ok(2)
+    );
+    assert_eq!(drained, [4, 1]);
+    assert_eq!(fire(Irq(5), SAMPLES::pop_int_context), Ok(None));
+}
+
+#[test]
+fn deque() {
+    let mut token = EVENTS::set_initial_nvic(Deque::new(), &mut nvic()).unwrap();
+
+    assert_eq!(token.push_back(1), Ok(()));
+    assert_eq!(token.push_back(2), Ok(()));
+    assert_eq!(token.push_back(3), Err(Error::Full));
+
+    fire(Irq(6), || {
+        assert_eq!(EVENTS::pop_front_int_context(), Ok(Some(1)));
+        assert_eq!(EVENTS::pop_front_int_context(), Ok(Some(2)));
+        assert_eq!(EVENTS::pop_front_int_context(), Ok(None));
+        assert_eq!(EVENTS::push_back_int_context(3), Ok(()));
+    });
+
+    assert_eq!(token.pop_front(), Ok(Some(3)));
+    assert_eq!(token.pop_front(), Ok(None));
+}
+
+#[test]
+fn string() {
+    let mut token = LINE::set_initial_nvic(String::new(), &mut nvic()).unwrap();
+
+    fire(Irq(7), || {
+        assert_eq!(LINE::push_str_int_context("hello"), Ok(()));
+        assert_eq!(LINE::push_str_int_context("world"), Err(Error::Full));
+        assert_eq!(LINE::pop_char_int_context(), Ok(Some('o')));
+    });
+
+    assert_eq!(token.pop_char(), Ok(Some('l')));
+    assert_eq!(token.push_str("p!"), Ok(()));
+    assert_eq!(token.read_app_context(|line| line == "help!"), Ok(true));
+}
@@ -0,0 +1,367 @@
+//! Host tests of `shared` items, run against the simulated interrupt
+//! controller of the `std` feature:
+//!
+//! ```text
+//! cargo test --features std
+//! ```
+//!
+//! The simulated controller is global, so the tests take turns through
+//! `serial`, which also resets it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use shared::mock::{fire, reset, Irq, MockNvic};
+use shared::{
+    init_all, shared, Dma, DmaBuffer, DoubleBuffer, Error, GrantBuffer, Grants, PingPong, Pool,
+    Queue, SeqCell, SeqLock, Shared, SlotPool, Spsc,
+};
+
+const TIMER: Irq = Irq(3);
+const RADIO: Irq = Irq(4);
+const UART: Irq = Irq(5);
+
+shared! {
+    COUNTER: u32 => TIMER,
+    TICKS: u32 = 5 => TIMER,
+    FIRST: u8 => TIMER,
+    SECOND: u8 => RADIO, reader = SecondReader,
+    FIFO: Queue<u8, 4> => TIMER,
+    TELEMETRY: SeqCell<[u32; 8]> => TIMER,
+    SLOTS: Pool<u32, 2> => TIMER,
+    SPARE: Pool<u32, 2> => RADIO,
+    FRAMES: GrantBuffer<8> => TIMER,
+    HALVES: DoubleBuffer<u8> => TIMER,
+    RX: DmaBuffer<[u8; 4]> => RADIO,
+}
+
+static BAUD: Shared<u32, Irq> = Shared::new(UART);
+static PARITY: Shared<bool, Irq> = Shared::with_initial(UART, false);
+
+/// Run the tests one at a time, each starting with every interrupt
+/// disabled, inactive and not pending
+fn serial() -> MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    let guard = SERIAL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    reset();
+    guard
+}
+
+fn nvic() -> MockNvic {
+    // NOTE(unsafe) the tests run one at a time
+    unsafe { MockNvic::steal() }
+}
+
+#[test]
+fn init_and_teardown() {
+    let _serial = serial();
+
+    let token = COUNTER::set_initial(1).unwrap();
+    assert!(COUNTER::set_initial(2).is_err());
+    assert!(COUNTER::take_token().is_none());
+    assert_eq!(token.into_inner(), 1);
+
+    let mut token = COUNTER::set_initial_nvic(3, &mut nvic()).unwrap();
+    assert_eq!(
+        fire(TIMER, || COUNTER::modify_int_context(|c| *c).unwrap()),
+        3
+    );
+    drop(token);
+    assert!(COUNTER::take_token().is_none());
+
+    // Dropping the token disabled the interrupt again
+    token = COUNTER::set_initial(4).unwrap();
+    assert_eq!(token.modify_app_context(|c| *c).unwrap(), 4);
+
+    let mut ticks = TICKS::take_token().unwrap();
+    assert!(TICKS::take_token().is_none());
+    assert_eq!(ticks.modify_app_context(|t| *t).unwrap(), 5);
+}
+
+#[test]
+fn without_a_macro() {
+    let _serial = serial();
+
+    let mut baud = BAUD.set_initial_nvic(9600, &mut nvic()).ok().unwrap();
+    assert!(BAUD.set_initial(115_200).is_err());
+    fire(UART, || BAUD.modify_int_context(|b| *b *= 2).unwrap());
+    assert_eq!(baud.replace(115_200).unwrap(), 19_200);
+    assert_eq!(BAUD.modify_int_context(|b| *b), Err(Error::WrongContext));
+    assert_eq!(baud.into_inner(), 115_200);
+
+    let mut parity = PARITY.take_token().unwrap();
+    assert!(PARITY.take_token().is_none());
+    assert!(!parity.modify_app_context(|p| *p).unwrap());
+}
+
+#[test]
+fn readers() {
+    let _serial = serial();
+
+    let mut token = SECOND::set_initial_nvic(7, &mut nvic()).ok().unwrap();
+    let reader = token.reader();
+    let copy = reader;
+    assert_eq!(reader.read_app_context(|s| *s).unwrap(), 7);
+
+    token.modify_app_context(|s| *s += 1).unwrap();
+    assert_eq!(copy.read(|s| *s).unwrap(), 8);
+    assert_eq!(fire(RADIO, || copy.read(|s| *s).unwrap()), 8);
+}
+
+#[test]
+fn masking() {
+    let _serial = serial();
+
+    let mut token = COUNTER::set_initial_nvic(0, &mut nvic()).unwrap();
+    let ran = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let guard = token.lock_app_context().unwrap();
+        let isr = scope.spawn(|| {
+            fire(TIMER, || {
+                COUNTER::modify_int_context(|c| *c += 1).unwrap();
+                ran.store(true, Ordering::SeqCst);
+            })
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!ran.load(Ordering::SeqCst));
+        drop(guard);
+        isr.join().unwrap();
+    });
+
+    assert!(ran.load(Ordering::SeqCst));
+    assert_eq!(token.modify_app_context(|c| *c).unwrap(), 1);
+}
+
+#[test]
+fn wrong_context() {
+    let _serial = serial();
+
+    let _token = COUNTER::set_initial_nvic(0, &mut nvic()).unwrap();
+    assert_eq!(
+        COUNTER::modify_int_context(|c| *c),
+        Err(Error::WrongContext)
+    );
+    assert_eq!(COUNTER::read_int_context(|c| *c), Err(Error::WrongContext));
+    assert_eq!(
+        fire(TIMER, || SECOND::modify_int_context(|s| *s)),
+        Err(Error::WrongContext)
+    );
+}
+
+#[test]
+fn queue_wraps_around() {
+    let _serial = serial();
+
+    let mut token = FIFO::set_initial_nvic(Queue::to_app(), &mut nvic())
+        .ok()
+        .unwrap();
+
+    for round in 0..3u8 {
+        fire(TIMER, || {
+            for i in 0..4 {
+                assert_eq!(FIFO::enqueue_int_context(round * 4 + i).unwrap(), Ok(()));
+            }
+            assert_eq!(FIFO::enqueue_int_context(0xff).unwrap(), Err(0xff));
+        });
+
+        for i in 0..4 {
+            assert_eq!(token.dequeue().unwrap(), Some(round * 4 + i));
+        }
+        assert_eq!(token.dequeue().unwrap(), None);
+    }
+
+    assert_eq!(token.enqueue(0), Err(Error::WrongContext));
+}
+
+#[test]
+fn lock_free() {
+    let _serial = serial();
+
+    let mut fifo = FIFO::set_initial_nvic(Queue::to_app(), &mut nvic())
+        .ok()
+        .unwrap();
+
+    // The application reads these without masking, so they are never
+    // borrowed mutably
+    assert_eq!(fifo.modify_app_context(|_| ()), Err(Error::LockFree));
+    assert_eq!(fifo.replace(Queue::to_app()).err(), Some(Error::LockFree));
+    assert_eq!(fifo.read_app_context(|fifo| fifo.len()), Ok(0));
+    fire(TIMER, || {
+        assert_eq!(FIFO::modify_int_context(|_| ()), Err(Error::LockFree));
+        assert_eq!(
+            FIFO::read_int_context(|_| FIFO::enqueue_int_context(1)),
+            Ok(Err(Error::Reentrant))
+        );
+    });
+
+    // Nor may the half of the interrupt be borrowed twice
+    drop(fifo);
+    let mut halves = HALVES::set_initial_nvic(DoubleBuffer::new(0, 0), &mut nvic())
+        .ok()
+        .unwrap();
+    fire(TIMER, || {
+        let nested = HALVES::modify_int_half_int_context(|half| {
+            *half = 1;
+            HALVES::modify_int_half_int_context(|_| ())
+        });
+        assert_eq!(nested, Ok(Err(Error::Reentrant)));
+    });
+    halves.flip().unwrap();
+    assert_eq!(halves.modify_app_half(|half| *half), Ok(1));
+}
+
+#[test]
+fn seqlock_retries() {
+    let _serial = serial();
+
+    let mut token = TELEMETRY::set_initial_nvic(SeqCell::new([0; 8]), &mut nvic())
+        .ok()
+        .unwrap();
+    let done = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for i in 1..=2000 {
+                fire(TIMER, || TELEMETRY::store_int_context([i; 8]).unwrap());
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+
+        // Every value read is whole, whether or not the read raced a write
+        while !done.load(Ordering::SeqCst) {
+            match token.load() {
+                Ok(value) => assert!(value.iter().all(|&word| word == value[0])),
+                Err(err) => assert_eq!(err, Error::WouldBlock),
+            }
+        }
+    });
+
+    assert_eq!(token.load().unwrap(), [2000; 8]);
+}
+
+#[test]
+fn pool_alloc_and_free() {
+    let _serial = serial();
+
+    let mut token = SLOTS::set_initial_nvic(Pool::new(), &mut nvic())
+        .ok()
+        .unwrap();
+    let mut other = SPARE::set_initial(Pool::new()).ok().unwrap();
+
+    let (mut first, second) = fire(TIMER, || {
+        let first = SLOTS::alloc_int_context(1).unwrap().unwrap();
+        let second = SLOTS::alloc_int_context(2).unwrap().unwrap();
+        assert_eq!(SLOTS::alloc_int_context(3).unwrap().err(), Some(3));
+        (first, second)
+    });
+
+    *token.get_mut(&mut first).unwrap() += 10;
+    assert_eq!(token.free(first).unwrap(), 11);
+
+    // The other pool hands the handle back, so its slot can still be freed
+    let (err, second) = other.free(second).err().unwrap();
+    assert_eq!(err, Error::InvalidHandle);
+    assert_eq!(token.free(second).unwrap(), 2);
+
+    let third = fire(TIMER, || SLOTS::alloc_int_context(3).unwrap().unwrap());
+    assert_eq!(token.free(third).unwrap(), 3);
+}
+
+#[test]
+fn grants() {
+    let _serial = serial();
+
+    let mut token = FRAMES::set_initial_nvic(GrantBuffer::new(), &mut nvic())
+        .ok()
+        .unwrap();
+
+    let mut grant = fire(TIMER, || FRAMES::grant_int_context(4).unwrap().unwrap());
+    assert!(fire(TIMER, || FRAMES::grant_int_context(1).unwrap()).is_none());
+    assert_eq!(token.replace(GrantBuffer::new()).err(), Some(Error::Lent));
+    assert_eq!(token.read_app_context(|_| ()), Err(Error::Lent));
+    assert_eq!(
+        fire(TIMER, || FRAMES::modify_int_context(|_| ())),
+        Err(Error::Lent)
+    );
+
+    grant[..3].copy_from_slice(b"abc");
+    grant.commit(3);
+
+    let read = token.read_grant().unwrap().unwrap();
+    assert_eq!(
+        fire(TIMER, || FRAMES::modify_int_context(|_| ())),
+        Err(Error::Lent)
+    );
+    assert_eq!(&read[..], b"abc");
+    read.release(3);
+    assert!(token.read_grant().unwrap().is_none());
+    assert_eq!(token.read_app_context(|_| ()), Ok(()));
+    assert_eq!(
+        token.replace(GrantBuffer::new()).err(),
+        Some(Error::LockFree)
+    );
+}
+
+#[test]
+fn dma_transfer() {
+    let _serial = serial();
+
+    let mut token = RX::set_initial_nvic(DmaBuffer::new([0; 4]), &mut nvic())
+        .ok()
+        .unwrap();
+
+    // The closure stands in for the DMA engine, writing to the buffer
+    token
+        .start_transfer(|buf| unsafe { *buf = *b"ping" })
+        .unwrap();
+    assert_eq!(token.start_transfer(|_| ()), Err(Error::WouldBlock));
+    assert_eq!(token.modify_app_context(|_| ()), Err(Error::Lent));
+
+    fire(RADIO, || {
+        assert_eq!(
+            RX::modify_int_context(|dma| *dma = DmaBuffer::new([0; 4])),
+            Err(Error::Lent)
+        );
+        assert_eq!(RX::on_complete(|buf| *buf), Ok(Some(*b"ping")));
+        assert_eq!(RX::on_complete(|buf| *buf), Ok(None));
+    });
+
+    assert!(token.is_complete());
+    assert_eq!(
+        token.modify_app_context(|dma| dma.buffer().copied()),
+        Ok(Some(*b"ping"))
+    );
+}
+
+#[test]
+fn init_all_rolls_back() {
+    let _serial = serial();
+
+    let second = SECOND::set_initial(2).unwrap();
+    assert!(init_all!((FIRST, 1), (SECOND, 2)).is_none());
+
+    // Neither item was initialized
+    drop(second);
+    assert!(FIRST::take_token().is_none());
+    let (mut first, mut second) = init_all!((FIRST, 1), (SECOND, 2)).unwrap();
+    assert_eq!(first.modify_app_context(|f| *f).unwrap(), 1);
+    assert_eq!(second.modify_app_context(|s| *s).unwrap(), 2);
+}
+
+#[test]
+fn init_all_keeps_interrupts_enabled() {
+    let _serial = serial();
+
+    let _counter = COUNTER::set_initial_nvic(0, &mut nvic()).unwrap();
+    assert!(init_all!((FIRST, 1), (FIRST, 2)).is_none());
+
+    // The interrupt is still enabled, which `set_initial` refuses
+    assert!(FIRST::set_initial(1).is_err());
+    let _first = init_all!((FIRST, 1)).unwrap();
+}
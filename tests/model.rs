@@ -0,0 +1,284 @@
+//! The handoff between application and interrupt context, with the
+//! interrupt preempting the application at every point where it may, one
+//! interleaving after another:
+//!
+//! ```text
+//! cargo test --features std
+//! ```
+//!
+//! The application runs under `with_preemption`, which calls a hook at
+//! every access to the simulated interrupt controller, and wherever the
+//! application calls `preemption_point`, such as half way through its
+//! access to the data. Each interleaving pends the interrupts at one of
+//! those points, and they run at the first point where they are enabled
+//! and not masked, as they would on hardware. The points are counted as
+//! they are reached, so every one of them is tried in turn.
+//!
+//! A higher priority interrupt may enable the interrupt at any point,
+//! including between the check and the disable in `IrqSet::disable`, which
+//! lets the interrupt preempt the application while it holds the data. The
+//! flag then turns the interrupt away with `Error::Reentrant`.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use shared::mock::{preempt, preemption_point, reset, with_preemption, Irq, MockNvic};
+use shared::{shared, Error};
+
+const INT: Irq = Irq(3);
+const HIGH: Irq = Irq(4);
+
+shared! {
+    DATA: u32 => INT,
+}
+
+/// Set while either context accesses the data
+static INSIDE: AtomicBool = AtomicBool::new(false);
+
+/// The data as last seen by the interrupt
+static SEEN: AtomicU32 = AtomicU32::new(0);
+
+/// Run the tests one at a time, each starting with every interrupt
+/// disabled, inactive and not pending
+fn serial() -> MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    let guard = SERIAL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    reset();
+    guard
+}
+
+fn nvic() -> MockNvic {
+    // NOTE(unsafe) the tests run one at a time
+    unsafe { MockNvic::steal() }
+}
+
+/// Mark the data as being accessed until `f` returns, which no other
+/// context may be doing
+fn inside<R>(f: impl FnOnce() -> R) -> R {
+    assert!(
+        !INSIDE.swap(true, Ordering::SeqCst),
+        "two contexts accessed the data at once"
+    );
+    let ret = f();
+    INSIDE.store(false, Ordering::SeqCst);
+    ret
+}
+
+/// Whether the interrupt is enabled and not masked, found by running an
+/// empty handler
+fn can_run() -> bool {
+    preempt(INT, || ()).is_some()
+}
+
+/// What an interleaving ended with
+#[derive(Debug, Default)]
+struct Outcome {
+    /// Whether the point at which the interrupts were pended was reached
+    reached: bool,
+    /// What the interrupt got, if it ran
+    int: Option<Result<(), Error>>,
+    /// Whether the interrupt preempted the application while it held the
+    /// data
+    preempted_inside: bool,
+}
+
+/// Run `app` with `pend` called at preemption point `at`. From then on,
+/// `int` runs at the first point where it can, or once `app` returns.
+fn interleave<A, R>(at: usize, app: A, pend: fn(), int: fn() -> Result<(), Error>) -> (R, Outcome)
+where
+    A: FnOnce() -> R,
+{
+    let outcome = Rc::new(Cell::new(None));
+    let reached = Rc::new(Cell::new(false));
+    let preempted_inside = Rc::new(Cell::new(false));
+
+    let hook = {
+        let (outcome, reached, preempted_inside) =
+            (outcome.clone(), reached.clone(), preempted_inside.clone());
+        let mut point = 0;
+        move || {
+            if point == at {
+                reached.set(true);
+                pend();
+            }
+            point += 1;
+            if reached.get() && outcome.get().is_none() {
+                let held = INSIDE.load(Ordering::SeqCst);
+                if let Some(ret) = preempt(INT, int) {
+                    preempted_inside.set(held);
+                    outcome.set(Some(ret));
+                }
+            }
+        }
+    };
+    let ret = with_preemption(hook, app);
+
+    // An interrupt which was pended but could not preempt runs once the
+    // application is done, if it can at all
+    if reached.get() && outcome.get().is_none() {
+        outcome.set(preempt(INT, int));
+    }
+
+    let outcome = Outcome {
+        reached: reached.get(),
+        int: outcome.get(),
+        preempted_inside: preempted_inside.get(),
+    };
+    (ret, outcome)
+}
+
+#[test]
+fn every_interleaving_accesses_the_data_once_at_a_time() {
+    let _serial = serial();
+
+    for enabled in [false, true] {
+        let mut interleavings = 0;
+        for at in 0.. {
+            let mut token = DATA::set_initial(0).ok().unwrap();
+            if enabled {
+                nvic().unmask(INT);
+            }
+
+            let (ret, outcome) = interleave(
+                at,
+                || {
+                    token.modify_app_context(|data| {
+                        inside(|| {
+                            let before = *data;
+                            preemption_point();
+                            *data = before + 10;
+                        })
+                    })
+                },
+                || {},
+                || DATA::modify_int_context(|data| inside(|| *data += 1)),
+            );
+            if !outcome.reached {
+                break;
+            }
+            assert_eq!(ret, Ok(()));
+
+            // The interrupt never ran while the data was held, and always
+            // got it when it did run
+            assert!(!outcome.preempted_inside);
+            if enabled {
+                assert_eq!(outcome.int, Some(Ok(())));
+                assert_eq!(token.modify_app_context(|data| *data), Ok(11));
+            } else {
+                assert_eq!(outcome.int, None);
+                assert_eq!(token.modify_app_context(|data| *data), Ok(10));
+            }
+
+            // The interrupt is left as enabled as it was
+            assert_eq!(can_run(), enabled);
+            interleavings += 1;
+            drop(token);
+            reset();
+        }
+
+        assert!(interleavings > 2);
+    }
+}
+
+#[test]
+fn an_interrupt_enabled_while_the_data_is_held_is_turned_away() {
+    let _serial = serial();
+
+    let mut reentrant = 0;
+    let mut preempted_inside = 0;
+    let mut interleavings = 0;
+    for at in 0.. {
+        let mut token = DATA::set_initial(0).ok().unwrap();
+        nvic().unmask(HIGH);
+
+        // The higher priority interrupt enables the interrupt, which is
+        // pended at the same time
+        let (ret, outcome) = interleave(
+            at,
+            || {
+                token.modify_app_context(|data| {
+                    inside(|| {
+                        let before = *data;
+                        preemption_point();
+                        *data = before + 10;
+                    })
+                })
+            },
+            || {
+                preempt(HIGH, || nvic().unmask(INT)).unwrap();
+            },
+            || DATA::modify_int_context(|data| inside(|| *data += 1)),
+        );
+        if !outcome.reached {
+            break;
+        }
+        assert_eq!(ret, Ok(()));
+
+        match outcome.int {
+            Some(Ok(())) => {
+                assert_eq!(token.modify_app_context(|data| *data), Ok(11));
+            }
+            Some(Err(err)) => {
+                assert_eq!(err, Error::Reentrant);
+                assert!(outcome.preempted_inside);
+                reentrant += 1;
+                assert_eq!(token.modify_app_context(|data| *data), Ok(10));
+            }
+            None => unreachable!("the interrupt was enabled, but never ran"),
+        }
+        preempted_inside += usize::from(outcome.preempted_inside);
+
+        // The enable made by the other interrupt is kept
+        assert!(can_run());
+        interleavings += 1;
+        drop(token);
+        reset();
+    }
+
+    // The race in `IrqSet::disable` is reached, and the flag turns the
+    // interrupt away
+    assert!(preempted_inside > 0 && preempted_inside < interleavings);
+    assert_eq!(reentrant, preempted_inside);
+}
+
+#[test]
+fn a_held_interrupt_sees_none_of_the_accesses_until_it_is_released() {
+    let _serial = serial();
+
+    let mut seen = [false; 3];
+    for at in 0.. {
+        let mut token = DATA::set_initial(0).ok().unwrap();
+        nvic().unmask(INT);
+
+        let (ret, outcome) = interleave(
+            at,
+            || {
+                token.modify_app_context_and_hold(|data| *data = 1)?;
+                preemption_point();
+                token.modify_app_context_and_hold(|data| *data = 2)?;
+                token.release();
+                Ok::<_, Error>(())
+            },
+            || {},
+            || DATA::modify_int_context(|data| SEEN.store(*data, Ordering::SeqCst)),
+        );
+        if !outcome.reached {
+            break;
+        }
+        assert_eq!(ret, Ok(()));
+        assert_eq!(outcome.int, Some(Ok(())));
+
+        // The interrupt ran before the first access, or after the release
+        let data = SEEN.load(Ordering::SeqCst);
+        assert_ne!(data, 1, "the interrupt ran while it was held");
+        seen[data as usize] = true;
+        drop(token);
+        reset();
+    }
+
+    assert_eq!(seen, [true, false, true]);
+}